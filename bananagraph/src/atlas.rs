@@ -0,0 +1,154 @@
+use cgmath::{Point2, Vector2};
+use crate::Sprite;
+
+/// Where an image added with `GpuWrapper::add_image` ended up: which layer (atlas page) it's on,
+/// and the rectangle of that page it occupies. This converts straight into a `Sprite` showing the
+/// whole image, so it can be passed to `DrawingContext::place` without knowing the coordinates:
+/// ```no_run
+/// # use bananagraph::{DrawingContext, GpuWrapper, Sprite};
+/// # fn f(wrapper: &mut GpuWrapper) {
+/// let toast = wrapper.add_image(&std::fs::read("toast.png").unwrap());
+/// let dc = DrawingContext::new((320.0, 240.0));
+/// wrapper.redraw([dc.place(toast, (10.0, 10.0))]);
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AtlasRegion {
+    pub layer: u32,
+    pub origin: Point2<u32>,
+    pub size: Vector2<u32>
+}
+
+impl From<AtlasRegion> for Sprite {
+    fn from(region: AtlasRegion) -> Self {
+        Sprite::new(region.origin, region.size).with_layer(region.layer)
+    }
+}
+
+impl AtlasRegion {
+    /// A sprite showing part of this image, given in coordinates relative to the image (for
+    /// images that are themselves spritesheets)
+    pub fn sprite(&self, origin: impl Into<Point2<u32>>, size: impl Into<Vector2<u32>>) -> Sprite {
+        let origin = origin.into();
+        Sprite::new((self.origin.x + origin.x, self.origin.y + origin.y), size).with_layer(self.layer)
+    }
+}
+
+/// A row of images on a page, all placed left to right. The row is as tall as the first image
+/// placed in it, and only takes images that fit in that height.
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32
+}
+
+#[derive(Default)]
+struct Page {
+    shelves: Vec<Shelf>,
+    next_y: u32
+}
+
+/// Packs rectangles into fixed-size pages, using a simple shelf algorithm. This only decides where
+/// things go; `GpuWrapper` owns the actual textures for the pages.
+pub(crate) struct AtlasPacker {
+    page_size: Vector2<u32>,
+    pages: Vec<Page>
+}
+
+/// Pixels of empty space left around each image so neighbors can't bleed into each other
+const PADDING: u32 = 1;
+
+impl AtlasPacker {
+    pub(crate) fn new(page_size: impl Into<Vector2<u32>>) -> Self {
+        Self { page_size: page_size.into(), pages: vec![] }
+    }
+
+    /// Find a spot for a rectangle of this size, returning the index of the page and the origin on
+    /// that page. This might start a new page, in which case the index will be one past the last
+    /// page. Returns None if the rectangle won't fit on even an empty page.
+    pub(crate) fn pack(&mut self, size: impl Into<Vector2<u32>>) -> Option<(usize, Point2<u32>)> {
+        let size = size.into();
+        let padded = Vector2::new(size.x + PADDING, size.y + PADDING);
+        if padded.x > self.page_size.x || padded.y > self.page_size.y {
+            return None
+        }
+
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            if let Some(origin) = Self::pack_page(page, self.page_size, padded) {
+                return Some((index, origin))
+            }
+        }
+
+        let mut page = Page::default();
+        let origin = Self::pack_page(&mut page, self.page_size, padded).unwrap();
+        self.pages.push(page);
+        Some((self.pages.len() - 1, origin))
+    }
+
+    /// Whether a rectangle of this size would go on a page that's already started, rather than
+    /// needing a new page (or a layer of its own)
+    pub(crate) fn fits(&self, size: impl Into<Vector2<u32>>) -> bool {
+        let size = size.into();
+        let padded = Vector2::new(size.x + PADDING, size.y + PADDING);
+        padded.x <= self.page_size.x && padded.y <= self.page_size.y &&
+            self.pages.iter().any(|page| Self::find_spot(page, self.page_size, padded).is_some())
+    }
+
+    fn pack_page(page: &mut Page, page_size: Vector2<u32>, size: Vector2<u32>) -> Option<Point2<u32>> {
+        match Self::find_spot(page, page_size, size)? {
+            (origin, Some(index)) => {
+                page.shelves[index].next_x += size.x;
+                Some(origin)
+            }
+            (origin, None) => {
+                page.shelves.push(Shelf { y: page.next_y, height: size.y, next_x: size.x });
+                page.next_y += size.y;
+                Some(origin)
+            }
+        }
+    }
+
+    /// Where a rectangle would go on a page, and which shelf it'd go on (None for a new one)
+    fn find_spot(page: &Page, page_size: Vector2<u32>, size: Vector2<u32>) -> Option<(Point2<u32>, Option<usize>)> {
+        // Try to fit it on an existing shelf first
+        for (index, shelf) in page.shelves.iter().enumerate() {
+            if size.y <= shelf.height && shelf.next_x + size.x <= page_size.x {
+                return Some((Point2::new(shelf.next_x, shelf.y), Some(index)))
+            }
+        }
+
+        // Otherwise start a new shelf under the last one, if there's room
+        if page.next_y + size.y <= page_size.y {
+            Some((Point2::new(0, page.next_y), None))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_shelves() {
+        let mut packer = AtlasPacker::new((64, 64));
+        assert_eq!(packer.pack((31, 15)), Some((0, (0, 0).into())));
+        assert_eq!(packer.pack((15, 7)), Some((0, (32, 0).into())));
+        assert_eq!(packer.pack((31, 15)), Some((0, (0, 16).into())));
+        assert_eq!(packer.pack((16, 16)), Some((0, (0, 32).into())));
+    }
+
+    #[test]
+    fn test_pack_pages() {
+        let mut packer = AtlasPacker::new((64, 64));
+        assert_eq!(packer.pack((40, 40)), Some((0, (0, 0).into())));
+        assert_eq!(packer.pack((40, 40)), Some((1, (0, 0).into())));
+        assert_eq!(packer.pack((20, 20)), Some((0, (41, 0).into())));
+        assert_eq!(packer.pack((64, 10)), None);
+
+        assert!(packer.fits((20, 20)));
+        assert!(!packer.fits((40, 40)));
+        assert!(!packer.fits((64, 10)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+use cgmath::{Matrix3, Point2, Rad, SquareMatrix, Vector2};
+use crate::{DrawingContext, GpuWrapper};
+
+/// A view onto a world that's bigger than the screen. The camera is centered on `position` (in
+/// world pixels), magnified by `zoom` and turned by `rotation`; its `context` is a
+/// `DrawingContext` that places sprites by their world position:
+/// ```no_run
+/// # use bananagraph::{Camera, GpuWrapper, Sprite};
+/// # use std::time::Duration;
+/// # fn f(wrapper: &GpuWrapper, player: (f32, f32), tree: Sprite, mouse_pos: cgmath::Point2<f64>, dt: Duration) {
+/// let mut camera = Camera::new((320.0, 240.0)).with_zoom(2.0).with_bounds((0.0, 0.0), (1024.0, 1024.0));
+/// camera.follow(player, 5.0, dt);
+///
+/// let dc = camera.context();
+/// wrapper.redraw([dc.place(tree, (100.0, 200.0))]);
+///
+/// // And where in the world is the mouse pointing?
+/// let clicked = camera.window_to_world(wrapper, mouse_pos);
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Camera {
+    /// The size of the screen the camera draws to, the same as you'd pass to `DrawingContext::new`
+    pub screen: Vector2<f32>,
+
+    /// The world point in the center of the screen
+    pub position: Vector2<f32>,
+
+    /// How many screen pixels a world pixel takes up
+    pub zoom: f32,
+
+    pub rotation: Rad<f32>,
+
+    /// If set, the (min, max) corners of a rectangle of the world that the camera won't show
+    /// anything outside of. Rotation is ignored for this.
+    pub bounds: Option<(Vector2<f32>, Vector2<f32>)>
+}
+
+impl Camera {
+    /// Create a camera looking at the world as though it were the screen: world (0, 0) in the
+    /// top left corner, unzoomed and unrotated
+    pub fn new(screen: impl Into<Vector2<f32>>) -> Self {
+        let screen = screen.into();
+        Self {
+            screen,
+            position: screen / 2.0,
+            zoom: 1.0,
+            rotation: Rad(0.0),
+            bounds: None
+        }
+    }
+
+    pub fn with_position(self, position: impl Into<Vector2<f32>>) -> Self {
+        let mut camera = Self { position: position.into(), ..self };
+        camera.clamp();
+        camera
+    }
+
+    pub fn with_zoom(self, zoom: f32) -> Self {
+        let mut camera = Self { zoom, ..self };
+        camera.clamp();
+        camera
+    }
+
+    pub fn with_rotation(self, rotation: impl Into<Rad<f32>>) -> Self {
+        Self { rotation: rotation.into(), ..self }
+    }
+
+    pub fn with_bounds(self, min: impl Into<Vector2<f32>>, max: impl Into<Vector2<f32>>) -> Self {
+        let mut camera = Self { bounds: Some((min.into(), max.into())), ..self };
+        camera.clamp();
+        camera
+    }
+
+    /// Center the camera on a world point (or as close as the bounds allow)
+    pub fn look_at(&mut self, target: impl Into<Vector2<f32>>) {
+        self.position = target.into();
+        self.clamp()
+    }
+
+    /// Move the camera part of the way toward a target, for smoothly following something that
+    /// moves. `rate` is how quickly it catches up: each second, the distance left shrinks by a
+    /// factor of e^rate, so, 5.0 or so is snappy and 1.0 is lazy. This doesn't depend on the frame
+    /// rate, so call it once per tick with the tick's `dt`.
+    pub fn follow(&mut self, target: impl Into<Vector2<f32>>, rate: f32, dt: Duration) {
+        let t = 1.0 - (-rate * dt.as_secs_f32()).exp();
+        self.position += (target.into() - self.position) * t;
+        self.clamp()
+    }
+
+    /// Keep the visible part of the world inside the bounds. If the bounds are smaller than the
+    /// view in some direction, center on them in that direction instead.
+    fn clamp(&mut self) {
+        let Some((min, max)) = self.bounds else { return };
+        let half_view = self.screen / (2.0 * self.zoom);
+
+        let clamp_axis = |pos: f32, min: f32, max: f32, half: f32| {
+            if max - min < half * 2.0 {
+                (min + max) / 2.0
+            } else {
+                pos.clamp(min + half, max - half)
+            }
+        };
+
+        self.position = Vector2::new(
+            clamp_axis(self.position.x, min.x, max.x, half_view.x),
+            clamp_axis(self.position.y, min.y, max.y, half_view.y)
+        );
+    }
+
+    /// The transform from world pixels to screen pixels
+    fn matrix(&self) -> Matrix3<f32> {
+        Matrix3::from_translation(self.screen / 2.0) *
+            Matrix3::from_angle_z(self.rotation) *
+            Matrix3::from_scale(self.zoom) *
+            Matrix3::from_translation(-self.position)
+    }
+
+    /// A drawing context that places sprites at world positions, as seen by this camera
+    pub fn context(&self) -> DrawingContext {
+        DrawingContext::new(self.screen)
+            .translate((-self.position.x / self.screen.x, -self.position.y / self.screen.y))
+            .scale((self.zoom, self.zoom))
+            .rotate(self.rotation)
+            .translate((0.5, 0.5))
+    }
+
+    /// Where on the screen (in the same units as `screen`) a world point is drawn
+    pub fn world_to_screen(&self, world: impl Into<Vector2<f32>>) -> Vector2<f32> {
+        (self.matrix() * world.into().extend(1.0)).truncate()
+    }
+
+    /// Which world point is drawn at a point on the screen (in the same units as `screen`)
+    pub fn screen_to_world(&self, screen: impl Into<Vector2<f32>>) -> Vector2<f32> {
+        // This can only fail to invert at zoom 0, where nothing is visible anyway
+        let inverse = self.matrix().invert().unwrap_or(Matrix3::identity());
+        (inverse * screen.into().extend(1.0)).truncate()
+    }
+
+    /// Which world point is drawn at a point in the window (like a mouse position, in physical
+    /// pixels). The window is scaled from the wrapper's logical size, which is in turn scaled from
+    /// the camera's `screen`.
+    pub fn window_to_world(&self, wrapper: &GpuWrapper, point: impl Into<Point2<f64>>) -> Vector2<f32> {
+        let logical = wrapper.window_to_logical(point);
+        let logical_size = wrapper.logical_size;
+        self.screen_to_world((
+            logical.x as f32 * self.screen.x / logical_size.x as f32,
+            logical.y as f32 * self.screen.y / logical_size.y as f32
+        ))
+    }
+
+    /// Where in the window (in physical pixels) a world point is drawn; the opposite of
+    /// `window_to_world`
+    pub fn world_to_window(&self, wrapper: &GpuWrapper, world: impl Into<Vector2<f32>>) -> Point2<f64> {
+        let screen = self.world_to_screen(world);
+        let logical_size = wrapper.logical_size;
+        wrapper.logical_to_window((
+            (screen.x * logical_size.x as f32 / self.screen.x) as f64,
+            (screen.y * logical_size.y as f32 / self.screen.y) as f64
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_relative_eq, Deg};
+    use crate::Sprite;
+    use super::*;
+
+    #[test]
+    fn test_context_matches_world_to_screen() {
+        let camera = Camera::new((320.0, 240.0))
+            .with_position((500.0, 100.0))
+            .with_zoom(2.0)
+            .with_rotation(Deg(30.0));
+
+        // The top left corner of a placed sprite is where world_to_screen says it is
+        let sprite = camera.context().place(Sprite::new((0, 0), (16, 16)), (510.0, 90.0));
+        let corner = (sprite.transform * cgmath::Vector3::new(0.0, 0.0, 1.0)).truncate();
+        let expected = camera.world_to_screen((510.0, 90.0));
+        assert_relative_eq!(corner.x * 320.0, expected.x, epsilon = 0.001);
+        assert_relative_eq!(corner.y * 240.0, expected.y, epsilon = 0.001);
+
+        assert_relative_eq!(camera.world_to_screen((500.0, 100.0)), Vector2::new(160.0, 120.0), epsilon = 0.001);
+        assert_relative_eq!(camera.screen_to_world(expected), Vector2::new(510.0, 90.0), epsilon = 0.001);
+    }
+
+    #[test]
+    fn test_follow_and_bounds() {
+        let mut camera = Camera::new((100.0, 100.0)).with_bounds((0.0, 0.0), (300.0, 80.0));
+
+        // The bounds are shorter than the view, so it's centered vertically
+        camera.look_at((0.0, 0.0));
+        assert_eq!(camera.position, Vector2::new(50.0, 40.0));
+
+        camera.follow((150.0, 40.0), 1.0, Duration::from_secs_f32(std::f32::consts::LN_2));
+        assert_relative_eq!(camera.position, Vector2::new(100.0, 40.0), epsilon = 0.001);
+
+        camera.follow((1000.0, 40.0), 100.0, Duration::from_secs(1));
+        assert_eq!(camera.position, Vector2::new(250.0, 40.0));
+    }
+
+    #[test]
+    fn test_window_to_world() {
+        // A 16x16 logical screen drawn in a 40x32 window is scaled 2x and centered horizontally
+        let wrapper = pollster::block_on(GpuWrapper::headless((40, 32).into(), (16, 16).into()));
+        let camera = Camera::new((16.0, 16.0)).with_position((100.0, 100.0));
+        assert_relative_eq!(camera.window_to_world(&wrapper, (20.0, 16.0)), Vector2::new(100.0, 100.0), epsilon = 0.001);
+        assert_relative_eq!(camera.window_to_world(&wrapper, (4.0, 0.0)), Vector2::new(92.0, 92.0), epsilon = 0.001);
+        assert_relative_eq!(camera.world_to_window(&wrapper, (92.0, 92.0)), Point2::new(4.0, 0.0), epsilon = 0.001);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageFormat, RgbaImage};
+use wgpu::{Buffer, Device, TextureFormat};
+
+/// The last few frames drawn, for turning into a GIF or APNG (for a bug report, say). Start one
+/// with `GpuWrapper::start_recording`; once it's full, every new frame pushes the oldest one out.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    frames: VecDeque<RgbaImage>,
+    capacity: usize
+}
+
+impl Recording {
+    pub fn new(capacity: usize) -> Self {
+        Self { frames: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+    }
+
+    /// Add a frame, dropping the oldest one if we're full
+    pub fn push(&mut self, frame: RgbaImage) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame)
+    }
+
+    /// The frames, oldest first
+    pub fn frames(&self) -> impl Iterator<Item=&RgbaImage> {
+        self.frames.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Encode the frames as a looping GIF, each shown for `frame_delay`. GIFs only have 256
+    /// colors a frame, and count time in hundredths of a second, so this is lossy on both counts;
+    /// see `encode_apng`.
+    pub fn encode_gif(&self, frame_delay: Duration) -> crate::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            let delay = Delay::from_saturating_duration(frame_delay);
+            encoder.encode_frames(self.frames.iter().map(|frame| Frame::from_parts(frame.clone(), 0, 0, delay)))?;
+        }
+        Ok(bytes)
+    }
+
+    /// Encode the frames as a looping animated PNG, each shown for `frame_delay` (to the
+    /// millisecond). Unlike a GIF this keeps every color exactly.
+    pub fn encode_apng(&self, frame_delay: Duration) -> crate::Result<Vec<u8>> {
+        let Some(first) = self.frames.front() else { return Ok(vec![]) };
+        let mut bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, first.width(), first.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(self.frames.len() as u32, 0).map_err(png_error)?;
+            encoder.set_frame_delay(frame_delay.as_millis().min(u16::MAX as u128) as u16, 1000).map_err(png_error)?;
+
+            let mut writer = encoder.write_header().map_err(png_error)?;
+            for frame in &self.frames {
+                // Every frame has to be the size of the first (the window might have resized)
+                let frame = if frame.dimensions() == first.dimensions() {
+                    frame.clone()
+                } else {
+                    image::imageops::resize(frame, first.width(), first.height(), image::imageops::FilterType::Nearest)
+                };
+                writer.write_image_data(&frame).map_err(png_error)?;
+            }
+            writer.finish().map_err(png_error)?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// Encode an image (like a screenshot, see `GpuWrapper::take_screenshot`) as a PNG
+pub fn encode_png(image: &RgbaImage) -> crate::Result<Vec<u8>> {
+    let mut bytes = Cursor::new(vec![]);
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+fn png_error(err: png::EncodingError) -> crate::Error {
+    crate::Error::Image(image::ImageError::Encoding(image::error::EncodingError::new(ImageFormat::Png.into(), err)))
+}
+
+/// Turn pixels read back from a texture, in rows padded out to `row_bytes`, into an image. This
+/// only knows 8-bit RGBA and BGRA textures, which is what we draw in (see `can_capture`).
+pub(crate) fn image_from_rows(bytes: &[u8], row_bytes: usize, width: u32, height: u32, format: TextureFormat) -> Option<RgbaImage> {
+    if !can_capture(format) {
+        return None
+    }
+    let mut pixels: Vec<u8> = bytes.chunks(row_bytes).take(height as usize).flat_map(|row| &row[0..width as usize * 4]).copied().collect();
+    if matches!(format, TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb) {
+        pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2))
+    }
+    RgbaImage::from_raw(width, height, pixels)
+}
+
+/// Whether we can turn a frame drawn in this format into an image
+pub(crate) fn can_capture(format: TextureFormat) -> bool {
+    matches!(format, TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb | TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb)
+}
+
+/// What we've been asked to keep of the frames being drawn: the next one, as a screenshot, and / or
+/// all of them, in a recording
+#[derive(Default)]
+pub(crate) struct Capture {
+    pub screenshot_requested: bool,
+    pub screenshot: Option<RgbaImage>,
+    pub recording: Option<Recording>,
+
+    /// What frames are copied into to be read back, kept between frames while it's the right size
+    buffer: Option<Arc<Buffer>>
+}
+
+impl Capture {
+    /// Whether the next frame needs reading back
+    pub fn wants_frame(&self) -> bool {
+        self.screenshot_requested || self.recording.is_some()
+    }
+
+    /// A buffer `size` bytes long to copy the frame into
+    pub fn buffer(&mut self, device: &Device, size: u64) -> Arc<Buffer> {
+        if self.buffer.as_ref().is_none_or(|buffer| buffer.size() != size) {
+            self.buffer = Some(Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("capture buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })));
+        }
+        self.buffer.clone().unwrap()
+    }
+
+    /// Hand a frame that's been read back to whoever wanted it
+    pub fn push(&mut self, frame: RgbaImage) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(frame.clone())
+        }
+        if std::mem::take(&mut self.screenshot_requested) {
+            self.screenshot = Some(frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::AnimationDecoder;
+    use crate::{GpuWrapper, Sprite};
+
+    #[test]
+    fn test_screenshot() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        let sprite = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5));
+
+        // Nothing until it's asked for, and then only the next frame
+        wrapper.redraw([sprite]);
+        assert!(wrapper.take_screenshot().is_none());
+        wrapper.request_screenshot().unwrap();
+        wrapper.redraw([sprite]);
+        let screenshot = wrapper.take_screenshot().unwrap();
+        assert_eq!(screenshot.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(screenshot.get_pixel(6, 6).0, [0, 0, 0, 0xff]);
+        assert!(wrapper.take_screenshot().is_none());
+
+        let png = encode_png(&screenshot).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), screenshot);
+    }
+
+    #[test]
+    fn test_recording() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff; 4].repeat(4 * 4), 4, None);
+        let tints = [(1.0, 0.0, 0.0, 1.0), (0.0, 1.0, 0.0, 1.0), (0.0, 0.0, 1.0, 1.0)];
+
+        // Only the last two frames are kept
+        wrapper.start_recording(2).unwrap();
+        for tint in tints {
+            wrapper.redraw([Sprite::new((0, 0), (4, 4)).with_tint(tint)]);
+        }
+        let recording = wrapper.stop_recording().unwrap();
+        let firsts: Vec<_> = recording.frames().map(|frame| frame.get_pixel(0, 0).0).collect();
+        assert_eq!(firsts, vec![[0, 0xff, 0, 0xff], [0, 0, 0xff, 0xff]]);
+        assert!(wrapper.recording().is_none());
+
+        let gif = recording.encode_gif(Duration::from_millis(100)).unwrap();
+        let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(gif)).unwrap();
+        assert_eq!(decoder.into_frames().count(), 2);
+
+        let apng = recording.encode_apng(Duration::from_millis(100)).unwrap();
+        let reader = png::Decoder::new(apng.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 2);
+    }
+}
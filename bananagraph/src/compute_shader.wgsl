@@ -1,5 +1,5 @@
 @group(0) @binding(0)
-var out_texture: texture_storage_2d<bgra8unorm, write>;
+var out_texture: texture_storage_2d<rgba8unorm, write>;
 
 // This is actually 128k bytes but we have to access it a word at a time
 @group(0) @binding(1)
@@ -17,8 +17,14 @@ struct DisplayRegisters {
 }
 
 @compute
-@workgroup_size(1)
+@workgroup_size(8, 8)
 fn pixel_shader(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // We run in 8x8 tiles, which might hang off the edge of the texture
+    let size = textureDimensions(out_texture);
+    if global_id.x >= size.x || global_id.y >= size.y {
+        return;
+    }
+
     let mode = peek8(16u);
     let reg = read_display_registers();
     var color: vec4<f32>;
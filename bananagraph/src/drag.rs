@@ -0,0 +1,125 @@
+use crate::{MouseButton, MousePos, SpriteId};
+use crate::event_handler::Modifiers;
+
+/// How far (in physical pixels) the mouse has to move with a button held before it counts as a
+/// drag rather than a slightly wobbly click
+const DRAG_THRESHOLD: f64 = 4.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DragPhase {
+    /// The mouse has moved far enough with a button down to be dragging. This is sent once per
+    /// drag, before any updates.
+    Start,
+
+    /// The mouse moved during a drag
+    Update,
+
+    /// The button was released, ending the drag
+    End
+}
+
+/// A click-and-drag gesture, sent to `WindowEventHandler::drag`. A drag also still sends `click`
+/// events for the button going down and coming up, so handlers that don't care about dragging
+/// don't need to change.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Drag {
+    pub button: MouseButton,
+    pub phase: DragPhase,
+
+    /// Where the button was pressed
+    pub start: MousePos,
+
+    /// Where the mouse is now
+    pub mouse_pos: MousePos,
+
+    /// The sprite under the mouse where the button was pressed (not wherever it is now; for
+    /// that, look at `mouse_move`)
+    pub entity: Option<SpriteId>,
+    pub modifiers: Modifiers
+}
+
+/// Turns a stream of button and motion events into `Drag`s. The window and browser event loops
+/// use this to call `WindowEventHandler::drag`, but it's public in case you're running your own.
+#[derive(Default)]
+pub struct DragTracker {
+    /// The press that might turn into a drag, and whether it has yet
+    pressed: Option<(MouseButton, MousePos, Option<SpriteId>)>,
+    dragging: bool
+}
+
+impl DragTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A button went down. Only one button drags at a time; pressing others mid-drag is ignored.
+    pub fn press(&mut self, button: MouseButton, mouse_pos: MousePos, entity: Option<SpriteId>) {
+        if self.pressed.is_none() {
+            self.pressed = Some((button, mouse_pos, entity))
+        }
+    }
+
+    /// The mouse moved; returns a drag event if this starts or continues a drag
+    pub fn motion(&mut self, mouse_pos: MousePos, modifiers: Modifiers) -> Option<Drag> {
+        let (button, start, entity) = self.pressed?;
+
+        if !self.dragging {
+            let (dx, dy) = (mouse_pos.physical.x - start.physical.x, mouse_pos.physical.y - start.physical.y);
+            if dx.hypot(dy) < DRAG_THRESHOLD {
+                return None
+            }
+            self.dragging = true;
+            return Some(Drag { button, phase: DragPhase::Start, start, mouse_pos, entity, modifiers })
+        }
+
+        Some(Drag { button, phase: DragPhase::Update, start, mouse_pos, entity, modifiers })
+    }
+
+    /// A button came up; returns a drag event if this ends a drag
+    pub fn release(&mut self, button: MouseButton, mouse_pos: MousePos, modifiers: Modifiers) -> Option<Drag> {
+        let (pressed, start, entity) = self.pressed?;
+        if pressed != button {
+            return None
+        }
+
+        self.pressed = None;
+        let dragging = std::mem::take(&mut self.dragging);
+        dragging.then_some(Drag { button, phase: DragPhase::End, start, mouse_pos, entity, modifiers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: f64, y: f64) -> MousePos {
+        MousePos { physical: (x, y).into(), logical: (x / 2.0, y / 2.0).into() }
+    }
+
+    #[test]
+    fn test_drag() {
+        let mods = Modifiers::default();
+        let mut tracker = DragTracker::new();
+        assert_eq!(tracker.motion(pos(0.0, 0.0), mods), None);
+
+        // Wobbling a little isn't a drag
+        tracker.press(MouseButton::Left, pos(10.0, 10.0), Some(5));
+        assert_eq!(tracker.motion(pos(12.0, 11.0), mods), None);
+
+        let start = tracker.motion(pos(20.0, 10.0), mods).unwrap();
+        assert_eq!((start.phase, start.entity, start.start), (DragPhase::Start, Some(5), pos(10.0, 10.0)));
+        assert_eq!(tracker.motion(pos(30.0, 10.0), mods).unwrap().phase, DragPhase::Update);
+
+        // Other buttons don't interfere
+        tracker.press(MouseButton::Right, pos(30.0, 10.0), None);
+        assert_eq!(tracker.release(MouseButton::Right, pos(30.0, 10.0), mods), None);
+
+        let end = tracker.release(MouseButton::Left, pos(40.0, 10.0), mods).unwrap();
+        assert_eq!((end.phase, end.mouse_pos), (DragPhase::End, pos(40.0, 10.0)));
+        assert_eq!(tracker.motion(pos(50.0, 10.0), mods), None);
+
+        // A click without moving is just a click
+        tracker.press(MouseButton::Left, pos(10.0, 10.0), None);
+        assert_eq!(tracker.release(MouseButton::Left, pos(10.0, 10.0), mods), None);
+    }
+}
@@ -56,6 +56,27 @@ impl DrawingContext {
         sprite.with_transform(self.transform * t)
     }
 
+    /// The transform this context puts on top of what it places, to move a whole `SpriteBatch`
+    /// with (see `SpriteBatch::set_transform`)
+    pub fn transform(&self) -> Matrix3<f32> {
+        self.transform
+    }
+
+    /// Return a sprite that's already been placed (by this or any other context) with this
+    /// context's transform applied on top of it
+    pub fn apply(&self, sprite: Sprite) -> Sprite {
+        sprite.with_transform(self.transform * sprite.transform)
+    }
+
+    /// Return this drawing context nested inside another one, so that anything placed with it is
+    /// transformed by this context first and then by `parent`
+    pub fn within(self, parent: &DrawingContext) -> Self {
+        Self {
+            transform: parent.transform * self.transform,
+            ..self
+        }
+    }
+
     /// Return a drawing context with the transform matrix scaled by these factors
     pub fn scale(self, factor: impl Into<Vector2<f32>>) -> Self {
         let factor = factor.into();
@@ -87,3 +108,25 @@ impl DrawingContext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_scaled_rotated() {
+        let mut snapshot = crate::Snapshot::new((48, 48));
+        let mut texture = [0xff, 0xff, 0xff, 0xff].repeat(8 * 4);
+        texture.extend([0xff, 0, 0, 0xff].repeat(8 * 4));
+        snapshot.add_texture_from_array(texture, 8, None);
+
+        let dc = DrawingContext::new((48.0, 48.0));
+        let sprite = Sprite::new((0, 0), (8, 8));
+        snapshot.assert_matches(concat!(env!("CARGO_MANIFEST_DIR"), "/src/snapshots/place_scaled_rotated.png"), [
+            dc.place(sprite, (2.0, 2.0)),
+            dc.place_rotated(sprite, (20.0, 2.0), Deg(90.0)),
+            dc.place_scaled(sprite, (2.0, 20.0), (2.0, 1.0)),
+            dc.place_scaled_rotated(sprite, (28.0, 28.0), (2.0, 2.0), Deg(180.0)),
+        ], 0);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt::{Display, Formatter};
+
+/// Anything that can go wrong in bananagraph that isn't a bug in bananagraph: images that don't
+/// decode, GPUs that aren't there, surfaces that go away.
+#[derive(Debug)]
+pub enum Error {
+    /// Couldn't decode an image (or encode one, for a screenshot or recording)
+    Image(image::ImageError),
+
+    /// Raw RGBA data that isn't a whole number of rows of the given width: (byte length, width)
+    ImageSize(usize, u32),
+
+    /// Couldn't create a surface for the window or canvas
+    CreateSurface(wgpu::CreateSurfaceError),
+
+    /// There's no GPU adapter (not even a software one) that can draw to the surface
+    NoAdapter,
+
+    /// The adapter wouldn't give us a device, probably because it doesn't support the limits we need
+    RequestDevice(wgpu::RequestDeviceError),
+
+    /// The surface doesn't support any texture formats
+    NoSurfaceFormat,
+
+    /// Couldn't get a texture from the surface to draw the frame in, even after reconfiguring it
+    Surface(wgpu::SurfaceError),
+
+    /// Couldn't read something (like the id buffer) back from the GPU
+    Readback(wgpu::BufferAsyncError),
+
+    /// The device can't run compute shaders (like on WebGL), which a `RetroDisplay` needs
+    NoComputeShaders,
+
+    /// Frames can't be read back from this surface, for screenshots or recordings
+    NoCapture,
+
+    /// An image is too big for a spritesheet layer on this device: (width, height, the most it allows)
+    TextureTooBig(u32, u32, u32),
+
+    /// There are already as many spritesheet layers as this device allows
+    TooManyLayers(u32),
+
+    /// The window's event loop failed
+    #[cfg(feature = "desktop")]
+    EventLoop(winit::error::EventLoopError)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Image(err) => write!(f, "Couldn't decode or encode image: {}", err),
+            Error::ImageSize(len, width) => write!(f, "{} bytes isn't a whole number of rows of {} RGBA pixels", len, width),
+            Error::CreateSurface(err) => write!(f, "Couldn't create surface: {}", err),
+            Error::NoAdapter => write!(f, "Couldn't find a GPU adapter"),
+            Error::RequestDevice(err) => write!(f, "Couldn't create device: {}", err),
+            Error::NoSurfaceFormat => write!(f, "Surface doesn't support any formats"),
+            Error::Surface(err) => write!(f, "Couldn't get a frame from the surface: {}", err),
+            Error::Readback(err) => write!(f, "Couldn't read back from the GPU: {}", err),
+            Error::NoComputeShaders => write!(f, "This device doesn't support compute shaders"),
+            Error::NoCapture => write!(f, "Frames can't be read back from this surface"),
+            Error::TextureTooBig(width, height, max) => write!(f, "A {}x{} texture is bigger than the {}x{} this device allows", width, height, max, max),
+            Error::TooManyLayers(max) => write!(f, "This device only allows {} spritesheet layers", max),
+            #[cfg(feature = "desktop")]
+            Error::EventLoop(err) => write!(f, "Event loop failed: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Image(err) => Some(err),
+            Error::CreateSurface(err) => Some(err),
+            Error::RequestDevice(err) => Some(err),
+            Error::Surface(err) => Some(err),
+            Error::Readback(err) => Some(err),
+            #[cfg(feature = "desktop")]
+            Error::EventLoop(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
+
+impl From<wgpu::CreateSurfaceError> for Error {
+    fn from(err: wgpu::CreateSurfaceError) -> Self {
+        Error::CreateSurface(err)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        Error::RequestDevice(err)
+    }
+}
+
+impl From<wgpu::SurfaceError> for Error {
+    fn from(err: wgpu::SurfaceError) -> Self {
+        Error::Surface(err)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for Error {
+    fn from(err: wgpu::BufferAsyncError) -> Self {
+        Error::Readback(err)
+    }
+}
+
+#[cfg(feature = "desktop")]
+impl From<winit::error::EventLoopError> for Error {
+    fn from(err: winit::error::EventLoopError) -> Self {
+        Error::EventLoop(err)
+    }
+}
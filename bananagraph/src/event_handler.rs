@@ -1,46 +1,236 @@
 use std::time::Duration;
-use cgmath::Point2;
-use crate::{GpuWrapper, IdBuffer, SpriteId};
+use cgmath::{Point2, Vector2};
+use crate::{Drag, GameLoop, Gesture, GpuWrapper, IdBuffer, SpriteId, Touch, WindowHandle};
 
-#[derive(Copy,Clone,Debug,PartialEq)]
-pub enum MouseButton { Left, Right }
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+
+    /// The thumb buttons on the side of some mice, usually for back and forward in a browser
+    Back,
+    Forward,
+
+    /// Any other button, by number
+    Other(u16)
+}
 
 #[derive(Copy,Clone,Debug,PartialEq)]
 pub enum ElementState { Pressed, Released }
 
+/// Where the mouse pointer is, both in physical window pixels (which is what `IdBuffer` is
+/// indexed by) and in logical pixels (the coordinates the game draws in, with the window's
+/// `ScalingMode` undone). Off the edges of the logical screen, like in the letterbox bars, the
+/// logical position will be negative or past `logical_size`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MousePos {
+    pub physical: Point2<f64>,
+    pub logical: Point2<f64>
+}
+
+impl MousePos {
+    /// Figure out the logical position of a point in the window
+    pub fn new(physical: impl Into<Point2<f64>>, wrapper: &GpuWrapper) -> Self {
+        let physical = physical.into();
+        Self { physical, logical: wrapper.window_to_logical(physical) }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Click {
     pub button: MouseButton,
     pub state: ElementState,
-    pub mouse_pos: Point2<f64>,
-    pub entity: Option<SpriteId>
+    pub mouse_pos: MousePos,
+    pub entity: Option<SpriteId>,
+
+    /// Which modifier keys were held down, for shift-clicking and such
+    pub modifiers: Modifiers
+}
+
+/// The mouse moved, and what it's now over
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MouseMove {
+    pub mouse_pos: MousePos,
+    pub entity: Option<SpriteId>,
+    pub modifiers: Modifiers
 }
 
-#[derive(Copy, Clone, PartialEq)]
+/// How far the mouse wheel (or trackpad) scrolled. Positive y is scrolling down, toward the
+/// bottom of a page, and positive x is scrolling right.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScrollDelta {
+    /// A wheel that moves in steps: this is how many lines (notches) it moved
+    Lines(Vector2<f64>),
+
+    /// A trackpad or other smooth scroll, in physical pixels
+    Pixels(Vector2<f64>)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Scroll {
+    pub delta: ScrollDelta,
+
+    /// Where the mouse is, and what it's over, for things like zooming in on the mouse
+    pub mouse_pos: MousePos,
+    pub entity: Option<SpriteId>,
+    pub modifiers: Modifiers
+}
+
+/// The window (or canvas) changed size. The sizes are in logical pixels: physical pixels divided
+/// by the scale factor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Resize {
+    pub old_size: Vector2<u32>,
+    pub new_size: Vector2<u32>,
+    pub physical_size: Vector2<u32>
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Dir { North, South, East, West }
 
-#[derive(Clone, PartialEq)]
-pub enum KeyEvent {
-    Letter(char),
+/// A key, by what it means rather than where it is: with the current keyboard layout and shift
+/// state taken into account. These are parsed from the W3C names for keys, which is what both
+/// browsers and winit use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A key that types something, with what it types (so shift-2 on a US keyboard is '@', and
+    /// the space bar is ' ')
+    Character(char),
     Enter,
-    Esc,
-    Arrow(Dir)
+    Escape,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Arrow(Dir),
+    Home,
+    End,
+    PageUp,
+    PageDown,
+
+    /// A function key, F1 and up
+    F(u8),
+    Shift,
+    Control,
+    Alt,
+
+    /// The Windows / Command key
+    Meta,
+    CapsLock,
+
+    /// Anything we don't have a name for (media keys, dead keys, and so on)
+    Other
+}
+
+impl Key {
+    /// Parse a W3C key name, as in a browser's `KeyboardEvent.key`, like "a", "Enter" or "F5"
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "Enter" => Key::Enter,
+            "Escape" => Key::Escape,
+            "Tab" => Key::Tab,
+            "Backspace" => Key::Backspace,
+            "Delete" => Key::Delete,
+            "Insert" => Key::Insert,
+            "ArrowUp" => Key::Arrow(Dir::North),
+            "ArrowDown" => Key::Arrow(Dir::South),
+            "ArrowRight" => Key::Arrow(Dir::East),
+            "ArrowLeft" => Key::Arrow(Dir::West),
+            "Home" => Key::Home,
+            "End" => Key::End,
+            "PageUp" => Key::PageUp,
+            "PageDown" => Key::PageDown,
+            "Shift" => Key::Shift,
+            "Control" => Key::Control,
+            "Alt" => Key::Alt,
+            "Meta" | "Super" => Key::Meta,
+            "CapsLock" => Key::CapsLock,
+            "Space" => Key::Character(' '),
+            _ => {
+                // Function keys are F plus a number; anything else one character long is something
+                // that types. This is chars, which are unicode scalar values, which isn't perfect
+                // but is better than bytes.
+                let mut chars = name.chars();
+                match (chars.next(), chars.as_str()) {
+                    (Some('F'), number) if !number.is_empty() => number.parse().map_or(Key::Other, Key::F),
+                    (Some(c), "") => Key::Character(c),
+                    _ => Key::Other
+                }
+            }
+        }
+    }
+}
+
+/// Whether a key event is the key going down, coming up, or the key being held long enough to
+/// repeat
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KeyState { Pressed, Repeat, Released }
+
+/// Which modifier keys are being held down
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+
+    /// The Windows / Command key
+    pub meta: bool
+}
+
+impl Modifiers {
+    /// Whether any of the modifiers that turn a key into a shortcut (rather than typing something)
+    /// are held: everything but shift
+    pub fn shortcut(&self) -> bool {
+        self.ctrl || self.alt || self.meta
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyEvent {
+    /// What the key means, see `Key`
+    pub logical: Key,
+
+    /// Where the key is on the keyboard, regardless of layout, as a W3C key code name like "KeyW"
+    /// or "Numpad4" (the same as a browser's `KeyboardEvent.code`). This is what to use for
+    /// things like WASD movement, that should stay in the same place on an AZERTY keyboard.
+    pub physical: String,
+    pub state: KeyState,
+    pub modifiers: Modifiers
 }
 
 /// A trait for handling game-level events. Bananagraph can keep track of the winit event loop
 /// and translate its events into something more game-level semantic. These all have default
-/// implementations so you only need to override the ones you care about, but without `redraw` and
-/// `init` at minimum, you can't do very much.
+/// implementations so you only need to override the ones you care about, but without `redraw` (or
+/// `render`) and `init` at minimum, you can't do very much.
 pub trait WindowEventHandler {
+    /// Called once at startup, with a handle for changing the window while the game runs
+    /// (fullscreen, title, cursor, and closing it). Keep it if you want it. See `WindowHandle`. In
+    /// a window this is right before `init`; in a browser, when the `JsGpuWrapper` is created.
+    fn window_handle(&mut self, _handle: WindowHandle) {}
+
     /// Run once at the creation of the window; put any one-time init code here, like
     fn init(&mut self, _wrapper: &mut GpuWrapper) {}
 
-    /// Run periodically to redraw the window. If this returns Some, then the given `IdBuffer` is used to
-    /// handle future click events.
-    fn redraw(&self, mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer>;
+    /// How often to call `tick` and `render`. This is asked once, when the window (or browser
+    /// wrapper) is created. The default is 60 fps for both.
+    fn game_loop(&self) -> GameLoop { GameLoop::default() }
 
-    /// Called at about 60 fps, with the actual duration between calls passed
-    /// as a parameter.
+    /// Run every frame to redraw the window, unless `render` is overridden. If this returns Some,
+    /// then the given `IdBuffer` is used to handle future click events.
+    fn redraw(&self, _mouse_pos: MousePos, _wrapper: &GpuWrapper) -> Option<IdBuffer> { None }
+
+    /// Run every frame to redraw the window. `alpha` is how far (0 to 1) it is from the last tick
+    /// to the next one, so things can be drawn part of the way between where they were and where
+    /// they're going, when drawing more often than ticking. The default implementation ignores
+    /// that and calls `redraw`.
+    fn render(&self, mouse_pos: MousePos, wrapper: &GpuWrapper, _alpha: f32) -> Option<IdBuffer> {
+        self.redraw(mouse_pos, wrapper)
+    }
+
+    /// Called at a fixed rate (60 fps unless `game_loop` says otherwise), with the length of a
+    /// tick passed as a parameter. If frames run long this is called several times in a row to
+    /// catch up, so it always sees the same `dt`.
     fn tick(&mut self, _dt: Duration) {}
 
     /// Called when the user tries to close the window. The default implementation
@@ -52,21 +242,85 @@ pub trait WindowEventHandler {
     /// Otherwise, we'll redraw.
     fn running(&self) -> bool { true }
 
+    /// Called when the window is resized. `GpuWrapper::logical_size` has already been updated
+    /// by then, if it's following the window's size.
+    fn resize(&mut self, _event: Resize) {}
+
+    /// Called when the window's scale factor changes, like from being dragged to a monitor with a
+    /// different DPI. A `resize` always comes after this, since the logical size changes too.
+    fn scale_factor_changed(&mut self, _scale_factor: f64) {}
+
+    /// Called when the window gains (true) or loses (false) keyboard focus, like to pause the
+    /// game when the player switches to something else
+    fn focus(&mut self, _focused: bool) {}
+
+    /// Called when the game is hidden: the window's minimized or covered up, the app's sent to the
+    /// background on a phone, or the browser tab is hidden. Nothing ticks or draws until `resume`,
+    /// and the time in between is skipped rather than caught up on.
+    fn suspend(&mut self) {}
+
+    /// Called when the game is visible again after `suspend`
+    fn resume(&mut self) {}
+
     /// Called when the user clicks the mouse somewhere in the window
     fn click(&mut self, _event: Click) {}
 
-    /// Called on every key event in the window. The default implementation parses
-    /// pressed events for the arrow keys, printable characters including space, and
-    /// the enter and esc keys. If you override this, you can get access to the raw
-    /// (from winit, anyway) key events and handle more. But, if you override this,
-    /// you'll need to handle calling arrow_key, enter_key, etc yourself if you want
+    /// Called whenever the mouse moves over the window, for hover effects and such
+    fn mouse_move(&mut self, _event: MouseMove) {}
+
+    /// Called when the mouse moves into the window
+    fn mouse_enter(&mut self) {}
+
+    /// Called when the mouse leaves the window
+    fn mouse_leave(&mut self) {}
+
+    /// Called when the mouse wheel turns (or a trackpad scrolls) over the window
+    fn scroll(&mut self, _event: Scroll) {}
+
+    /// Called as the user clicks and drags: once when the drag starts, every time the mouse moves
+    /// during it, and once when the button is released. See `Drag`.
+    fn drag(&mut self, _event: Drag) {}
+
+    /// Called for every finger touching, moving on, or lifting off the screen. Each finger has its
+    /// own id, so you can follow several at once. See `Touch`.
+    fn touch(&mut self, _event: Touch) {}
+
+    /// Whether to run touches through a `GestureRecognizer` and call `gesture` with what it
+    /// finds. Off by default; turn it on to make a mouse-and-keyboard game playable on a phone.
+    fn recognize_gestures(&self) -> bool { false }
+
+    /// Called with each recognized touch gesture, if `recognize_gestures` is on. The default
+    /// implementation pretends they're mouse and keyboard input: a tap is a left click (a press
+    /// and a release), a long press is a right click, and a swipe is an arrow key. Pinches are
+    /// ignored. Override this to do something else with them.
+    fn gesture(&mut self, event: Gesture) {
+        let (button, mouse_pos, entity) = match event {
+            Gesture::Tap { position, entity } => (MouseButton::Left, position, entity),
+            Gesture::LongPress { position, entity } => (MouseButton::Right, position, entity),
+            Gesture::Swipe { dir, .. } => return self.arrow_key(dir),
+            Gesture::Pinch { .. } => return
+        };
+
+        for state in [ElementState::Pressed, ElementState::Released] {
+            self.click(Click { button, state, mouse_pos, entity, modifiers: Modifiers::default() })
+        }
+    }
+
+    /// Called on every key event in the window, including releases and repeats. The default
+    /// implementation looks at presses (and repeats) of the arrow keys, printable characters
+    /// including space (as long as ctrl, alt or meta aren't held), and the enter and esc keys.
+    /// If you override this, you can handle the rest: other keys, releases, modifiers. But, if you
+    /// override this, you'll need to handle calling arrow_key, enter_key, etc yourself if you want
     /// to use those as well.
     fn key(&mut self, event: KeyEvent) {
-        match event {
-            KeyEvent::Letter(c) => self.letter_key(c),
-            KeyEvent::Enter => self.enter_key(),
-            KeyEvent::Esc => self.esc_key(),
-            KeyEvent::Arrow(dir) => self.arrow_key(dir)
+        if event.state == KeyState::Released { return }
+
+        match event.logical {
+            Key::Character(c) if !event.modifiers.shortcut() => self.letter_key(c),
+            Key::Enter => self.enter_key(),
+            Key::Escape => self.esc_key(),
+            Key::Arrow(dir) => self.arrow_key(dir),
+            _ => {}
         }
     }
 
@@ -83,3 +337,22 @@ pub trait WindowEventHandler {
     /// can include shift chars like @, unicode characters from non-US keyboards, etc.
     fn letter_key(&mut self, _c: char) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_from_name() {
+        assert_eq!(Key::from_name("a"), Key::Character('a'));
+        assert_eq!(Key::from_name("F"), Key::Character('F'));
+        assert_eq!(Key::from_name(" "), Key::Character(' '));
+        assert_eq!(Key::from_name("Space"), Key::Character(' '));
+        assert_eq!(Key::from_name("F12"), Key::F(12));
+        assert_eq!(Key::from_name("ArrowLeft"), Key::Arrow(Dir::West));
+        assert_eq!(Key::from_name("PageDown"), Key::PageDown);
+        assert_eq!(Key::from_name("Super"), Key::Meta);
+        assert_eq!(Key::from_name("Fn"), Key::Other);
+        assert_eq!(Key::from_name("Dead"), Key::Other);
+    }
+}
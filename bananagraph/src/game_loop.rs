@@ -0,0 +1,126 @@
+use std::time::Duration;
+use crate::WindowEventHandler;
+
+/// How often the game simulates and draws. The simulation runs in fixed-length ticks, however
+/// long frames actually take: every frame, the real time that's passed goes into an accumulator,
+/// and as many whole ticks as fit are taken out of it and sent to `WindowEventHandler::tick`. Then
+/// the frame is drawn with `WindowEventHandler::render`, which gets how far (0 to 1) we are between
+/// the last tick and the next, to smooth out motion when drawing faster than ticking.
+///
+/// The window and browser event loops both drive this the same way; the only difference is that
+/// the browser decides the frame rate itself (with requestAnimationFrame) and ignores `frame_length`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GameLoop {
+    /// How much game time passes each tick, which is the `dt` every `tick` gets
+    pub tick_length: Duration,
+
+    /// How long to wait between drawing frames, in the desktop window
+    pub frame_length: Duration,
+
+    /// The most ticks we'll run in one frame. If we fall further behind than this (the machine's
+    /// too slow, or the window was dragged or asleep) the rest is dropped and the game slows down,
+    /// rather than falling further behind every frame trying to catch up.
+    pub max_ticks: u32,
+
+    /// Real time that's passed that we haven't run ticks for yet
+    accumulator: Duration
+}
+
+impl Default for GameLoop {
+    /// Ticking and drawing at 60 fps, catching up at most a quarter second at once
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1) / 60)
+    }
+}
+
+impl GameLoop {
+    /// A loop that ticks every `tick_length`, and draws at the same rate
+    pub fn new(tick_length: Duration) -> Self {
+        Self {
+            tick_length,
+            frame_length: tick_length,
+            max_ticks: (Duration::from_millis(250).as_secs_f64() / tick_length.as_secs_f64()).ceil().max(1.0) as u32,
+            accumulator: Duration::ZERO
+        }
+    }
+
+    pub fn with_frame_length(self, frame_length: Duration) -> Self {
+        Self { frame_length, ..self }
+    }
+
+    pub fn with_max_ticks(self, max_ticks: u32) -> Self {
+        Self { max_ticks, ..self }
+    }
+
+    /// Some real time passed: returns how many ticks to run for it
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        if self.tick_length.is_zero() {
+            return 0
+        }
+
+        self.accumulator += dt;
+        let tick = self.tick_length.as_nanos();
+        let ticks = self.accumulator.as_nanos() / tick;
+
+        // Only keep the leftover part of a tick if we're dropping some
+        if ticks > self.max_ticks as u128 {
+            self.accumulator = Duration::from_nanos((self.accumulator.as_nanos() % tick) as u64);
+            self.max_ticks
+        } else {
+            self.accumulator -= self.tick_length * ticks as u32;
+            ticks as u32
+        }
+    }
+
+    /// How far into the next tick we are, from 0 (just ticked) to 1 (about to tick)
+    pub fn alpha(&self) -> f32 {
+        if self.tick_length.is_zero() {
+            return 0.0
+        }
+        (self.accumulator.as_secs_f64() / self.tick_length.as_secs_f64()) as f32
+    }
+
+    /// Run the ticks for a frame that took `dt`, and return the alpha to render it with. The
+    /// window and `JsGpuWrapper` call this for you; it's only needed when driving a handler from
+    /// some other loop.
+    pub fn frame<H: WindowEventHandler + ?Sized>(&mut self, handler: &mut H, dt: Duration) -> f32 {
+        for _ in 0..self.advance(dt) {
+            handler.tick(self.tick_length)
+        }
+        self.alpha()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_accumulator() {
+        let mut game_loop = GameLoop::new(ms(10));
+        assert_eq!(game_loop.max_ticks, 25);
+
+        assert_eq!(game_loop.advance(ms(4)), 0);
+        assert_eq!(game_loop.alpha(), 0.4);
+        assert_eq!(game_loop.advance(ms(17)), 2);
+        assert_eq!(game_loop.alpha(), 0.1);
+
+        // Fractions of a millisecond aren't lost
+        assert_eq!(game_loop.advance(Duration::from_micros(8500)), 0);
+        assert_eq!(game_loop.advance(Duration::from_micros(500)), 1);
+    }
+
+    #[test]
+    fn test_max_ticks() {
+        let mut game_loop = GameLoop::new(ms(10)).with_max_ticks(3);
+
+        // A long stall only runs three ticks, and forgets the rest except the leftover fraction
+        assert_eq!(game_loop.advance(ms(1005)), 3);
+        assert_eq!(game_loop.alpha(), 0.5);
+        assert_eq!(game_loop.advance(ms(5)), 1);
+    }
+}
@@ -1,11 +1,24 @@
-use crate::scale_transform;
+use crate::scale_transform::{self, ScalingMode};
 use std::default::Default;
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
-use cgmath::Vector2;
+use cgmath::{Matrix3, Point2, SquareMatrix, Vector2, Vector4};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use wgpu::{BlendState, Buffer, BufferUsages, Color, ColorWrites, CompareFunction, Device, Extent3d, LoadOp, ShaderModule, StoreOp, Surface, SurfaceCapabilities, SurfaceTarget, TexelCopyBufferLayout, TexelCopyTextureInfo, Texture, TextureFormat, TextureUsages};
-use crate::id_buffer::IdBuffer;
-use crate::sprite::{RawSprite, Sprite};
+use image::RgbaImage;
+use wgpu::{BlendState, Buffer, BufferUsages, Color, ColorWrites, CompareFunction, Device, Extent3d, LoadOp, PresentMode, ShaderModule, StoreOp, Surface, SurfaceCapabilities, SurfaceTarget, TexelCopyBufferLayout, TexelCopyTextureInfo, Texture, TextureFormat, TextureUsages};
+use crate::atlas::{AtlasPacker, AtlasRegion};
+use crate::id_buffer::{IdBuffer, IdLayout};
+use crate::id_readback::IdReadback;
+use crate::sprite::{BlendMode, RawSprite, Sprite};
+use crate::sprite_batch::SpriteBatch;
+use crate::event_handler::Resize;
+use crate::hit_test::{AlphaMask, HitTester};
+use crate::retro_display::{RetroDisplay, RETRO_DISPLAY_SIZE};
+use crate::post_process::{Effect, EffectId, PostChain};
+use crate::material::{Material, MaterialId};
+use crate::render_layer::RenderLayer;
+use crate::capture::{self, Capture, Recording};
+use crate::Error;
 
 pub struct GpuWrapper<'a> {
     /// The handles to the actual GPU hardware
@@ -20,20 +33,56 @@ pub struct GpuWrapper<'a> {
     render_pipeline: wgpu::RenderPipeline,
     id_pipeline: wgpu::RenderPipeline,
 
-    /// The window and surface of that window that we're rendering to
+    /// Variations on the render pipeline for translucent sprites, one per `BlendMode`
+    /// (indexed by the mode), which blend with what's behind them instead of writing depth
+    blend_pipelines: Vec<wgpu::RenderPipeline>,
+
+    /// Fills the logical screen with black before the sprites are drawn, so the letterbox bars
+    /// around it can be a different color
+    background_pipeline: wgpu::RenderPipeline,
+
+    /// The size of whatever we're rendering to, and the thing itself: the surface of a window
+    /// or canvas, or an offscreen texture
     current_size: Vector2<u32>,
-    surface: Surface<'a>,
+    target: RenderTarget<'a>,
+
+    /// Whether the window's been resized to nothing, which is what minimizing it does on some
+    /// platforms. Nothing can be made that size, so until it comes back we keep everything at the
+    /// last real size and skip drawing.
+    minimized: bool,
 
     /// The "logical" size of the window space, used for creating the
     /// scale transform
     pub logical_size: Vector2<u32>,
 
+    /// If this is set, `logical_size` is kept the same as the window's size (in logical pixels)
+    /// whenever the window resizes, for games that lay themselves out to fit the window rather
+    /// than scaling a fixed resolution up to it
+    pub follow_window_size: bool,
+
+    /// The window's size in logical pixels, and its scale factor (physical pixels per logical
+    /// one), as of the last `handle_window_resize`
+    pub(crate) window_size: Vector2<u32>,
+    pub(crate) scale_factor: f64,
+
+    /// How the logical size is scaled to fit the window, and the color of the bars around it
+    /// if it doesn't fill the whole thing
+    pub scaling_mode: ScalingMode,
+    pub letterbox_color: Vector4<f32>,
+
+    /// How frames are synced to the display; see `set_present_mode`
+    present_mode: PresentMode,
+
     /// Inputs to the render pipelines: a unit square, which we need
     /// buffers to store on the GPU, and a uniform buffer with the
-    /// scale transform.
+    /// scale transform (and each batch's transform, see `Locals`).
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    render_uniform_buffer: Buffer,
+    render_uniform_buffer: RefCell<Buffer>,
+
+    /// The sprites passed to each redraw get written into this, so we aren't allocating a new
+    /// buffer every frame. It's replaced with a bigger one when a frame has too many sprites.
+    instance_buffer: RefCell<Buffer>,
 
     /// The nearest-neighbor sampler for a sharp pixel effect
     sampler: wgpu::Sampler,
@@ -41,34 +90,196 @@ pub struct GpuWrapper<'a> {
     /// A texture for the pipeline to write depth data to
     depth_texture: crate::texture::Texture,
 
-    /// The textures we'll draw sprites from
-    spritesheets: Vec<crate::texture::Texture>,
+    /// How big each spritesheet is. They all live in one texture array (one per layer), so a
+    /// single bind group can draw from all of them at once; its layers are as big as the biggest
+    /// sheet, and there are usually some spare ones, see `new_layer`.
+    spritesheet_sizes: Vec<Vector2<u32>>,
+
+    /// Which texels of each spritesheet are transparent, for `hit_tester`
+    alpha_masks: Vec<AlphaMask>,
+    spritesheet_array: crate::texture::Texture,
+    render_bind_group: RefCell<wgpu::BindGroup>,
+
+    /// Packs images added with `add_image` onto shared pages, and which layer each page is
+    atlas: AtlasPacker,
+    atlas_layers: Vec<u32>,
 
     /// The texture the id pipeline outputs to, and the buffer
     /// we read them from
     id_texture: crate::texture::Texture,
     id_buffer: Arc<Buffer>,
+
+    /// Window pixels per id texel; see `set_id_downscale`. When the id texture is smaller than the
+    /// window it needs its own depth texture, too.
+    id_downscale: u32,
+    id_depth_texture: Option<crate::texture::Texture>,
+
+    /// The part of the window to read ids back from, if not all of it; see `set_id_region`
+    id_region: Cell<Option<(Point2<u32>, Vector2<u32>)>>,
+
+    /// What was copied into the id buffer by the last id pass
+    id_layout: Cell<IdLayout>,
+
+    /// The staging buffers for `redraw_with_latest_ids`
+    id_readback: RefCell<IdReadback>,
+
+    /// Full-screen passes run over each frame after the sprites are drawn; see `add_effect`
+    post_chain: RefCell<PostChain>,
+
+    /// Custom shaders for sprites, indexed by `MaterialId`; see `add_material`
+    materials: Vec<Material>,
+
+    /// What we need to make more sprite pipelines for materials: the format we're drawing in,
+    /// and the layout of the unit square's vertices
+    format: TextureFormat,
+    vertex_buffer_layout: wgpu::VertexBufferLayout<'static>,
+
+    /// Copies render layers into their spritesheets; made when the first one's created
+    blit_pipeline: Option<wgpu::RenderPipeline>,
+
+    /// Screenshots and recordings of the frames being drawn; see `request_screenshot`
+    capture: RefCell<Capture>,
+}
+
+/// The width and height of each page images are packed into by `add_image`
+const ATLAS_PAGE_SIZE: u32 = 1024;
+
+/// How a run of sprites is drawn: how it blends (`None` for opaque sprites) and with what
+/// material. Consecutive sprites with the same run can be drawn in one call.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct Run {
+    pub blend: Option<BlendMode>,
+    pub material: Option<MaterialId>
+}
+
+/// A set of sprites to draw in one pass: the buffer they're in, their runs, and the transform
+/// to put on top of all of theirs (see `SpriteBatch::set_transform`)
+type Instances<'b> = (&'b Buffer, &'b [Run], Matrix3<f32>);
+
+/// What the vertex shader gets for each set of instances: the scale transform, which is the
+/// same for all of them, and then the set's own transform, as a `mat3x3` (whose columns are
+/// padded out to four floats in a uniform). Each set's copy is in its own slot of the uniform
+/// buffer, picked with a dynamic offset.
+type Locals = [f32; 16 + 12];
+
+fn locals(transform: [f32; 16], batch: Matrix3<f32>) -> Locals {
+    let mut locals = [0.0; 28];
+    locals[..16].copy_from_slice(&transform);
+    for (column, c) in [batch.x, batch.y, batch.z].into_iter().enumerate() {
+        locals[16 + column * 4..][..3].copy_from_slice(&[c.x, c.y, c.z]);
+    }
+    locals
+}
+
+/// What a pass draws into: the color (or id) view and a depth view the same size, how many
+/// window pixels each of their pixels covers (see `set_id_downscale`), and the rectangle of
+/// window pixels to draw in (everything outside it is left alone)
+struct PassTarget<'t> {
+    color: &'t wgpu::TextureView,
+    depth: &'t wgpu::TextureView,
+    downscale: u32,
+    viewport: (Point2<u32>, Vector2<u32>)
+}
+
+/// How many bytes each pixel of the id texture is: the id and the packed uv
+pub(crate) const ID_TEXEL_SIZE: u32 = 8;
+
+/// How many sprites the instance buffer can hold before it first has to grow
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+
+/// Where the render pipeline sends its output. Normally this is the surface of a window or
+/// canvas, but a headless wrapper renders into a texture instead, and copies that texture
+/// into a buffer so it can be read back as an image.
+enum RenderTarget<'a> {
+    Surface(Surface<'a>),
+    Offscreen {
+        texture: crate::texture::Texture,
+        buffer: Arc<Buffer>
+    }
+}
+
+/// The order sprites are drawn in. Opaque sprites go first, so they're all in the depth buffer
+/// before we draw the translucent ones, which have to go strictly back to front because each one
+/// blends with what's already been drawn.
+pub(crate) fn draw_order(a: &Sprite, b: &Sprite) -> std::cmp::Ordering {
+    a.blend.is_some().cmp(&b.blend.is_some()).then_with(|| {
+        if a.z == b.z {
+            b.layer.cmp(&a.layer)
+        } else {
+            b.z.total_cmp(&a.z)
+        }
+    })
 }
 
 impl<'a> GpuWrapper<'a> {
+    /// Create a wrapper drawing to a window or canvas. This panics if there's no GPU to draw
+    /// with; see `try_targeting` to handle that yourself.
     pub async fn targeting(target: impl Into<SurfaceTarget<'a>>, physical_size: Vector2<u32>, logical_size: Vector2<u32>) -> Self {
+        Self::try_targeting(target, physical_size, logical_size).await.expect("Failed to create GpuWrapper")
+    }
+
+    pub async fn try_targeting(target: impl Into<SurfaceTarget<'a>>, physical_size: Vector2<u32>, logical_size: Vector2<u32>) -> crate::Result<Self> {
         let target = target.into();
-        let (surface, adapter, device, queue) = Self::create_device(target).await;
+        let (surface, adapter, device, queue) = Self::create_device(target).await?;
         let surface_caps = surface.get_capabilities(&adapter);
-        let format = *surface_caps.formats.iter().find(|f| !f.is_srgb()).unwrap(); // Find the first format that's not srgb...
+        let format = Self::surface_format(&surface_caps).ok_or(Error::NoSurfaceFormat)?;
+
+        let wrapper = Self::new(adapter, device, queue, RenderTarget::Surface(surface), format, physical_size, logical_size);
+        wrapper.configure_surface();
+        Ok(wrapper)
+    }
+
+    /// Create a wrapper with no window at all, which renders into an offscreen texture instead.
+    /// This prefers a software (fallback) adapter if there is one, so it can run on a machine
+    /// without a GPU, like CI. Frames rendered by this can be read back with `render_to_image`.
+    pub async fn headless(physical_size: Vector2<u32>, logical_size: Vector2<u32>) -> Self {
+        Self::try_headless(physical_size, logical_size).await.expect("Failed to create GpuWrapper")
+    }
 
-        let config = Self::surface_config(&surface_caps, format, physical_size);
-        surface.configure(&device, &config);
-        let depth_texture = crate::texture::Texture::create_depth_texture(&device, &config);
-        let id_texture = crate::texture::Texture::create_id_texture(&device, &config);
-        let render_uniform_buffer = Self::create_buffer(&device, "render-uniform-buffer", (16 * 4) as wgpu::BufferAddress, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+    pub async fn try_headless(physical_size: Vector2<u32>, logical_size: Vector2<u32>) -> crate::Result<Self> {
+        let instance = Self::create_instance();
+
+        let adapter = match instance.request_adapter(&wgpu::RequestAdapterOptions {
+            force_fallback_adapter: true,
+            ..Default::default()
+        }).await {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&Default::default())
+                .await
+                .ok_or(Error::NoAdapter)?
+        };
+
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let format = TextureFormat::Rgba8Unorm;
+        let texture = crate::texture::Texture::create_offscreen_texture(&device, physical_size, format);
+        let buffer = Arc::new(Self::create_id_buffer(&device, &texture.texture));
+        Ok(Self::new(adapter, device, queue, RenderTarget::Offscreen { texture, buffer }, format, physical_size, logical_size))
+    }
+
+    /// Everything shared between the windowed and headless constructors: once we have a device
+    /// and something to draw on, build the pipelines and the buffers they need
+    fn new(adapter: wgpu::Adapter, device: Device, queue: wgpu::Queue, target: RenderTarget<'a>, format: TextureFormat, physical_size: Vector2<u32>, logical_size: Vector2<u32>) -> Self {
+        let minimized = physical_size.x == 0 || physical_size.y == 0;
+        let physical_size = physical_size.map(|n| n.max(1));
+        let logical_size = logical_size.map(|n| n.max(1));
+        let depth_texture = crate::texture::Texture::create_depth_texture(&device, physical_size);
+        let id_texture = crate::texture::Texture::create_id_texture(&device, physical_size);
+        let render_uniform_buffer = Self::create_uniform_buffer(&device, 1);
+        let instance_buffer = RefCell::new(Self::create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY));
         let sampler = Self::create_sampler(&device);
         let id_buffer = Arc::new(Self::create_id_buffer(&device, &id_texture.texture));
         let (vertex_buffer, vertex_buffer_layout) = Self::create_vertex_buffer(&device);
         let index_buffer = Self::create_index_buffer(&device);
         let shader = Self::create_shader(&device);
-        let render_pipeline = Self::create_render_pipeline(&device, vertex_buffer_layout.clone(), &shader, format);
-        let id_pipeline = Self::create_id_pipeline(&device, vertex_buffer_layout, &shader);
+        let render_pipeline = Self::create_render_pipeline(&device, vertex_buffer_layout.clone(), &shader, format, None, None);
+        let blend_pipelines = BlendMode::ALL.iter().map(|mode| Self::create_render_pipeline(&device, vertex_buffer_layout.clone(), &shader, format, Some(*mode), None)).collect();
+        let background_pipeline = Self::create_background_pipeline(&device, vertex_buffer_layout.clone(), &shader, format);
+        let id_pipeline = Self::create_id_pipeline(&device, vertex_buffer_layout.clone(), &shader, None);
+        let spritesheet_array = crate::texture::Texture::create_array(&device, (1, 1).into(), 1);
+        let render_bind_group = RefCell::new(Self::create_render_bind_group(&device, &render_pipeline, &sampler, &spritesheet_array, &render_uniform_buffer));
+        let render_uniform_buffer = RefCell::new(render_uniform_buffer);
+        let post_chain = RefCell::new(PostChain::new(&device, format));
 
         Self {
             adapter,
@@ -76,42 +287,85 @@ impl<'a> GpuWrapper<'a> {
             queue,
             render_pipeline,
             id_pipeline,
+            blend_pipelines,
+            background_pipeline,
             current_size: physical_size,
-            surface,
+            target,
+            minimized,
             logical_size,
+            follow_window_size: false,
+            window_size: logical_size,
+            scale_factor: 1.0,
+            scaling_mode: ScalingMode::default(),
+            letterbox_color: (0.0, 0.0, 0.0, 1.0).into(),
+            present_mode: PresentMode::AutoVsync,
             vertex_buffer,
             index_buffer,
             render_uniform_buffer,
+            instance_buffer,
             sampler,
             depth_texture,
             id_texture,
             id_buffer,
-            spritesheets: vec![],
+            id_downscale: 1,
+            id_depth_texture: None,
+            id_region: Cell::new(None),
+            id_layout: Cell::new(IdLayout::full(physical_size)),
+            id_readback: RefCell::new(IdReadback::default()),
+            post_chain,
+            materials: vec![],
+            format,
+            vertex_buffer_layout,
+            blit_pipeline: None,
+            capture: RefCell::new(Capture::default()),
+            spritesheet_sizes: vec![],
+            alpha_masks: vec![],
+            spritesheet_array,
+            render_bind_group,
+            atlas: AtlasPacker::new((ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE)),
+            atlas_layers: vec![],
         }
     }
 
-    pub async fn create_device(target: impl Into<SurfaceTarget<'a>>) -> (Surface<'a>, wgpu::Adapter, Device, wgpu::Queue) {
+    pub async fn create_device(target: impl Into<SurfaceTarget<'a>>) -> crate::Result<(Surface<'a>, wgpu::Adapter, Device, wgpu::Queue)> {
         let target = target.into();
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY | wgpu::Backends::GL,
-            ..Default::default()
-        });
+        let instance = Self::create_instance();
 
-        let surface = instance.create_surface(target).expect("Failed to create surface");
+        let surface = instance.create_surface(target)?;
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 compatible_surface: Some(&surface),
                 ..Default::default()
             })
             .await
-            .expect("Failed to create adapter");
+            .ok_or(Error::NoAdapter)?;
 
+        let (device, queue) = Self::request_device(&adapter).await?;
+        Ok((surface, adapter, device, queue))
+    }
+
+    fn create_instance() -> wgpu::Instance {
+        wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::PRIMARY | wgpu::Backends::GL,
+            ..Default::default()
+        })
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> crate::Result<(Device, wgpu::Queue)> {
+        // WebGL2 is the lowest common denominator, but if the adapter can run compute shaders
+        // (anything but WebGL, really) ask for enough to run a `RetroDisplay` too
+        let compute = wgpu::Limits::downlevel_defaults();
+        let base = if adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) && compute.check_limits(&adapter.limits()) {
+            compute
+        } else {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        };
         let limits = wgpu::Limits {
             max_texture_dimension_2d: 8192,
-            ..wgpu::Limits::downlevel_webgl2_defaults()
+            ..base
         };
 
-        let (device, queue) = adapter
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
@@ -122,9 +376,7 @@ impl<'a> GpuWrapper<'a> {
                 None,
             )
             .await
-            .expect("Failed to create device / queue");
-
-        (surface, adapter, device, queue)
+            .map_err(Error::from)
     }
 
     fn create_buffer(device: &Device, label: &str, size: wgpu::BufferAddress, usage: BufferUsages) -> Buffer {
@@ -143,7 +395,7 @@ impl<'a> GpuWrapper<'a> {
         })
     }
 
-    fn create_vertex_buffer(device: &Device) -> (Buffer, wgpu::VertexBufferLayout) {
+    fn create_vertex_buffer(device: &Device) -> (Buffer, wgpu::VertexBufferLayout<'static>) {
         let vertex_data: [[f32; 2]; 4] = [
             [0.0, 1.0],
             [0.0, 0.0],
@@ -188,8 +440,10 @@ impl<'a> GpuWrapper<'a> {
         })
     }
 
-    fn create_render_pipeline(device: &Device, vertex_buffer_layout: wgpu::VertexBufferLayout, shader: &ShaderModule, format: TextureFormat) -> wgpu::RenderPipeline {
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    /// The layout of the bind group all the pipelines share: the sampler, the spritesheet array,
+    /// and the scale transform
+    fn create_bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("render pipeline"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
@@ -201,31 +455,47 @@ impl<'a> GpuWrapper<'a> {
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Texture {
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
                         multisampled: false,
                         sample_type: wgpu::TextureSampleType::Float { filterable: true }
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    // The transform matrix for the vertex shader
+                    // The transform matrices for the vertex shader, one slot per set of instances
                     binding: 2,
                     visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<Locals>() as u64),
                     },
                     count: None,
                 },
             ],
-        });
+        })
+    }
+
+    /// The render pipeline for opaque sprites, or (with a blend mode) for translucent ones. Opaque
+    /// sprites write to the depth buffer so the closest one wins; translucent ones only test against
+    /// it, and blend with whatever's already been drawn behind them.
+    /// A material's pipelines (given the layout of its bind group) use its shader's `fs_material`
+    /// instead of `fs_main`, and have its uniforms bound as well.
+    fn create_render_pipeline(device: &Device, vertex_buffer_layout: wgpu::VertexBufferLayout, shader: &ShaderModule, format: TextureFormat, blend: Option<BlendMode>, material: Option<&wgpu::BindGroupLayout>) -> wgpu::RenderPipeline {
+        let bind_group_layout = Self::create_bind_group_layout(device);
+        let layout = match material {
+            None => Self::pipeline_layout_for(device, bind_group_layout),
+            Some(material) => device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[&bind_group_layout, material],
+                ..Default::default()
+            })
+        };
 
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("render pipeline"),
-            layout: Some(&Self::pipeline_layout_for(device, bind_group_layout)),
+            layout: Some(&layout),
             vertex: wgpu::VertexState {
                 module: shader,
                 entry_point: Some("vs_main"),
@@ -239,7 +509,7 @@ impl<'a> GpuWrapper<'a> {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
+                depth_write_enabled: blend.is_none(),
                 depth_compare: CompareFunction::Less,
                 stencil: Default::default(),
                 bias: Default::default(),
@@ -247,11 +517,53 @@ impl<'a> GpuWrapper<'a> {
             multisample: Default::default(),
             fragment: Some(wgpu::FragmentState {
                 module: shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some(if material.is_some() { "fs_material" } else { "fs_main" }),
                 compilation_options: Default::default(),
                 targets: &[Some(wgpu::ColorTargetState {
                     format,
-                    blend: Some(BlendState::ALPHA_BLENDING),
+                    blend: Some(blend.map_or(BlendState::ALPHA_BLENDING, BlendMode::blend_state)),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Draws a single (black) unit square, through the scale transform, under everything else. It
+    /// doesn't touch the depth buffer, so sprites still draw over it.
+    fn create_background_pipeline(device: &Device, vertex_buffer_layout: wgpu::VertexBufferLayout, shader: &ShaderModule, format: TextureFormat) -> wgpu::RenderPipeline {
+        let bind_group_layout = Self::create_bind_group_layout(device);
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("background pipeline"),
+            layout: Some(&Self::pipeline_layout_for(device, bind_group_layout)),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_background"),
+                compilation_options: Default::default(),
+                buffers: &[vertex_buffer_layout],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Always,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_background"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
                     write_mask: ColorWrites::ALL,
                 })],
             }),
@@ -262,45 +574,23 @@ impl<'a> GpuWrapper<'a> {
 
     /// Very similar to the render pipeline, but different in two ways:
     /// One, the fragment stage uses fs_id instead of fs_main, because we want to run a different
-    /// shader. Two, the output is R32Uint, because we're just extracting one u32 out of this.
-    fn create_id_pipeline(device: &Device, vertex_buffer_layout: wgpu::VertexBufferLayout, shader: &ShaderModule) -> wgpu::RenderPipeline {
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    // The nearest-neighbor sampler
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true }
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    // The transform matrix for the vertex shader
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
+    /// shader. Two, the output is Rg32Uint, because we're just extracting two u32s out of this:
+    /// the id, and where on the sprite it was hit.
+    /// The id pipeline, or (given the layout of its bind group) a material's, which uses its
+    /// shader's `fs_material_id` so that pixels the material makes transparent aren't hit either
+    fn create_id_pipeline(device: &Device, vertex_buffer_layout: wgpu::VertexBufferLayout, shader: &ShaderModule, material: Option<&wgpu::BindGroupLayout>) -> wgpu::RenderPipeline {
+        let bind_group_layout = Self::create_bind_group_layout(device);
+        let layout = match material {
+            None => Self::pipeline_layout_for(device, bind_group_layout),
+            Some(material) => device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[&bind_group_layout, material],
+                ..Default::default()
+            })
+        };
 
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("id shader"),
-            layout: Some(&Self::pipeline_layout_for(device, bind_group_layout)),
+            layout: Some(&layout),
             vertex: wgpu::VertexState {
                 module: shader,
                 entry_point: Some("vs_main"),
@@ -322,12 +612,12 @@ impl<'a> GpuWrapper<'a> {
             multisample: Default::default(),
             fragment: Some(wgpu::FragmentState {
                 module: shader,
-                entry_point: Some("fs_id"),
+                entry_point: Some(if material.is_some() { "fs_material_id" } else { "fs_id" }),
                 compilation_options: Default::default(),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: TextureFormat::R32Uint,
+                    format: TextureFormat::Rg32Uint,
                     blend: None,
-                    write_mask: ColorWrites::RED,
+                    write_mask: ColorWrites::RED | ColorWrites::GREEN,
                 })],
             }),
             multiview: None,
@@ -353,27 +643,185 @@ impl<'a> GpuWrapper<'a> {
         })
     }
 
+    /// Convert a point in window pixels (like a mouse position) to logical pixels, undoing the
+    /// scaling and centering that `redraw` does
+    pub fn window_to_logical(&self, point: impl Into<Point2<f64>>) -> Point2<f64> {
+        scale_transform::window_to_texture(self.logical_size, self.current_size, point.into(), self.scaling_mode)
+    }
+
+    /// Convert a point in logical pixels to where it's drawn in the window; the opposite of
+    /// `window_to_logical`
+    pub fn logical_to_window(&self, point: impl Into<Point2<f64>>) -> Point2<f64> {
+        scale_transform::texture_to_window(self.logical_size, self.current_size, point.into(), self.scaling_mode)
+    }
+
+    /// How much of the window, as a fraction of its size, one logical pixel covers
+    fn logical_pixel_uv(&self) -> Vector2<f32> {
+        let pixel = self.logical_to_window((1.0, 1.0)) - self.logical_to_window((0.0, 0.0));
+        Vector2::new((pixel.x / self.current_size.x as f64) as f32, (pixel.y / self.current_size.y as f64) as f32)
+    }
+
     /// Call whenever the window backing all this is resized, to update the various internal
     /// textures and buffers needed for the render pipeline
     pub fn handle_resize(&mut self, new_size: Vector2<u32>) {
-        let surface_caps = self.surface.get_capabilities(&self.adapter);
-        let format = *surface_caps.formats.iter().find(|f| !f.is_srgb()).unwrap();
-        let config = Self::surface_config(&surface_caps, format, new_size);
-        self.depth_texture = crate::texture::Texture::create_depth_texture(&self.device, &config);
-        self.id_texture = crate::texture::Texture::create_id_texture(&self.device, &config);
-        self.id_buffer = Arc::new(Self::create_id_buffer(&self.device, &self.id_texture.texture));
-        self.surface.configure(&self.device, &config);
+        self.minimized = new_size.x == 0 || new_size.y == 0;
+        if self.minimized { return }
+
+        self.depth_texture = crate::texture::Texture::create_depth_texture(&self.device, new_size);
+
+        if let RenderTarget::Offscreen { texture, buffer } = &mut self.target {
+            *texture = crate::texture::Texture::create_offscreen_texture(&self.device, new_size, texture.texture.format());
+            *buffer = Arc::new(Self::create_id_buffer(&self.device, &texture.texture));
+        }
         self.current_size = new_size;
+        self.resize_id_texture();
+        self.configure_surface();
+    }
+
+    /// Remake the id texture (and everything sized to match it) for the current size and downscale
+    fn resize_id_texture(&mut self) {
+        let size = self.current_size.map(|n| n.div_ceil(self.id_downscale));
+        self.id_texture = crate::texture::Texture::create_id_texture(&self.device, size);
+        self.id_depth_texture = (self.id_downscale > 1).then(|| crate::texture::Texture::create_depth_texture(&self.device, size));
+        self.id_buffer = Arc::new(Self::create_id_buffer(&self.device, &self.id_texture.texture));
+        self.id_readback.get_mut().clear();
+    }
+
+    /// Draw the id buffer at a fraction of the window's resolution: each id texel covers a
+    /// `downscale` by `downscale` square of window pixels. Hit testing gets coarser, but there's
+    /// that many times fewer pixels to draw and read back. The default is 1, full resolution.
+    pub fn set_id_downscale(&mut self, downscale: u32) {
+        self.id_downscale = downscale.max(1);
+        self.resize_id_texture();
+    }
+
+    pub fn id_downscale(&self) -> u32 {
+        self.id_downscale
+    }
+
+    /// Only read back part of the id buffer: a rectangle (origin and size, in physical window
+    /// pixels), or the whole thing for `None`. Everything outside the region reads as 0. This is
+    /// for games that only ever look up what's under the mouse, which can read back a few pixels
+    /// around it instead of the whole window; see `set_id_region_around`. It's kept until changed.
+    pub fn set_id_region(&self, region: Option<(Point2<u32>, Vector2<u32>)>) {
+        self.id_region.set(region)
+    }
+
+    /// Only read back the square of the id buffer within `radius` pixels of a point (probably
+    /// the mouse); see `set_id_region`
+    pub fn set_id_region_around(&self, center: impl Into<Point2<f64>>, radius: u32) {
+        let center = center.into();
+        let origin = Point2::new((center.x - radius as f64).max(0.0) as u32, (center.y - radius as f64).max(0.0) as u32);
+        self.set_id_region(Some((origin, Vector2::new(radius * 2 + 1, radius * 2 + 1))))
+    }
+
+    /// What part of the id texture the next id pass will read back, in id texels, given the
+    /// region and downscale. This is always at least one texel.
+    fn next_id_layout(&self) -> IdLayout {
+        let id_size = self.id_texture.size;
+        let (origin, size) = match self.id_region.get() {
+            None => (Point2::new(0, 0), id_size),
+            Some((origin, size)) => {
+                let d = self.id_downscale;
+                let left = (origin.x / d).min(id_size.x.saturating_sub(1));
+                let top = (origin.y / d).min(id_size.y.saturating_sub(1));
+                let right = (origin.x + size.x).div_ceil(d).clamp(left + 1, id_size.x.max(left + 1));
+                let bottom = (origin.y + size.y).div_ceil(d).clamp(top + 1, id_size.y.max(top + 1));
+                (Point2::new(left, top), Vector2::new(right - left, bottom - top))
+            }
+        };
+
+        IdLayout {
+            origin,
+            size,
+            downscale: self.id_downscale,
+            window_size: self.current_size,
+            logical_size: self.logical_size,
+            scaling_mode: self.scaling_mode
+        }
     }
 
-    /// Creates a config object for the surface given a physical size. Called by `handle_resize`
-    fn surface_config(surface_caps: &SurfaceCapabilities, format: TextureFormat, size: Vector2<u32>) -> wgpu::SurfaceConfiguration {
+    /// Like `handle_resize`, but also knowing the window's scale factor, so it can work out the
+    /// window's new logical size (and make it the `logical_size`, if `follow_window_size` is set).
+    /// Returns the event to send to `WindowEventHandler::resize`. The event loops call this; you
+    /// only need to if you're running your own.
+    pub fn handle_window_resize(&mut self, physical_size: Vector2<u32>, scale_factor: f64) -> Resize {
+        let old_size = self.window_size;
+        self.handle_resize(physical_size);
+        self.scale_factor = scale_factor;
+
+        // A minimized window keeps its last real size, rather than having everything divide by 0
+        if !self.minimized {
+            self.window_size = (
+                ((physical_size.x as f64 / scale_factor).round() as u32).max(1),
+                ((physical_size.y as f64 / scale_factor).round() as u32).max(1)
+            ).into();
+        }
+
+        if self.follow_window_size {
+            self.logical_size = self.window_size
+        }
+        Resize { old_size, new_size: self.window_size, physical_size }
+    }
+
+    /// The window's size in logical pixels. This is the same as `logical_size` unless the game
+    /// set its own logical size (or the window has resized and `follow_window_size` is off).
+    pub fn window_size(&self) -> Vector2<u32> {
+        self.window_size
+    }
+
+    /// How many physical pixels there are per logical pixel, as of the last resize
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// The present mode asked for with `set_present_mode` (which may not be the one in use, if the
+    /// surface doesn't support it)
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    /// Change how frames are synced to the display: `Fifo` or `AutoVsync` for vsync, `Immediate`,
+    /// `Mailbox` or `AutoNoVsync` to draw as fast as possible. If the surface doesn't support the
+    /// mode asked for, this falls back to `AutoVsync`, which everything does.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        self.present_mode = present_mode;
+        self.configure_surface()
+    }
+
+    /// (Re)configure the surface, if there is one, for the current size and present mode. A
+    /// minimized window's surface is left alone, since it can't be configured to be zero-sized.
+    fn configure_surface(&self) {
+        if self.minimized { return }
+        if let RenderTarget::Surface(surface) = &self.target {
+            let surface_caps = surface.get_capabilities(&self.adapter);
+            let Some(format) = Self::surface_format(&surface_caps) else { return };
+            let config = Self::surface_config(&surface_caps, format, self.current_size, self.present_mode);
+            surface.configure(&self.device, &config);
+        }
+    }
+
+    /// The format to draw to the surface in: the first one that's not srgb, if there is one
+    fn surface_format(surface_caps: &SurfaceCapabilities) -> Option<TextureFormat> {
+        surface_caps.formats.iter().find(|f| !f.is_srgb()).or(surface_caps.formats.first()).copied()
+    }
+
+    /// Creates a config object for the surface given a physical size. Called by `configure_surface`
+    fn surface_config(surface_caps: &SurfaceCapabilities, format: TextureFormat, size: Vector2<u32>, present_mode: PresentMode) -> wgpu::SurfaceConfiguration {
+        // The auto modes are always allowed, even though they're never in the list
+        let present_mode = match present_mode {
+            PresentMode::AutoVsync | PresentMode::AutoNoVsync => present_mode,
+            _ if surface_caps.present_modes.contains(&present_mode) => present_mode,
+            _ => PresentMode::AutoVsync
+        };
+
         wgpu::SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
+            // Copying frames out is for screenshots, which not every surface allows
+            usage: TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & TextureUsages::COPY_SRC),
             format,
             width: size.x,
             height: size.y,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             desired_maximum_frame_latency: 2,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
@@ -381,65 +829,160 @@ impl<'a> GpuWrapper<'a> {
     }
 
     /// The bind group for the render pass
-    fn render_bind_groups(&self) -> Vec<wgpu::BindGroup> {
-        let bind_group_layout = self.render_pipeline.get_bind_group_layout(0);
-
-        self.spritesheets.iter().map(|sp|
-            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &bind_group_layout,
-                entries: &[
-                    // The sampler
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                    // The texture for the spritesheet
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&sp.view),
-                    },
-                    // The uniform buffer, which contains the overall transform matrix
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: self.render_uniform_buffer.as_entire_binding(),
-                    },
-                ],
-            })
-        ).collect()
+    fn create_render_bind_group(device: &Device, pipeline: &wgpu::RenderPipeline, sampler: &wgpu::Sampler, spritesheet_array: &crate::texture::Texture, uniform_buffer: &Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                // The sampler
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                // The texture array with all the spritesheets
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&spritesheet_array.view),
+                },
+                // The uniform buffer, which contains the overall transform matrix (one slot of
+                // it at a time, see `Locals`)
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: uniform_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(size_of::<Locals>() as u64),
+                    }),
+                },
+            ],
+        })
+    }
+
+    /// Make room in the spritesheet array for one more sheet of this size, returning its layer.
+    /// Usually there's a spare layer already. If there isn't (or the sheet's bigger than the
+    /// layers), the array's replaced with one with twice as many layers (or bigger ones), the
+    /// sheets so far are copied across in one go, and the new array is bound in place of the old.
+    fn new_layer(&mut self, size: Vector2<u32>) -> u32 {
+        let layer = self.spritesheet_sizes.len() as u32;
+        self.spritesheet_sizes.push(size);
+
+        let old = &self.spritesheet_array;
+        let capacity = old.texture.depth_or_array_layers();
+        if layer < capacity && size.x <= old.size.x && size.y <= old.size.y {
+            return layer
+        }
+
+        let layers = if layer < capacity { capacity } else { (capacity * 2).min(self.device.limits().max_texture_array_layers) };
+        let array = crate::texture::Texture::create_array(&self.device, Vector2::new(old.size.x.max(size.x), old.size.y.max(size.y)), layers);
+        if layer > 0 {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            encoder.copy_texture_to_texture(old.texture.as_image_copy(), array.texture.as_image_copy(), Extent3d {
+                width: old.size.x,
+                height: old.size.y,
+                depth_or_array_layers: layer,
+            });
+            self.queue.submit(Some(encoder.finish()));
+        }
+
+        *self.render_bind_group.borrow_mut() = Self::create_render_bind_group(&self.device, &self.render_pipeline, &self.sampler, &array, &self.render_uniform_buffer.borrow());
+        self.spritesheet_array = array;
+        layer
     }
 
-    /// Writes the scaling transform matrix to the uniform buffer, so the render pass can pick it up
-    fn bind_for_render(&self) {
-        self.queue.write_buffer(&self.render_uniform_buffer, 0, bytemuck::bytes_of(&scale_transform::transform(self.logical_size, self.current_size)));
+    /// Queues copying a whole texture into one layer of the spritesheet array
+    fn copy_to_layer(encoder: &mut wgpu::CommandEncoder, source: &crate::texture::Texture, array: &crate::texture::Texture, layer: u32) {
+        Self::copy_region_to_layer(encoder, source, array, layer, (0, 0).into(), source.size)
     }
 
-    /// The instance buffer contains the packed sprite data for the render pipeline to iterate over
-    fn create_instance_buffer<S: AsRef<Sprite>>(&self, sprites: Vec<S>) -> Buffer {
-        let raw_sprites = sprites.into_iter().map(|s| s.as_ref().into_raw(self.spritesheets[s.as_ref().layer as usize].size)).collect::<Vec<RawSprite>>();
-        self.device.create_buffer_init(
-            &BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&raw_sprites),
-                usage: BufferUsages::VERTEX,
-            }
-        )
+    /// Queues copying one rectangle of a texture into the same place in a layer of the
+    /// spritesheet array
+    fn copy_region_to_layer(encoder: &mut wgpu::CommandEncoder, source: &crate::texture::Texture, array: &crate::texture::Texture, layer: u32, origin: Point2<u32>, size: Vector2<u32>) {
+        encoder.copy_texture_to_texture(TexelCopyTextureInfo {
+            texture: &source.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
+            aspect: Default::default(),
+        }, TexelCopyTextureInfo {
+            texture: &array.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: layer },
+            aspect: Default::default(),
+        }, Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        });
+    }
+
+    /// How far apart the slots of the uniform buffer are: a `Locals`, padded out to the alignment
+    /// the device wants for dynamic offsets
+    fn uniform_stride(device: &Device) -> u64 {
+        (size_of::<Locals>() as u64).next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64)
+    }
+
+    fn create_uniform_buffer(device: &Device, slots: usize) -> Buffer {
+        Self::create_buffer(device, "render-uniform-buffer", Self::uniform_stride(device) * slots as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST)
+    }
+
+    /// Writes the scaling transform matrix to the uniform buffer, so the render pass can pick it
+    /// up, along with each set of instances' own transform in a slot of its own. That's all
+    /// moving a batch costs: one matrix, not re-encoding its sprites.
+    fn bind_for_render(&self, transform: [f32; 16], instances: &[Instances]) {
+        let stride = Self::uniform_stride(&self.device) as usize;
+        let mut bytes = vec![0u8; stride * instances.len()];
+        for (slot, (_, _, batch)) in instances.iter().enumerate() {
+            bytes[slot * stride..][..size_of::<Locals>()].copy_from_slice(bytemuck::bytes_of(&locals(transform, *batch)));
+        }
+
+        // Room for more batches means a new buffer, and a bind group pointing at it
+        if bytes.len() as u64 > self.render_uniform_buffer.borrow().size() {
+            let buffer = Self::create_uniform_buffer(&self.device, instances.len().next_power_of_two());
+            *self.render_bind_group.borrow_mut() = Self::create_render_bind_group(&self.device, &self.render_pipeline, &self.sampler, &self.spritesheet_array, &buffer);
+            *self.render_uniform_buffer.borrow_mut() = buffer;
+        }
+        self.queue.write_buffer(&self.render_uniform_buffer.borrow(), 0, &bytes);
+    }
+
+    /// The sets of instances to draw for some batches plus some loose sprites (which go last, and
+    /// aren't transformed any further)
+    fn instances<'b>(batches: &[&'b SpriteBatch], loose: &'b Buffer, runs: &'b [Run]) -> Vec<Instances<'b>> {
+        let mut instances: Vec<Instances> = batches.iter().map(|b| (&b.buffer, b.runs.as_slice(), b.transform)).collect();
+        instances.push((loose, runs, Matrix3::identity()));
+        instances
+    }
+
+    /// The instance buffer contains the packed sprite data for the render pipeline to iterate over.
+    /// This creates an empty one with room for `capacity` sprites.
+    fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+        Self::create_buffer(device, "instance buffer", (capacity * size_of::<RawSprite>()) as wgpu::BufferAddress, BufferUsages::VERTEX | BufferUsages::COPY_DST)
     }
 
     /// Queues a call to an arbitrary shader pipeline, targeting an arbitrary texture view. It will
-    /// iterate over the given instances for the unit-square-vertex-buffer.
-    fn call_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &Buffer, layers: &[u32], pipeline: &wgpu::RenderPipeline, target: &wgpu::TextureView) {
+    /// iterate over each of the given sets of instances for the unit-square-vertex-buffer. If
+    /// `blend_pipelines` is given, runs of translucent sprites are drawn with those instead of
+    /// `pipeline`. If `background` is given, the target is cleared to the letterbox color and
+    /// then that's drawn first, to fill in the logical screen; otherwise it's cleared to
+    /// transparent (which for the id texture is all zeroes).
+    fn call_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &[Instances], pipeline: &wgpu::RenderPipeline, blend_pipelines: Option<&[wgpu::RenderPipeline]>, background: Option<&wgpu::RenderPipeline>, target: PassTarget) {
+        let clear = match background {
+            Some(_) => {
+                let [r, g, b, a]: [f32; 4] = self.letterbox_color.into();
+                Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 }
+            }
+            None => Color::TRANSPARENT
+        };
+
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: target,
+                view: target.color,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: LoadOp::Clear(Color::BLACK),
+                    load: LoadOp::Clear(clear),
                     store: StoreOp::Store,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
+                view: target.depth,
                 depth_ops: Some(wgpu::Operations {
                     load: LoadOp::Clear(1.0),
                     store: StoreOp::Store,
@@ -448,81 +991,168 @@ impl<'a> GpuWrapper<'a> {
             }),
             ..Default::default()
         });
-        rpass.set_pipeline(pipeline);
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-
-        rpass.set_vertex_buffer(1, instances.slice(..));
-
         rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
-        let bind_groups = self.render_bind_groups();
-
-        // Go through the runs of same-layer sprites and dispatch draw calls
-        let mut start = 0;
-        let mut end = 0;
-        while start < layers.len() {
-            // after this, end is the first one of the new group, start is the first of this group
-            while end < layers.len() && layers[start] == layers[end] { end += 1 }
+        // Every spritesheet is in the one texture array, so this is the only binding we need,
+        // though each set of instances gets its own slot of the uniform buffer
+        let bind_group = self.render_bind_group.borrow();
+        let stride = Self::uniform_stride(&self.device) as u32;
+        rpass.set_bind_group(0, &*bind_group, &[0]);
+
+        // Nothing gets drawn in the letterbox bars, including sprites hanging off the edge of the
+        // logical screen. This also keeps them out of the id buffer.
+        let (origin, size) = target.viewport;
+        let d = target.downscale;
+        let (left, top) = (origin.x / d, origin.y / d);
+        let (right, bottom) = ((origin.x + size.x).div_ceil(d), (origin.y + size.y).div_ceil(d));
+        rpass.set_scissor_rect(left, top, right - left, bottom - top);
+
+        if let Some(background) = background {
+            rpass.set_pipeline(background);
+            rpass.draw_indexed(0..6, 0, 0..1);
+        }
 
-            // Bind the texture for this group
-            rpass.set_bind_group(0, &bind_groups[layers[start] as usize], &[]);
-            // Draw this run!
-            rpass.draw_indexed(0..6, 0, start as u32..end as u32);
-            start = end; // Jump to the next group
+        // Draw every set's opaque sprites before anyone's translucent ones, so the translucent
+        // ones have everything they could be in front of already in the depth buffer
+        for translucent in [false, true] {
+            for (slot, (buffer, runs, _)) in instances.iter().enumerate().filter(|(_, (_, runs, _))| !runs.is_empty()) {
+                rpass.set_bind_group(0, &*bind_group, &[slot as u32 * stride]);
+                rpass.set_vertex_buffer(1, buffer.slice(..));
+
+                // Go through the runs of same-blend sprites and dispatch draw calls. If nothing's
+                // translucent, that's one call for the whole set.
+                let mut start = 0;
+                let mut end = 0;
+                while start < runs.len() {
+                    // after this, end is the first one of the new group, start is the first of this group
+                    while end < runs.len() && runs[start] == runs[end] { end += 1 }
+
+                    let run = runs[start];
+                    if run.blend.is_some() == translucent {
+                        // The id pass runs materials too, so what they hide can't be clicked on
+                        let material = run.material.and_then(|id| self.materials.get(id.0 as usize));
+                        match (material, blend_pipelines, run.blend) {
+                            (Some(material), blend_pipelines, blend) => {
+                                rpass.set_pipeline(if blend_pipelines.is_some() { material.pipeline(blend) } else { &material.id_pipeline });
+                                rpass.set_bind_group(1, &material.bind_group, &[]);
+                            }
+                            (None, Some(blend_pipelines), Some(mode)) => rpass.set_pipeline(&blend_pipelines[mode as usize]),
+                            _ => rpass.set_pipeline(pipeline)
+                        }
+
+                        // Draw this run!
+                        rpass.draw_indexed(0..6, 0, start as u32..end as u32);
+                    }
+                    start = end; // Jump to the next group
+                }
+            }
         }
     }
 
-    /// Queues a call to the render shader, which outputs color data to the surface
-    fn call_render_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &Buffer, layers: &[u32], surface: &wgpu::SurfaceTexture) {
-        self.call_shader(encoder, instances, layers, &self.render_pipeline, &surface.texture.create_view(&Default::default()))
+    /// Queues a call to the render shader, which outputs color data to the given view (of the
+    /// surface texture, or the offscreen texture)
+    fn call_render_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &[Instances], target: &wgpu::TextureView) {
+        self.call_shader(encoder, instances, &self.render_pipeline, Some(&self.blend_pipelines), Some(&self.background_pipeline), PassTarget { color: target, depth: &self.depth_texture.view, downscale: 1, viewport: self.viewport() })
+    }
+
+    /// The part of the window the logical screen is drawn over, in window pixels
+    fn viewport(&self) -> (Point2<u32>, Vector2<u32>) {
+        scale_transform::viewport(self.logical_size, self.current_size, self.scaling_mode)
+    }
+
+    /// Grabs the texture we'll render this frame into. For a surface this is the next texture in the
+    /// swapchain, which we'll need to present afterward, so we return it along with the view. For an
+    /// offscreen target it's always the same texture, and there's nothing to present.
+    ///
+    /// If the surface has been lost or gone out of date (the window was minimized, or moved to
+    /// another display) this reconfigures it and tries once more.
+    fn current_frame(&self) -> crate::Result<(Option<wgpu::SurfaceTexture>, wgpu::TextureView)> {
+        match &self.target {
+            RenderTarget::Surface(surface) => {
+                let tex = match surface.get_current_texture() {
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        self.configure_surface();
+                        surface.get_current_texture()?
+                    }
+                    result => result?
+                };
+                let view = tex.texture.create_view(&Default::default());
+                Ok((Some(tex), view))
+            }
+            RenderTarget::Offscreen { texture, .. } => Ok((None, texture.view.clone()))
+        }
     }
 
     /// Queues a call to the id shader, which outputs sprite ids to id_texture
-    fn call_id_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &Buffer, layers: &[u32]) {
+    fn call_id_shader(&self, encoder: &mut wgpu::CommandEncoder, instances: &[Instances]) {
         let target = self.id_texture.texture.create_view(&wgpu::TextureViewDescriptor {
-            format: Some(TextureFormat::R32Uint),
+            format: Some(TextureFormat::Rg32Uint),
             ..Default::default()
         });
 
-        self.call_shader(encoder, instances, layers, &self.id_pipeline, &target);
+        let depth = self.id_depth_texture.as_ref().unwrap_or(&self.depth_texture);
+        self.call_shader(encoder, instances, &self.id_pipeline, None, None, PassTarget { color: &target, depth: &depth.view, downscale: self.id_downscale, viewport: self.viewport() });
     }
 
     /// We can only copy textures to buffers that are multiples of `COPY_BYTES_PER_ROW_ALIGNMENT`
-    /// bytes wide. This is probably 64 pixels, so, we need to round up the size of the buffer to
-    /// accommodate that width. For a texture `x` pixels wide, this returns the required row width, which is at least `x`:
+    /// bytes wide. This is probably 32 pixels of the id texture (8 bytes per pixel), so, we need to
+    /// round up the size of the buffer to accommodate that width. For a texture `x` pixels wide,
+    /// this returns the required row width, which is at least `x`:
     fn id_buffer_width(x: u32) -> u32 {
-        let pixels_per_slice = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT / 4; // The texture is u32s, so 4 bytes per pixel
+        Self::padded_width(x, ID_TEXEL_SIZE)
+    }
+
+    /// Like `id_buffer_width`, for any texture with `bytes` bytes per pixel
+    fn padded_width(x: u32, bytes: u32) -> u32 {
+        let pixels_per_slice = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT / bytes;
         let slices_per_row = x as f32 / pixels_per_slice as f32; // Figure out how many of those slices per row
         slices_per_row.ceil() as u32 * pixels_per_slice // Round that up and multiply back to pixels
     }
 
-    /// Create a buffer we can copy the id texture into. Thus is likely to be wider than the original
-    /// texture, see `id_buffer_width`.
+    /// How many bytes a pixel of a texture takes up
+    fn pixel_size(texture: &Texture) -> u32 {
+        texture.format().block_copy_size(None).unwrap_or(4)
+    }
+
+    /// Create a buffer we can copy the id texture (or the offscreen texture) into. Thus is likely
+    /// to be wider than the original texture, see `id_buffer_width`.
     fn create_id_buffer(device: &Device, id_texture: &Texture) -> Buffer {
-        let bpr = Self::id_buffer_width(id_texture.width()) * 4;
         device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: (bpr * id_texture.height()).into(),
+            size: Self::readback_size(id_texture),
             usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
             mapped_at_creation: false,
         })
     }
 
-    /// Queues reading the id texture (target of the id shader) into the id buffer.
-    fn read_id_texture(&self, encoder: &mut wgpu::CommandEncoder) {
-        let size = self.id_texture.size;
+    /// How big a buffer has to be to copy all of a texture into, with its rows padded out
+    fn readback_size(texture: &Texture) -> u64 {
+        let bytes = Self::pixel_size(texture);
+        (Self::padded_width(texture.width(), bytes) * bytes * texture.height()).into()
+    }
+
+    /// Queues reading part of the id texture (target of the id shader) into a buffer: the id
+    /// buffer, or one of the staging buffers for `redraw_with_latest_ids`.
+    fn read_id_texture(&self, encoder: &mut wgpu::CommandEncoder, buffer: &Buffer, layout: IdLayout) {
+        Self::read_texture(encoder, &self.id_texture.texture, buffer, layout.origin, layout.size)
+    }
 
+    /// Queues copying a rectangle of a texture into a buffer created by `create_id_buffer`: the id
+    /// texture, the offscreen render target, or a frame being captured.
+    fn read_texture(encoder: &mut wgpu::CommandEncoder, texture: &Texture, buffer: &Buffer, origin: Point2<u32>, size: Vector2<u32>) {
+        let bytes = Self::pixel_size(texture);
         let src = TexelCopyTextureInfo {
-            texture: &self.id_texture.texture,
+            texture,
             mip_level: 0,
-            origin: Default::default(),
+            origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
             aspect: Default::default(),
         };
         let dest = wgpu::TexelCopyBufferInfo {
-            buffer: &self.id_buffer,
+            buffer,
             layout: TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(Self::id_buffer_width(size.x) * 4),
+                bytes_per_row: Some(Self::padded_width(size.x, bytes) * bytes),
                 rows_per_image: Some(size.y),
             },
         };
@@ -533,92 +1163,583 @@ impl<'a> GpuWrapper<'a> {
         });
     }
 
+    /// Adds a (probably png-encoded) image as a new spritesheet layer, returning the layer. This
+    /// panics if the image can't be decoded; see `try_add_texture`.
     pub fn add_texture(&mut self, bytes: &[u8], label: Option<&str>) -> u32 {
-        let spritesheet = crate::texture::Texture::from_bytes(&self.device, &self.queue, bytes, label).unwrap();
-        self.spritesheets.push(spritesheet);
-        self.spritesheets.len() as u32 - 1
+        self.try_add_texture(bytes, label).unwrap()
     }
 
+    /// Every spritesheet is a layer of the same texture array, so there's nowhere for the label
+    /// to go anymore; it's ignored.
+    pub fn try_add_texture(&mut self, bytes: &[u8], _label: Option<&str>) -> crate::Result<u32> {
+        let img = image::load_from_memory(bytes)?.to_rgba8();
+        self.add_spritesheet(&img)
+    }
+
+    /// Adds raw RGBA data, `width` pixels wide, as a new spritesheet layer. This panics if the
+    /// data isn't a whole number of rows; see `try_add_texture_from_array`.
     pub fn add_texture_from_array(&mut self, bytes: Vec<u8>, width: u32, label: Option<&str>) -> u32 {
-        let spritesheet = crate::texture::Texture::from_array(&self.device, &self.queue, bytes, width, label).unwrap();
-        self.spritesheets.push(spritesheet);
-        self.spritesheets.len() as u32 - 1
+        self.try_add_texture_from_array(bytes, width, label).unwrap()
     }
 
-    /// Sort the given sprite iterator by z and put it into an instance buffer, returning
-    /// the buffer and vec of layers (so we know how many / which draw calls to make).
-    /// If the iterator contains no sprites, return None
-    fn set_sprites<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> (Buffer, Vec<u32>) {
-        let mut sprites: Vec<_> = sprites.into_iter().collect();
+    pub fn try_add_texture_from_array(&mut self, bytes: Vec<u8>, width: u32, _label: Option<&str>) -> crate::Result<u32> {
+        let img = crate::texture::Texture::rgba_image(bytes, width)?;
+        self.add_spritesheet(&img)
+    }
+
+    /// Write an image into a new spritesheet layer, keeping a copy of its alpha for hit testing
+    fn add_spritesheet(&mut self, img: &RgbaImage) -> crate::Result<u32> {
+        self.check_new_layer(img.dimensions().into())?;
+        let layer = self.new_layer(img.dimensions().into());
+        self.spritesheet_array.write_image(&self.queue, layer, (0, 0).into(), img);
+        self.alpha_masks.push(AlphaMask::from_image(img));
+        Ok(layer)
+    }
+
+    /// Whether the spritesheet array can take one more layer of this size without going over the
+    /// device's limits, which wgpu would panic over
+    fn check_new_layer(&self, size: Vector2<u32>) -> crate::Result<()> {
+        let limits = self.device.limits();
+        if size.x > limits.max_texture_dimension_2d || size.y > limits.max_texture_dimension_2d {
+            return Err(Error::TextureTooBig(size.x, size.y, limits.max_texture_dimension_2d))
+        }
+        if self.spritesheet_sizes.len() as u32 >= limits.max_texture_array_layers {
+            return Err(Error::TooManyLayers(limits.max_texture_array_layers))
+        }
+        Ok(())
+    }
+
+    /// Something to find which sprite is at a point on the CPU, without an id buffer; see `HitTester`
+    pub fn hit_tester(&self) -> HitTester<'_> {
+        HitTester::with_masks(self.logical_size, &self.alpha_masks)
+    }
+
+    /// Adds a (probably png-encoded) image to the texture atlas, rather than giving it a layer of
+    /// its own like `add_texture` does. Small images get packed together onto shared pages, so
+    /// they can all be drawn without switching textures. Returns where the image ended up; see
+    /// `AtlasRegion`. This panics if the image can't be decoded; see `try_add_image`.
+    pub fn add_image(&mut self, bytes: &[u8]) -> AtlasRegion {
+        self.try_add_image(bytes).unwrap()
+    }
 
-        if !sprites.is_empty() {
-            // Prep sprites by sorting them
-            sprites.sort_by(|a, b| {
-                let (a, b) = (a.as_ref(), b.as_ref());
-                if a.z == b.z {
-                    b.layer.cmp(&a.layer)
-                } else {
-                    b.z.total_cmp(&a.z)
+    pub fn try_add_image(&mut self, bytes: &[u8]) -> crate::Result<AtlasRegion> {
+        let img = image::load_from_memory(bytes)?.to_rgba8();
+        self.add_atlas_image(&img)
+    }
+
+    /// Adds raw RGBA data to the texture atlas, like `add_texture_from_array` but see `add_image`
+    pub fn add_image_from_array(&mut self, bytes: Vec<u8>, width: u32) -> AtlasRegion {
+        self.try_add_image_from_array(bytes, width).unwrap()
+    }
+
+    pub fn try_add_image_from_array(&mut self, bytes: Vec<u8>, width: u32) -> crate::Result<AtlasRegion> {
+        let img = crate::texture::Texture::rgba_image(bytes, width)?;
+        self.add_atlas_image(&img)
+    }
+
+    fn add_atlas_image(&mut self, img: &RgbaImage) -> crate::Result<AtlasRegion> {
+        let size = img.dimensions().into();
+        if !self.atlas.fits(size) {
+            // It'll need a new layer, either for a new page or all to itself
+            self.check_new_layer(size)?;
+        }
+
+        Ok(match self.atlas.pack(size) {
+            Some((page, origin)) => {
+                // This might be the first thing on a new page, so we need a layer for it (which
+                // starts out transparent)
+                if page == self.atlas_layers.len() {
+                    let layer = self.new_layer((ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE).into());
+                    self.alpha_masks.push(AlphaMask::from_image(&RgbaImage::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE)));
+                    self.atlas_layers.push(layer);
                 }
+
+                // Only the part we're writing goes to the GPU
+                let layer = self.atlas_layers[page];
+                self.spritesheet_array.write_image(&self.queue, layer, origin, img);
+                self.alpha_masks[layer as usize].write(origin, img);
+                AtlasRegion { layer, origin, size }
+            }
+            None => {
+                // Too big to share a page, so it gets a whole layer to itself
+                AtlasRegion { layer: self.add_spritesheet(img)?, origin: (0, 0).into(), size }
+            }
+        })
+    }
+
+    /// Make a `RetroDisplay`, with a new spritesheet layer for its picture. This fails if the
+    /// device can't run compute shaders, which is the case on WebGL.
+    pub fn create_retro_display(&mut self) -> crate::Result<RetroDisplay> {
+        let limits = self.device.limits();
+        if limits.max_compute_workgroups_per_dimension == 0 || limits.max_storage_textures_per_shader_stage == 0 {
+            return Err(Error::NoComputeShaders)
+        }
+
+        let blank = RgbaImage::from_pixel(RETRO_DISPLAY_SIZE.x, RETRO_DISPLAY_SIZE.y, image::Rgba([0, 0, 0, 0xff]));
+        let layer = self.add_spritesheet(&blank)?;
+        Ok(RetroDisplay::new(&self.device, layer))
+    }
+
+    /// Run a retro display's compute pass, so its layer shows what's in its memory now. Call this
+    /// once a frame (or whenever its memory changes) before drawing its sprite.
+    pub fn update_retro_display(&self, display: &mut RetroDisplay) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        display.encode(&self.queue, &mut encoder);
+
+        // Then into its layer of the array, which is what actually gets drawn
+        Self::copy_to_layer(&mut encoder, &display.texture, &self.spritesheet_array, display.layer());
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Update a retro display and draw it on its own, filling the logical screen
+    pub fn redraw_retro_display(&self, display: &mut RetroDisplay) -> crate::Result<()> {
+        self.update_retro_display(display);
+        self.try_redraw([display.sprite()])
+    }
+
+    /// Make a `RenderLayer`: a new, transparent spritesheet layer of the given size, which sprites
+    /// can be drawn into with `draw_to_layer`. The CPU never sees what's drawn there, so
+    /// `hit_tester` counts the whole rectangle of any sprite drawn from it (the id buffer still
+    /// goes by what's actually there). This panics if the layer is bigger than the device allows,
+    /// or there are too many layers already; see `try_create_render_layer`.
+    pub fn create_render_layer(&mut self, size: impl Into<Vector2<u32>>) -> RenderLayer {
+        self.try_create_render_layer(size).unwrap()
+    }
+
+    pub fn try_create_render_layer(&mut self, size: impl Into<Vector2<u32>>) -> crate::Result<RenderLayer> {
+        let size = size.into().map(|n| n.max(1));
+        self.check_new_layer(size)?;
+        let layer = self.new_layer(size);
+        self.alpha_masks.push(AlphaMask::solid(size));
+
+        let blit = self.blit_pipeline.get_or_insert_with(|| RenderLayer::blit_pipeline(&self.device));
+        Ok(RenderLayer::new(&self.device, layer, size, self.format, blit, &self.sampler))
+    }
+
+    /// Draw some sprites into a render layer, replacing whatever was there before. It's like a
+    /// little screen of its own, the size of the layer, with no letterboxing or post-processing
+    /// (see `RenderLayer::drawing_context`). Sprites drawn from the layer itself see what it had
+    /// before this.
+    pub fn draw_to_layer<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, target: &RenderLayer, sprites: I) {
+        self.draw_batches_to_layer(target, &[], sprites)
+    }
+
+    /// Like `draw_to_layer`, but with batches, see `redraw_batches`
+    pub fn draw_batches_to_layer<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, target: &RenderLayer, batches: &[&SpriteBatch], sprites: I) {
+        let Some(blit) = &self.blit_pipeline else { return };
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        // The layer fills its own "window" exactly. The next redraw writes the real transform
+        // back before it's submitted.
+        let runs = self.set_sprites(sprites);
+        let size = target.size();
+        let instance_buffer = self.instance_buffer.borrow();
+        let instances = Self::instances(batches, &instance_buffer, &runs);
+        self.bind_for_render(scale_transform::transform(size, size, ScalingMode::Stretch), &instances);
+        self.call_shader(&mut encoder, &instances, &self.render_pipeline, Some(&self.blend_pipelines), None, PassTarget {
+            color: &target.canvas.view,
+            depth: &target.depth.view,
+            downscale: 1,
+            viewport: (Point2::new(0, 0), size)
+        });
+
+        // Then from the canvas into an RGBA copy, and from there into the array, which is what
+        // actually gets drawn
+        let sheet = &target.sheet;
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render layer"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &sheet.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: LoadOp::Clear(Color::TRANSPARENT), store: StoreOp::Store },
+                })],
+                ..Default::default()
             });
+            rpass.set_pipeline(blit);
+            rpass.set_bind_group(0, &target.blit_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        Self::copy_to_layer(&mut encoder, sheet, &self.spritesheet_array, target.layer());
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Add a material: a custom fragment shader for sprites drawn with it (see
+    /// `Sprite::with_material`), instead of the usual one that just samples the spritesheet and
+    /// multiplies by the tint. `wgsl` supplies
+    /// `fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32>`, which gets the color the
+    /// usual shader would have drawn and returns the one to draw instead (alpha 0 draws nothing).
+    /// `in` has the sprite's `tex_coord`, `tint`, `layer` and `rect` (its rectangle on the
+    /// spritesheet, in texels), and where on the sprite this pixel is, `uv`. It can read other
+    /// texels of the sprite with `sample_sprite(in, offset)`, `offset` being in texels.
+    ///
+    /// If it has uniforms, it declares them at `@group(1) @binding(0)`; `uniforms` are their
+    /// starting bytes, and how big they are. Like any shader, this panics if the WGSL doesn't
+    /// compile. The id buffer runs the material too, so pixels it makes transparent (alpha 0) can't
+    /// be clicked on, but `HitTester` can't run WGSL, and still goes by the spritesheet's alpha.
+    /// ```no_run
+    /// # use bananagraph::{GpuWrapper, Sprite};
+    /// # fn f(wrapper: &mut GpuWrapper, sprite: Sprite) {
+    /// let gray = wrapper.add_material("
+    ///     fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+    ///         return vec4f(vec3f(dot(color.rgb, vec3f(0.299, 0.587, 0.114))), color.a);
+    ///     }
+    /// ", &[]);
+    /// wrapper.redraw([sprite.with_material(gray)]);
+    /// # }
+    /// ```
+    pub fn add_material(&mut self, wgsl: &str, uniforms: &[u8]) -> MaterialId {
+        let shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("material"),
+            source: wgpu::ShaderSource::Wgsl(Material::source(wgsl).into()),
+        });
+        let layout = Material::bind_group_layout(&self.device);
+
+        let pipelines = std::iter::once(None).chain(BlendMode::ALL.map(Some))
+            .map(|blend| Self::create_render_pipeline(&self.device, self.vertex_buffer_layout.clone(), &shader, self.format, blend, Some(&layout)))
+            .collect();
+        let id_pipeline = Self::create_id_pipeline(&self.device, self.vertex_buffer_layout.clone(), &shader, Some(&layout));
+        self.materials.push(Material::new(&self.device, &self.queue, pipelines, id_pipeline, uniforms));
+        MaterialId(self.materials.len() as u32 - 1)
+    }
+
+    /// Write new uniforms for a material. Anything past the size it was added with is cut off.
+    pub fn set_material_uniforms(&self, id: MaterialId, uniforms: &[u8]) {
+        if let Some(material) = self.materials.get(id.0 as usize) {
+            material.set_uniforms(&self.queue, uniforms)
+        }
+    }
+
+    /// Add a built-in effect to the end of the post-processing chain, which runs over the whole
+    /// frame after the sprites are drawn (but not over the id buffer). Effects run in the order
+    /// they're added, each one seeing what the last one did. Returns a handle to change its
+    /// settings with `set_effect`, turn it on and off with `set_effect_enabled`, or remove it.
+    pub fn add_effect(&mut self, effect: Effect) -> EffectId {
+        self.post_chain.get_mut().add_effect(&self.device, effect)
+    }
+
+    /// Add a pass of our own to the end of the post-processing chain. `wgsl` is a fragment
+    /// shader, `fs_main`, which takes a `PostInput` (with the `uv` of the pixel being drawn, 0 to 1
+    /// across the window) and returns a color. It can read the frame so far with
+    /// `sample_input(uv)`, or `input_texture` and `input_sampler` directly, and if it has
+    /// uniforms it declares them at `@group(0) @binding(2)`; `uniforms` are their starting bytes,
+    /// and how big they are. Like any shader, this panics if the WGSL doesn't compile.
+    pub fn add_custom_effect(&mut self, wgsl: &str, uniforms: &[u8]) -> EffectId {
+        self.post_chain.get_mut().add_custom(&self.device, &self.queue, wgsl, uniforms)
+    }
+
+    /// Change a built-in effect's settings. It has to stay the same kind of effect: changing a
+    /// `Shake` into a `Flash` does nothing.
+    pub fn set_effect(&self, id: EffectId, effect: Effect) {
+        self.post_chain.borrow_mut().set_effect(id, effect)
+    }
+
+    /// Write new uniforms for a custom effect (see `add_custom_effect`). Anything past the size
+    /// it was added with is cut off.
+    pub fn set_effect_uniforms(&self, id: EffectId, uniforms: &[u8]) {
+        self.post_chain.borrow_mut().set_uniforms(&self.queue, id, uniforms)
+    }
+
+    /// Turn a single effect on or off, keeping its place in the chain
+    pub fn set_effect_enabled(&self, id: EffectId, enabled: bool) {
+        self.post_chain.borrow_mut().set_enabled(id, enabled)
+    }
+
+    pub fn effect_enabled(&self, id: EffectId) -> bool {
+        self.post_chain.borrow().is_enabled(id)
+    }
+
+    pub fn remove_effect(&mut self, id: EffectId) {
+        self.post_chain.get_mut().remove(id)
+    }
+
+    /// Turn the whole post-processing chain on or off. When it's off (or every effect in it is)
+    /// sprites are drawn straight into the frame, without the extra passes.
+    pub fn set_post_processing(&self, enabled: bool) {
+        self.post_chain.borrow_mut().enabled = enabled
+    }
+
+    pub fn post_processing(&self) -> bool {
+        self.post_chain.borrow().enabled
+    }
+
+    /// Sort the given sprite iterator by z and convert them to `RawSprite`s, returning those
+    /// and the vec of blend modes (so we know how many / which draw calls to make).
+    pub(crate) fn sort_sprites<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(sprites: I) -> (Vec<RawSprite>, Vec<Run>) {
+        let mut sprites: Vec<_> = sprites.into_iter().collect();
+        sprites.sort_by(|a, b| draw_order(a.as_ref(), b.as_ref()));
+
+        let runs = sprites.iter().map(|s| Run { blend: s.as_ref().blend, material: s.as_ref().material }).collect();
+        let raw_sprites = sprites.into_iter().map(|s| s.as_ref().into_raw()).collect();
+        (raw_sprites, runs)
+    }
 
-            let layers: Vec<u32> = sprites.iter().map(|s| s.as_ref().layer).collect();
+    /// Sort the given sprites and write them into the instance buffer (growing it if we have to),
+    /// returning the runs of blend modes and materials to draw them with
+    fn set_sprites<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> Vec<Run> {
+        let (raw_sprites, runs) = Self::sort_sprites(sprites);
 
+        if raw_sprites.len() * size_of::<RawSprite>() > self.instance_buffer.borrow().size() as usize {
+            *self.instance_buffer.borrow_mut() = Self::create_instance_buffer(&self.device, raw_sprites.len().next_power_of_two());
+        }
+
+        if !raw_sprites.is_empty() {
+            self.queue.write_buffer(&self.instance_buffer.borrow(), 0, bytemuck::cast_slice(&raw_sprites));
+        }
+        runs
+    }
+
+    /// Upload a list of sprites to the GPU once, to be drawn every frame with `redraw_batches`
+    /// without sorting and copying them again. Good for things that don't change often, like
+    /// the terrain of a map.
+    pub fn create_batch<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> SpriteBatch {
+        let (raw_sprites, runs) = Self::sort_sprites(sprites);
+        self.create_batch_from_raw(bytemuck::cast_slice(&raw_sprites), runs)
+    }
+
+    /// Replace the sprites in a batch, reusing its buffer if they fit
+    pub fn update_batch<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batch: &mut SpriteBatch, sprites: I) {
+        let (raw_sprites, runs) = Self::sort_sprites(sprites);
+        let bytes: &[u8] = bytemuck::cast_slice(&raw_sprites);
 
-            self.bind_for_render();
-            let instance_buffer = self.create_instance_buffer(sprites);
-            (instance_buffer, layers)
+        if bytes.len() > batch.buffer.size() as usize {
+            *batch = SpriteBatch { transform: batch.transform, ..self.create_batch_from_raw(bytes, runs) };
         } else {
-            let instance_buffer = self.create_instance_buffer(sprites);
-            (instance_buffer, vec![])
+            self.queue.write_buffer(&batch.buffer, 0, bytes);
+            batch.runs = runs;
         }
     }
 
-    /// Redraws the display, but does not populate the id buffer, returning how long it took to do that.
-    pub fn redraw<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) {
-        let tex = self.surface.get_current_texture().unwrap();
+    fn create_batch_from_raw(&self, bytes: &[u8], runs: Vec<Run>) -> SpriteBatch {
+        let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("sprite batch"),
+            contents: bytes,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        SpriteBatch { buffer, runs, transform: Matrix3::identity() }
+    }
+
+    /// Encodes the render and / or id passes for some batches plus some loose sprites, submits
+    /// them, and presents the frame if we rendered one (which we don't while minimized). If `ids`
+    /// is given, the id pass is drawn and copied into it.
+    fn draw<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I, render: bool, ids: Option<&Buffer>) -> crate::Result<()> {
+        let frame = (render && !self.minimized).then(|| self.current_frame()).transpose()?;
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
 
-        let (instance_buffer, layers) = self.set_sprites(sprites);
-        self.bind_for_render();
-        self.call_render_shader(&mut encoder, &instance_buffer, &layers, &tex);
+        let runs = self.set_sprites(sprites);
+        let instance_buffer = self.instance_buffer.borrow();
+        let instances = Self::instances(batches, &instance_buffer, &runs);
+        self.bind_for_render(scale_transform::transform(self.logical_size, self.current_size, self.scaling_mode), &instances);
+
+        if let Some((_, view)) = &frame {
+            let mut post_chain = self.post_chain.borrow_mut();
+            if post_chain.active() {
+                let scene = post_chain.scene_target(&self.device, self.current_size);
+                self.call_render_shader(&mut encoder, &instances, &scene);
+                post_chain.encode(&self.device, &self.queue, &mut encoder, &self.sampler, view, self.logical_pixel_uv());
+            } else {
+                self.call_render_shader(&mut encoder, &instances, view);
+            }
+        }
+
+        if let Some(buffer) = ids {
+            let layout = self.next_id_layout();
+            self.call_id_shader(&mut encoder, &instances);
+            self.read_id_texture(&mut encoder, buffer, layout);
+            self.id_layout.set(layout);
+        }
+
+        // If someone wants a copy of this frame, that has to be queued before it's presented
+        let captured = match &frame {
+            Some((tex, _)) if self.capture.borrow().wants_frame() => self.frame_texture(tex.as_ref()).filter(|texture| texture.usage().contains(TextureUsages::COPY_SRC)),
+            _ => None
+        }.map(|texture| {
+            let size = Vector2::new(texture.width(), texture.height());
+            let buffer = self.capture.borrow_mut().buffer(&self.device, Self::readback_size(texture));
+            Self::read_texture(&mut encoder, texture, &buffer, Point2::new(0, 0), size);
+            (buffer, size, texture.format())
+        });
 
         self.queue.submit(Some(encoder.finish()));
-        tex.present()
+        if let Some((Some(tex), _)) = frame { tex.present() }
+
+        if let Some((buffer, size, format)) = captured {
+            let bytes: Vec<u8> = self.read_buffer(&buffer, buffer.size())?;
+            let row_bytes = Self::padded_width(size.x, 4) as usize * 4;
+            if let Some(image) = capture::image_from_rows(&bytes, row_bytes, size.x, size.y, format) {
+                self.capture.borrow_mut().push(image)
+            }
+        }
+        Ok(())
+    }
+
+    /// The texture a frame from `current_frame` is being drawn into
+    fn frame_texture<'t>(&'t self, surface_texture: Option<&'t wgpu::SurfaceTexture>) -> Option<&'t Texture> {
+        match (surface_texture, &self.target) {
+            (Some(tex), _) => Some(&tex.texture),
+            (None, RenderTarget::Offscreen { texture, .. }) => Some(&texture.texture),
+            _ => None
+        }
+    }
+
+    /// Whether frames can be read back at all: the surface has to allow copying out of its
+    /// textures, and be in a format we know how to turn into an image
+    pub fn can_capture(&self) -> bool {
+        match &self.target {
+            RenderTarget::Surface(surface) => surface.get_capabilities(&self.adapter).usages.contains(TextureUsages::COPY_SRC) && capture::can_capture(self.format),
+            RenderTarget::Offscreen { .. } => capture::can_capture(self.format)
+        }
+    }
+
+    /// Keep a copy of the next frame drawn, to get with `take_screenshot` afterward (and save
+    /// with `encode_png`, say). This fails if frames can't be read back; see `can_capture`.
+    pub fn request_screenshot(&self) -> crate::Result<()> {
+        if !self.can_capture() {
+            return Err(Error::NoCapture)
+        }
+        self.capture.borrow_mut().screenshot_requested = true;
+        Ok(())
+    }
+
+    /// The frame asked for with `request_screenshot`, once it's been drawn
+    pub fn take_screenshot(&self) -> Option<RgbaImage> {
+        self.capture.borrow_mut().screenshot.take()
+    }
+
+    /// Start keeping a copy of every frame drawn, up to the last `max_frames` of them, to make a
+    /// GIF or APNG of with `recording` or `stop_recording`. This replaces any recording already
+    /// going. Every frame has to be read back from the GPU, which slows drawing down (a lot, at
+    /// big window sizes), so this is for debugging rather than leaving on.
+    pub fn start_recording(&self, max_frames: usize) -> crate::Result<()> {
+        if !self.can_capture() {
+            return Err(Error::NoCapture)
+        }
+        self.capture.borrow_mut().recording = Some(Recording::new(max_frames));
+        Ok(())
+    }
+
+    /// A copy of the frames recorded so far, leaving the recording going
+    pub fn recording(&self) -> Option<Recording> {
+        self.capture.borrow().recording.clone()
+    }
+
+    pub fn stop_recording(&self) -> Option<Recording> {
+        self.capture.borrow_mut().recording.take()
+    }
+
+    /// Redraws the display, but does not populate the id buffer. If the frame can't be drawn
+    /// (the surface is gone and won't come back) it's skipped, with a warning logged; see
+    /// `try_redraw`.
+    pub fn redraw<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) {
+        if let Err(err) = self.try_redraw(sprites) {
+            log::warn!("Skipped a frame: {}", err)
+        }
+    }
+
+    pub fn try_redraw<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> crate::Result<()> {
+        self.draw(&[], sprites, true, None)
+    }
+
+    /// Redraws the display with some previously-created batches (see `create_batch`) as well as
+    /// a list of sprites for this frame. Sprites in all of them are sorted against each other by
+    /// the depth buffer, but translucent ones are only drawn back to front within each batch.
+    pub fn redraw_batches<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) {
+        if let Err(err) = self.try_redraw_batches(batches, sprites) {
+            log::warn!("Skipped a frame: {}", err)
+        }
+    }
+
+    pub fn try_redraw_batches<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) -> crate::Result<()> {
+        self.draw(batches, sprites, true, None)
     }
 
     /// Redraws the display and populates the id buffer, returning the buffer. This is marginally faster than
     /// calling both `redraw` and `redraw_ids` individually since it only encodes the sprites once, but, it
     /// only encodes the sprites once, so the same sprites will be used for both pipelines.
+    /// This only returns errors reading the ids back: if the frame can't be drawn, it's skipped
+    /// (and logged) like `redraw` does, and the ids are whatever's left from the last frame. See
+    /// `try_redraw_with_ids`.
     pub fn redraw_with_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> Result<IdBuffer, wgpu::BufferAsyncError> {
-        let tex = self.surface.get_current_texture().unwrap();
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        self.recover_ids(self.try_redraw_with_ids(sprites))
+    }
 
-        let (instance_buffer, layers) = self.set_sprites(sprites);
-        self.bind_for_render();
+    pub fn try_redraw_with_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> crate::Result<IdBuffer> {
+        self.try_redraw_batches_with_ids(&[], sprites)
+    }
 
-        self.call_render_shader(&mut encoder, &instance_buffer, &layers, &tex);
-        self.call_id_shader(&mut encoder, &instance_buffer, &layers);
-        self.read_id_texture(&mut encoder);
+    /// Like `redraw_with_ids`, but with batches, see `redraw_batches`
+    pub fn redraw_batches_with_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) -> Result<IdBuffer, wgpu::BufferAsyncError> {
+        self.recover_ids(self.try_redraw_batches_with_ids(batches, sprites))
+    }
+
+    pub fn try_redraw_batches_with_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) -> crate::Result<IdBuffer> {
+        self.draw(batches, sprites, true, Some(&self.id_buffer))?;
+        self.try_get_sprite_ids()
+    }
 
+    /// Redraws the display and starts reading the id buffer back, but doesn't wait for it like
+    /// `redraw_with_ids` does. Instead this returns the newest id buffer that's made it back from
+    /// the GPU, which is usually the previous frame's (or this one's, if the GPU was quick about it),
+    /// and `None` until the first one arrives.
+    /// Being a frame behind doesn't matter for telling what's under the mouse, and it means the
+    /// CPU and GPU don't have to stop and wait for each other every frame.
+    pub fn redraw_with_latest_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> crate::Result<Option<IdBuffer>> {
+        self.redraw_batches_with_latest_ids(&[], sprites)
+    }
+
+    /// Like `redraw_with_latest_ids`, but with batches, see `redraw_batches`
+    pub fn redraw_batches_with_latest_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) -> crate::Result<Option<IdBuffer>> {
+        let mut readback = self.id_readback.borrow_mut();
+
+        // If the GPU's still busy with all the staging buffers, this frame's ids are just skipped
+        let slot = readback.free_slot(&self.device, self.id_buffer.size());
+        self.draw(batches, sprites, true, slot.map(|slot| readback.buffer(slot)))?;
+        if let Some(slot) = slot {
+            let layout = self.id_layout.get();
+            readback.start(slot, layout, Self::id_buffer_width(layout.size.x));
+        }
+
+        // Let any finished copies call back, without waiting for the ones that haven't
+        self.device.poll(wgpu::Maintain::Poll);
+        Ok(readback.collect())
+    }
+
+    /// Renders the sprites into an image rather than the screen, along with the id buffer. This only
+    /// works for a wrapper created with `headless`; one targeting a window will return `None`.
+    pub fn render_to_image<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> Option<crate::Result<(RgbaImage, IdBuffer)>> {
+        self.render_batches_to_image(&[], sprites)
+    }
+
+    /// Like `render_to_image`, but with batches, see `redraw_batches`
+    pub fn render_batches_to_image<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, batches: &[&SpriteBatch], sprites: I) -> Option<crate::Result<(RgbaImage, IdBuffer)>> {
+        let RenderTarget::Offscreen { texture, buffer } = &self.target else { return None };
+        let ids = self.try_redraw_batches_with_ids(batches, sprites);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        Self::read_texture(&mut encoder, &texture.texture, buffer, Point2::new(0, 0), texture.size);
         self.queue.submit(Some(encoder.finish()));
-        tex.present();
-        self.get_sprite_ids()
+
+        let result = ids.and_then(|ids| {
+            let bytes: Vec<u8> = self.read_buffer(buffer, buffer.size())?;
+            let (width, height) = texture.size.into();
+            let padded_width = Self::padded_width(width, 4) as usize * 4;
+
+            // The buffer rows are padded out to the copy alignment, so cut each row back down
+            let image = capture::image_from_rows(&bytes, padded_width, width, height, texture.texture.format()).ok_or(Error::NoCapture)?;
+            Ok((image, ids))
+        });
+        Some(result)
     }
 
     /// Populates the id buffer; does not redraw the display or run the render shader. Returns the id buffer
     /// (exactly as get_sprite_ids would)
     pub fn redraw_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> Result<IdBuffer, wgpu::BufferAsyncError> {
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-
-        let (instance_buffer, layers) = self.set_sprites(sprites);
-        self.bind_for_render();
-
-        self.call_id_shader(&mut encoder, &instance_buffer, &layers);
-        self.read_id_texture(&mut encoder);
+        self.recover_ids(self.try_redraw_ids(sprites))
+    }
 
-        self.queue.submit(Some(encoder.finish()));
-        self.get_sprite_ids()
+    pub fn try_redraw_ids<I: IntoIterator<Item=S>,S: AsRef<Sprite>>(&self, sprites: I) -> crate::Result<IdBuffer> {
+        self.draw(&[], sprites, false, Some(&self.id_buffer))?;
+        self.try_get_sprite_ids()
     }
 
     /// Returns the buffer of which sprite id is topmost for a given pixel, and the width of
@@ -629,16 +1750,45 @@ impl<'a> GpuWrapper<'a> {
     /// - Pixels with an alpha of 0 do not count as part of a sprite
     /// - Pixels not covered by a sprite have an id of 0, so, 0 is not a valid sprite id
     pub fn get_sprite_ids(&self) -> Result<IdBuffer, wgpu::BufferAsyncError> {
-        let capturable = self.id_buffer.clone();
-        let result: Option<Result<Vec<u32>, wgpu::BufferAsyncError>> = None;
+        let layout = self.id_layout.get();
+        let width = Self::id_buffer_width(layout.size.x);
+        let data = self.read_buffer(&self.id_buffer, (width * ID_TEXEL_SIZE * layout.size.y) as u64)?;
+        Ok(IdBuffer::with_layout(data, width, layout))
+    }
+
+    /// The same as `get_sprite_ids`, but returning a bananagraph `Error` like the other `try_`
+    /// functions do
+    pub fn try_get_sprite_ids(&self) -> crate::Result<IdBuffer> {
+        Ok(self.get_sprite_ids()?)
+    }
+
+    /// The id functions from before `Error` only return errors reading the ids back. Anything else
+    /// (the surface timing out, say) skips the frame like `redraw` does, and hands back the ids
+    /// still in the buffer from the last frame that was drawn.
+    fn recover_ids(&self, result: crate::Result<IdBuffer>) -> Result<IdBuffer, wgpu::BufferAsyncError> {
+        match result {
+            Ok(ids) => Ok(ids),
+            Err(Error::Readback(err)) => Err(err),
+            Err(err) => {
+                log::warn!("Skipped a frame: {}", err);
+                self.get_sprite_ids()
+            }
+        }
+    }
+
+    /// Maps the first `size` bytes of a buffer (which we've presumably just queued a copy into),
+    /// waits for that to finish, and returns them
+    fn read_buffer<T: bytemuck::Pod + Send>(&self, buffer: &Arc<Buffer>, size: u64) -> Result<Vec<T>, wgpu::BufferAsyncError> {
+        let capturable = buffer.clone();
+        let result: Option<Result<Vec<T>, wgpu::BufferAsyncError>> = None;
         let m = Arc::new(std::sync::Mutex::new(result));
         let m2 = m.clone();
 
-        self.id_buffer.slice(..).map_async(wgpu::MapMode::Read, move|result| {
+        buffer.slice(..size).map_async(wgpu::MapMode::Read, move|result| {
             if result.is_ok() {
-                let ids: Vec<u32> = bytemuck::cast_slice(&capturable.slice(..).get_mapped_range()).to_vec();
+                let data: Vec<T> = bytemuck::cast_slice(&capturable.slice(..size).get_mapped_range()).to_vec();
                 capturable.unmap();
-                let _ = m.lock().unwrap().insert(Ok(ids));
+                let _ = m.lock().unwrap().insert(Ok(data));
             } else {
                 let _ = m.lock().unwrap().insert(Err(result.err().unwrap()));
             }
@@ -651,7 +1801,217 @@ impl<'a> GpuWrapper<'a> {
             self.device.poll(wgpu::Maintain::wait()).panic_on_timeout()
         };
 
-        let screen_width = self.id_texture.size.x;
-        result.map(|data| IdBuffer::new(data, Self::id_buffer_width(screen_width), screen_width))
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_to_image() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        let red: Vec<u8> = [0xff, 0, 0, 0xff].repeat(4 * 4);
+        wrapper.add_texture_from_array(red, 4, None);
+
+        let sprite = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5)).with_id(7);
+        let (image, ids) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+
+        assert_eq!(image.dimensions(), (8, 8));
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(6, 6).0, [0, 0, 0, 0xff]);
+        assert_eq!(ids[cgmath::Point2::new(1.0, 1.0)], 7);
+        assert_eq!(ids[cgmath::Point2::new(6.0, 6.0)], 0);
+        assert_eq!(ids.hit((1.0, 2.0)).unwrap().texel((4, 4)), Point2::new(1, 2));
+    }
+
+    #[test]
+    fn test_latest_ids() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        let sprite = Sprite::new((0, 0), (4, 4)).with_id(7);
+
+        // Once the first frame's ids are back, the next frame gets them (or its own)
+        wrapper.redraw_with_latest_ids([sprite]).unwrap();
+        wrapper.device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+        let ids = wrapper.redraw_with_latest_ids([sprite]).unwrap().unwrap();
+        assert_eq!(ids[Point2::new(6.0, 6.0)], 7);
+
+        // Half resolution, reading back only the corner around (1, 1)
+        wrapper.set_id_downscale(2);
+        wrapper.set_id_region_around((1.0, 1.0), 1);
+        let ids = wrapper.redraw_ids([sprite]).unwrap();
+        assert_eq!(ids[Point2::new(1.0, 1.0)], 7);
+        assert_eq!(ids[Point2::new(3.0, 3.0)], 7);
+        assert_eq!(ids[Point2::new(6.0, 6.0)], 0);
+        assert!(!ids.covers((6.0, 6.0)));
+    }
+
+    #[test]
+    fn test_hit_tester_matches_ids() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((16, 16).into(), (16, 16).into()));
+        // A texture whose right half is transparent
+        let half = (0..8 * 8).flat_map(|n| if n % 8 < 4 { [0xff; 4] } else { [0; 4] }).collect();
+        wrapper.add_texture_from_array(half, 8, None);
+
+        let dc = crate::DrawingContext::new((16.0, 16.0));
+        let square = Sprite::new((0, 0), (8, 8));
+        let sprites = [
+            dc.place(square.with_id(1).with_z(0.5), (0.0, 0.0)),
+            dc.place(square.with_id(2).with_z(0.2), (2.0, 2.0)),
+            dc.place(square.with_id(3).with_z(0.2).with_blend(BlendMode::Alpha), (4.0, 4.0)),
+            dc.place(square.with_id(0).with_z(0.1), (0.0, 0.0)),
+            dc.place(square.with_id(4).with_z(0.5), (8.0, 8.0)).scale((2.0, 1.0)),
+        ];
+
+        let (_, ids) = wrapper.render_to_image(sprites).unwrap().unwrap();
+        let tester = wrapper.hit_tester();
+        for (x, y) in (0..16).flat_map(|y| (0..16).map(move |x| (x as f64, y as f64))) {
+            assert_eq!(tester.sprite_at(sprites, (x + 0.5, y + 0.5)), ids[Point2::new(x, y)], "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_blending() {
+        let mut snapshot = crate::Snapshot::new((32, 32));
+        snapshot.add_texture_from_array([0xff, 0xff, 0xff, 0xff].repeat(16 * 16), 16, None);
+        let dc = crate::DrawingContext::new((32.0, 32.0));
+        let square = Sprite::new((0, 0), (16, 16));
+
+        // Translucent sprites are drawn over opaque ones, and each other, back to front, but still
+        // hidden by opaque sprites in front of them
+        snapshot.assert_matches(concat!(env!("CARGO_MANIFEST_DIR"), "/src/snapshots/blending.png"), [
+            dc.place(square.with_tint((0.0, 0.0, 1.0, 0.5)).with_blend(BlendMode::Alpha).with_z(0.2), (8.0, 8.0)),
+            dc.place(square.with_tint((0.0, 1.0, 0.0, 0.5)).with_blend(BlendMode::Additive).with_z(0.3), (12.0, 12.0)),
+            dc.place(square.with_tint((1.0, 0.0, 0.0, 1.0)).with_z(0.5), (0.0, 0.0)),
+            dc.place(square.with_tint((1.0, 1.0, 0.0, 1.0)).with_z(0.1), (16.0, 16.0)),
+            dc.place(square.with_tint((0.5, 0.5, 0.5, 1.0)).with_blend(BlendMode::Multiply).with_z(0.05), (16.0, 0.0)),
+        ], 1);
+    }
+
+    #[test]
+    fn test_add_image() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        let red = wrapper.add_image_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4);
+        let blue = wrapper.add_image_from_array([0, 0, 0xff, 0xff].repeat(4 * 4), 4);
+        let huge = wrapper.add_image_from_array([0, 0xff, 0, 0xff].repeat(1024 * 4), 1024);
+
+        assert_eq!(red.layer, blue.layer);
+        assert_eq!(red.origin, (0, 0).into());
+        assert_eq!(blue.origin, (5, 0).into());
+        assert_eq!(huge, AtlasRegion { layer: red.layer + 1, origin: (0, 0).into(), size: (1024, 4).into() });
+
+        let dc = crate::DrawingContext::new((8.0, 8.0));
+        let (image, _) = wrapper.render_to_image([dc.place(red, (0.0, 0.0)), dc.place(blue, (4.0, 4.0))]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(3, 3).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(4, 4).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(4, 3).0, [0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_array_growth() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        let colors = [[0xff, 0, 0, 0xff], [0, 0xff, 0, 0xff], [0, 0, 0xff, 0xff], [0xff, 0xff, 0, 0xff], [0, 0xff, 0xff, 0xff]];
+        for color in colors {
+            wrapper.add_texture_from_array(color.repeat(4 * 4), 4, None);
+        }
+
+        // Room for five sheets is room for eight, and a bigger one makes the layers bigger
+        assert_eq!(wrapper.spritesheet_array.texture.depth_or_array_layers(), 8);
+        wrapper.add_texture_from_array([0xff; 4].repeat(16 * 16), 16, None);
+        assert_eq!(wrapper.spritesheet_array.size, (16, 16).into());
+        assert_eq!(wrapper.spritesheet_array.texture.depth_or_array_layers(), 8);
+
+        // Every sheet made it across each time
+        for (layer, color) in colors.iter().enumerate() {
+            let (image, _) = wrapper.render_to_image([Sprite::new((0, 0), (4, 4)).with_layer(layer as u32)]).unwrap().unwrap();
+            assert_eq!(&image.get_pixel(0, 0).0, color);
+        }
+    }
+
+    #[test]
+    fn test_minimized() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.follow_window_size = true;
+
+        // Nothing drops to zero, and drawing is skipped rather than panicking
+        let resize = wrapper.handle_window_resize((0, 0).into(), 1.0);
+        assert_eq!(resize.new_size, (8, 8).into());
+        assert_eq!(wrapper.logical_size, (8, 8).into());
+        assert!(wrapper.try_redraw([Sprite::new((0, 0), (1, 1))]).is_ok());
+
+        // And coming back works like any other resize
+        wrapper.handle_window_resize((4, 6).into(), 2.0);
+        assert_eq!(wrapper.window_size(), (2, 3).into());
+        let (image, _) = wrapper.render_to_image([Sprite::new((0, 0), (1, 1))]).unwrap().unwrap();
+        assert_eq!(image.dimensions(), (4, 6));
+    }
+
+    #[test]
+    fn test_bad_images() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        assert!(matches!(wrapper.try_add_texture(b"not a png", None), Err(Error::Image(_))));
+        assert!(matches!(wrapper.try_add_texture_from_array(vec![0; 12], 0, None), Err(Error::ImageSize(12, 0))));
+        assert!(matches!(wrapper.try_add_image_from_array(vec![0; 12], 2), Err(Error::ImageSize(12, 2))));
+
+        // Bigger than the device allows, whether it's a layer of its own or going in the atlas
+        let max = wrapper.device.limits().max_texture_dimension_2d;
+        assert!(matches!(wrapper.try_add_texture_from_array(vec![0; (max as usize + 1) * 4], max + 1, None), Err(Error::TextureTooBig(..))));
+        assert!(matches!(wrapper.try_add_image_from_array(vec![0; (max as usize + 1) * 4], max + 1), Err(Error::TextureTooBig(..))));
+        assert!(matches!(wrapper.try_create_render_layer((1, max + 1)), Err(Error::TextureTooBig(..))));
+
+        // And nothing was added by the failures
+        assert_eq!(wrapper.try_add_texture_from_array(vec![0; 16], 2, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        let red = wrapper.add_image_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4);
+        let dc = crate::DrawingContext::new((8.0, 8.0));
+
+        // A batch made before another texture is added still draws from the right place
+        let mut batch = wrapper.create_batch([dc.place(red, (0.0, 0.0)).with_z(0.5)]);
+        let blue = wrapper.add_image_from_array([0, 0, 0xff, 0xff].repeat(64 * 64), 64);
+        let blue = blue.sprite((0, 0), (4, 4));
+        let (image, _) = wrapper.render_batches_to_image(&[&batch], [dc.place(blue, (2.0, 2.0)).with_z(0.1)]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(7, 7).0, [0, 0, 0, 0xff]);
+
+        // More sprites than the instance buffer started with, some behind the batch
+        let many: Vec<_> = (0..INITIAL_INSTANCE_CAPACITY * 2).map(|n| dc.place(blue, ((n % 2) as f32 * 4.0, 4.0)).with_z(0.9)).collect();
+        wrapper.update_batch(&mut batch, [dc.place(red, (0.0, 0.0)).with_z(0.5), dc.place(red, (0.0, 4.0)).with_z(0.5)]);
+        assert_eq!(batch.len(), 2);
+        let (image, _) = wrapper.render_batches_to_image(&[&batch], many).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 5).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 5).0, [0, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_letterbox() {
+        // A 4x4 screen in a 12x8 window, scaled 2x with 2 pixel bars on the sides
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((12, 8).into(), (4, 4).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        wrapper.scaling_mode = ScalingMode::Integer;
+        wrapper.letterbox_color = (0.0, 0.0, 1.0, 1.0).into();
+
+        // This hangs off the left edge of the screen, but doesn't draw into the bar
+        let dc = crate::DrawingContext::new((4.0, 4.0));
+        let sprite = dc.place(Sprite::new((0, 0), (2, 2)).with_id(3), (-1.0, 0.0));
+        let (image, ids) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(11, 7).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(2, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 1).0, [0, 0, 0, 0xff]);
+        assert_eq!(ids[Point2::new(1.0, 1.0)], 0);
+        assert_eq!(ids[Point2::new(3.0, 1.0)], 3);
+        assert_eq!(ids.at_logical((0.5, 0.5)), 3);
+        assert_eq!(ids.at_logical((-0.5, 0.5)), 0);
+
+        let logical = wrapper.window_to_logical((4.0, 4.0));
+        assert!((logical.x - 1.0).abs() < 0.001 && (logical.y - 2.0).abs() < 0.001);
     }
 }
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use cgmath::{Point2, SquareMatrix, Vector2, Vector3};
+use image::RgbaImage;
+use crate::event_handler::MousePos;
+use crate::id_buffer::Hit;
+use crate::sprite::{Sprite, SpriteId};
+
+/// Which texels of a spritesheet layer aren't fully transparent, one bit each, so hit testing can
+/// ignore the transparent parts of sprites the way the id shader does
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AlphaMask {
+    size: Vector2<u32>,
+    bits: Vec<u64>
+}
+
+impl AlphaMask {
+    /// A mask where everything is transparent, for an empty atlas page
+    pub fn new(size: Vector2<u32>) -> Self {
+        Self { size, bits: vec![0; (size.x as usize * size.y as usize).div_ceil(64)] }
+    }
+
+    /// A mask where everything is opaque, for a layer we don't know the contents of
+    pub fn solid(size: Vector2<u32>) -> Self {
+        Self { size, bits: vec![u64::MAX; (size.x as usize * size.y as usize).div_ceil(64)] }
+    }
+
+    pub fn from_image(img: &RgbaImage) -> Self {
+        let mut mask = Self::new(img.dimensions().into());
+        mask.write((0, 0).into(), img);
+        mask
+    }
+
+    /// Copy an image's alpha into part of the mask, with its top-left corner at `origin`; the
+    /// same as `Texture::write_image` does to the texture
+    pub fn write(&mut self, origin: Point2<u32>, img: &RgbaImage) {
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let (x, y) = (origin.x + x, origin.y + y);
+            if x < self.size.x && y < self.size.y {
+                let i = x as usize + y as usize * self.size.x as usize;
+                if pixel.0[3] == 0 {
+                    self.bits[i / 64] &= !(1 << (i % 64))
+                } else {
+                    self.bits[i / 64] |= 1 << (i % 64)
+                }
+            }
+        }
+    }
+
+    /// Whether the texel at this point isn't transparent. Anything off the edge is.
+    pub fn opaque(&self, x: u32, y: u32) -> bool {
+        if x >= self.size.x || y >= self.size.y {
+            return false
+        }
+        let i = x as usize + y as usize * self.size.x as usize;
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+}
+
+/// Finds which sprite is under a point on the CPU, without drawing an id buffer: each sprite's
+/// transform is inverted to see whether the point lands inside it, and (if we have a copy of its
+/// spritesheet's alpha) whether it lands on a transparent texel. The answers are the same as the
+/// id buffer would give: 0 means nothing's there, sprites with id 0 are ignored, and when sprites
+/// overlap the one with the lowest z wins (ties going the same way `GpuWrapper::redraw` breaks
+/// them).
+///
+/// `GpuWrapper::hit_tester` makes one that knows every spritesheet's alpha. One made with `new`
+/// only knows about layers added to it with `with_alpha`, and counts the whole rectangle of
+/// sprites on any other layer, so it works without a GPU at all (in tests, say).
+///
+/// This only sees the sprites it's given, so sprites drawn from batches need passing in too. And it
+/// only knows the spritesheets' alpha, so it doesn't know what a sprite's material (see
+/// `Sprite::with_material`) does to it: pixels a material hides still count as hits.
+pub struct HitTester<'a> {
+    /// The logical screen size, which sprite transforms are relative to
+    logical_size: Vector2<u32>,
+
+    /// One per spritesheet layer, as far as we know them
+    masks: Cow<'a, [AlphaMask]>
+}
+
+impl HitTester<'static> {
+    pub fn new(logical_size: impl Into<Vector2<u32>>) -> Self {
+        Self { logical_size: logical_size.into(), masks: Cow::Owned(vec![]) }
+    }
+}
+
+impl<'a> HitTester<'a> {
+    pub(crate) fn with_masks(logical_size: Vector2<u32>, masks: &'a [AlphaMask]) -> Self {
+        Self { logical_size, masks: Cow::Borrowed(masks) }
+    }
+
+    /// Use this image's alpha for the next spritesheet layer (the first call is layer 0, and so on)
+    pub fn with_alpha(mut self, img: &RgbaImage) -> Self {
+        self.masks.to_mut().push(AlphaMask::from_image(img));
+        self
+    }
+
+    /// The id of the topmost sprite at a point in logical coordinates, or 0 if there isn't one;
+    /// like `IdBuffer::at_logical`
+    pub fn sprite_at<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, sprites: I, pt: impl Into<Point2<f64>>) -> SpriteId {
+        self.hit(sprites, pt).map_or(0, |hit| hit.id)
+    }
+
+    /// The id of the sprite under the mouse, if there is one; like `IdBuffer::entity`
+    pub fn entity<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, sprites: I, mouse_pos: MousePos) -> Option<SpriteId> {
+        self.hit(sprites, mouse_pos.logical).map(|hit| hit.id)
+    }
+
+    /// The topmost sprite at a point in logical coordinates, and where on it the point is; like
+    /// `IdBuffer::hit_logical`
+    pub fn hit<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, sprites: I, pt: impl Into<Point2<f64>>) -> Option<Hit> {
+        self.stack(sprites, pt).into_iter().next()
+    }
+
+    /// Every sprite at a point in logical coordinates, topmost first. This is what the id buffer
+    /// can't tell you: what's underneath the top one.
+    pub fn stack<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, sprites: I, pt: impl Into<Point2<f64>>) -> Vec<Hit> {
+        let pt = pt.into();
+
+        // Nothing's drawn outside the logical screen (see `GpuWrapper::call_shader`)
+        if pt.x < 0.0 || pt.y < 0.0 || pt.x >= self.logical_size.x as f64 || pt.y >= self.logical_size.y as f64 {
+            return vec![]
+        }
+        let screen = Vector3::new((pt.x / self.logical_size.x as f64) as f32, (pt.y / self.logical_size.y as f64) as f32, 1.0);
+
+        // Go through in the order they'd be drawn, so that among sprites with the same z, the
+        // first one drawn (which is the one the depth test keeps) comes first
+        let mut sprites: Vec<Sprite> = sprites.into_iter().map(|s| *s.as_ref()).collect();
+        sprites.sort_by(crate::gpu_wrapper::draw_order);
+
+        let mut hits: Vec<(f32, Hit)> = sprites.iter().filter_map(|sprite| Some((sprite.z, self.test(sprite, screen)?))).collect();
+        hits.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        hits.into_iter().map(|(_, hit)| hit).collect()
+    }
+
+    /// Whether a point (in screen units: 0 to 1 across the logical screen) is on a sprite
+    fn test(&self, sprite: &Sprite, screen: Vector3<f32>) -> Option<Hit> {
+        // Unclickable sprites, ones outside the depth range, and ones tinted invisible never make it
+        // into the id buffer
+        if sprite.id == 0 || !(0.0..1.0).contains(&sprite.z) || sprite.tint.w == 0.0 {
+            return None
+        }
+
+        // The transform takes the unit square to the screen, so undo it and see if we're in the square
+        let local = sprite.transform.invert()? * screen;
+        if !(0.0..1.0).contains(&local.x) || !(0.0..1.0).contains(&local.y) {
+            return None
+        }
+
+        if let Some(mask) = self.masks.get(sprite.layer as usize) {
+            let texel_x = sprite.origin.x + (local.x * sprite.size.x as f32) as u32;
+            let texel_y = sprite.origin.y + (local.y * sprite.size.y as f32) as u32;
+            if !mask.opaque(texel_x, texel_y) {
+                return None
+            }
+        }
+
+        Some(Hit { id: sprite.id, uv: Point2::new(local.x, local.y) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlendMode, DrawingContext};
+
+    #[test]
+    fn test_hits() {
+        let dc = DrawingContext::new((16.0, 16.0));
+        let square = Sprite::new((0, 0), (8, 8));
+        let sprites = [
+            dc.place(square.with_id(1).with_z(0.5), (0.0, 0.0)),
+            dc.place(square.with_id(2).with_z(0.2), (4.0, 4.0)),
+            dc.place(square.with_id(0).with_z(0.1), (0.0, 0.0)),
+        ];
+        let tester = HitTester::new((16, 16));
+
+        assert_eq!(tester.sprite_at(sprites, (1.0, 1.0)), 1);
+        assert_eq!(tester.sprite_at(sprites, (6.0, 6.0)), 2);
+        assert_eq!(tester.sprite_at(sprites, (14.0, 14.0)), 0);
+        assert_eq!(tester.sprite_at(sprites, (-1.0, 1.0)), 0);
+        assert_eq!(tester.stack(sprites, (6.0, 6.0)).iter().map(|hit| hit.id).collect::<Vec<_>>(), vec![2, 1]);
+
+        let hit = tester.hit(sprites, (6.0, 7.0)).unwrap();
+        assert_eq!(hit.texel((8, 8)), Point2::new(2, 3));
+    }
+
+    #[test]
+    fn test_ties() {
+        // Same z: the higher layer wins, then whichever came first; and translucent sprites are
+        // drawn after opaque ones, so lose to them
+        let dc = DrawingContext::new((8.0, 8.0));
+        let square = Sprite::new((0, 0), (8, 8));
+        let tester = HitTester::new((8, 8));
+
+        assert_eq!(tester.sprite_at([square.with_id(1), square.with_id(2).with_layer(1)], (1.0, 1.0)), 2);
+        assert_eq!(tester.sprite_at([square.with_id(1), square.with_id(2)], (1.0, 1.0)), 1);
+        assert_eq!(tester.sprite_at([dc.place(square.with_id(1).with_blend(BlendMode::Alpha), (0.0, 0.0)), square.with_id(2)], (1.0, 1.0)), 2);
+    }
+
+    #[test]
+    fn test_alpha() {
+        // Only the left half of the texture is opaque
+        let img = RgbaImage::from_fn(4, 4, |x, _| image::Rgba([0xff, 0xff, 0xff, if x < 2 { 0xff } else { 0 }]));
+        let tester = HitTester::new((4, 4)).with_alpha(&img);
+        let sprite = Sprite::new((0, 0), (4, 4)).with_id(1);
+
+        assert_eq!(tester.sprite_at([sprite], (1.5, 1.5)), 1);
+        assert_eq!(tester.sprite_at([sprite], (2.5, 1.5)), 0);
+
+        // Layers we don't have alpha for count as solid
+        assert_eq!(tester.sprite_at([sprite.with_layer(1)], (2.5, 1.5)), 1);
+    }
+}
@@ -1,22 +1,198 @@
+use std::collections::BTreeSet;
 use std::ops::Index;
-use cgmath::Point2;
+use cgmath::{Point2, Vector2};
+use image::{Rgba, RgbaImage};
+use crate::event_handler::MousePos;
+use crate::scale_transform::{self, ScalingMode};
 use crate::sprite::SpriteId;
 
+#[derive(Clone)]
 pub struct IdBuffer {
-    data: Vec<SpriteId>,
+    /// For each texel, the id and where on the sprite it was hit (see `Hit`), packed into 16 bits
+    /// per coordinate the way `pack2x16unorm` does it
+    data: Vec<[u32; 2]>,
+
+    /// The width of each row of `data`, which is probably padded out past the part we read back
     width: u32,
-    screen_width: u32
+
+    layout: IdLayout
+}
+
+/// What's under a point in the id buffer: which sprite, and where on it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub id: SpriteId,
+
+    /// Where on the sprite the point is, from (0, 0) at the top left corner of its source rectangle
+    /// to (1, 1) at the bottom right, however it's been transformed on the screen
+    pub uv: Point2<f32>
+}
+
+impl Hit {
+    /// Which texel of the sprite the point is on, for a sprite this size (the `Sprite::size`)
+    pub fn texel(&self, size: impl Into<Vector2<u32>>) -> Point2<u32> {
+        let size = size.into();
+        Point2::new(
+            ((self.uv.x * size.x as f32) as u32).min(size.x.saturating_sub(1)),
+            ((self.uv.y * size.y as f32) as u32).min(size.y.saturating_sub(1))
+        )
+    }
+
+    fn unpack([id, uv]: [u32; 2]) -> Option<Self> {
+        let unorm = |n: u32| (n & 0xffff) as f32 / 65535.0;
+        (id != 0).then(|| Hit { id, uv: Point2::new(unorm(uv), unorm(uv >> 16)) })
+    }
+}
+
+/// What part of the window an id buffer covers, and at what resolution. Normally that's the whole
+/// window at full resolution, but the id pass can be drawn smaller (see `GpuWrapper::set_id_downscale`)
+/// and only part of it read back (see `GpuWrapper::set_id_region`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct IdLayout {
+    /// The part of the id texture that was read back, in id texels
+    pub origin: Point2<u32>,
+    pub size: Vector2<u32>,
+
+    /// Window pixels per id texel, in each direction
+    pub downscale: u32,
+
+    /// The size of the window in physical pixels
+    pub window_size: Vector2<u32>,
+
+    /// How the logical screen was scaled into the window, for lookups by logical coordinate
+    pub logical_size: Vector2<u32>,
+    pub scaling_mode: ScalingMode
+}
+
+impl IdLayout {
+    /// The whole of a window this big, at full resolution
+    pub fn full(window_size: Vector2<u32>) -> Self {
+        Self {
+            origin: (0, 0).into(),
+            size: window_size,
+            downscale: 1,
+            window_size,
+            logical_size: window_size,
+            scaling_mode: ScalingMode::Stretch
+        }
+    }
 }
 
 impl IdBuffer {
     pub fn new(data: Vec<SpriteId>, width: u32, screen_width: u32) -> Self {
-        Self { data, width, screen_width }
+        let height = data.len() as u32 / width.max(1);
+        let data = data.into_iter().map(|id| [id, 0]).collect();
+        Self { data, width, layout: IdLayout::full((screen_width, height).into()) }
+    }
+
+    pub(crate) fn with_layout(data: Vec<[u32; 2]>, width: u32, layout: IdLayout) -> Self {
+        Self { data, width, layout }
+    }
+
+    /// The id of the sprite under the mouse, if there is one (0 means there's no sprite there, or
+    /// the sprite is unclickable)
+    pub fn entity(&self, mouse_pos: MousePos) -> Option<SpriteId> {
+        Some(self[mouse_pos]).filter(|id| *id != 0)
+    }
+
+    /// The id of the topmost sprite at a point in logical coordinates (rather than the physical
+    /// window pixels that indexing takes)
+    pub fn at_logical(&self, pt: impl Into<Point2<f64>>) -> SpriteId {
+        self[self.to_window(pt.into())]
     }
 
     /// Returns whether a given point is within the logical area of the screen
     /// (the total id buffer will be larger than this, probably)
     pub fn contains(&self, pt: Point2<u32>) -> bool {
-        pt.x < self.screen_width && pt.y < self.data.len() as u32 / self.width
+        pt.x < self.layout.window_size.x && pt.y < self.layout.window_size.y
+    }
+
+    /// The sprite at a point (in window pixels) and where on the sprite the point is, or `None` if
+    /// there's no sprite there
+    pub fn hit(&self, pt: impl Into<Point2<f64>>) -> Option<Hit> {
+        let i = self.texel(pt.into())?;
+        Hit::unpack(*self.data.get(i)?)
+    }
+
+    /// Like `hit`, but at a point in logical coordinates
+    pub fn hit_logical(&self, pt: impl Into<Point2<f64>>) -> Option<Hit> {
+        self.hit(self.to_window(pt.into()))
+    }
+
+    /// Every sprite that shows at least one pixel inside a rectangle, given by two opposite corners
+    /// in window pixels (like where a drag started and where the mouse is now). The ids are sorted,
+    /// and only counted once each. Sprites entirely hidden by others don't count, and nor does
+    /// anything outside the region that was read back.
+    pub fn ids_in_rect(&self, a: impl Into<Point2<f64>>, b: impl Into<Point2<f64>>) -> Vec<SpriteId> {
+        let (a, b) = (a.into(), b.into());
+        let layout = &self.layout;
+        let d = layout.downscale as f64;
+
+        // The texels the corners are in, clipped to what we read back, relative to its origin
+        let span = |a: f64, b: f64, origin: u32, size: u32| {
+            let (origin, size) = (origin as i64, size as i64);
+            let start = ((a.min(b) / d).floor() as i64).max(origin) - origin;
+            let end = ((a.max(b) / d).floor() as i64).min(origin + size - 1) - origin;
+            start..=end
+        };
+        let (xs, ys) = (span(a.x, b.x, layout.origin.x, layout.size.x), span(a.y, b.y, layout.origin.y, layout.size.y));
+
+        let ids: BTreeSet<SpriteId> = ys
+            .flat_map(|y| xs.clone().map(move |x| x as usize + y as usize * self.width as usize))
+            .filter_map(|i| self.data.get(i).map(|[id, _]| *id))
+            .filter(|id| *id != 0)
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    /// Like `ids_in_rect`, but with the corners in logical coordinates
+    pub fn ids_in_logical_rect(&self, a: impl Into<Point2<f64>>, b: impl Into<Point2<f64>>) -> Vec<SpriteId> {
+        self.ids_in_rect(self.to_window(a.into()), self.to_window(b.into()))
+    }
+
+    /// A picture of the buffer for debugging, one pixel per id texel read back: each id gets its
+    /// own (arbitrary, but always the same) color, and 0 is black
+    pub fn debug_image(&self) -> RgbaImage {
+        let size = self.layout.size;
+        RgbaImage::from_fn(size.x, size.y, |x, y| {
+            let id = self.data.get(x as usize + y as usize * self.width as usize).map_or(0, |[id, _]| *id);
+            Rgba(Self::debug_color(id))
+        })
+    }
+
+    /// Scramble the id's bits so neighboring ids get very different colors, and keep them bright
+    /// enough to tell from the black of no sprite
+    fn debug_color(id: SpriteId) -> [u8; 4] {
+        if id == 0 {
+            return [0, 0, 0, 0xff]
+        }
+        let [r, g, b, _] = id.wrapping_mul(0x9e3779b9).to_be_bytes();
+        [r | 0x40, g | 0x40, b | 0x40, 0xff]
+    }
+
+    fn to_window(&self, pt: Point2<f64>) -> Point2<f64> {
+        scale_transform::texture_to_window(self.layout.logical_size, self.layout.window_size, pt, self.layout.scaling_mode)
+    }
+
+    /// Whether a point (in window pixels) was actually read back, rather than being outside the
+    /// region we asked for. Points outside it always read as 0.
+    pub fn covers(&self, pt: impl Into<Point2<f64>>) -> bool {
+        self.texel(pt.into()).is_some()
+    }
+
+    /// Where in `data` a point in window pixels is, if it's in the part we read back
+    fn texel(&self, pt: Point2<f64>) -> Option<usize> {
+        let layout = &self.layout;
+        if pt.x < 0.0 || pt.y < 0.0 || pt.x >= layout.window_size.x as f64 || pt.y >= layout.window_size.y as f64 {
+            return None
+        }
+
+        let (x, y) = (pt.x as u32 / layout.downscale, pt.y as u32 / layout.downscale);
+        let (x, y) = (x.checked_sub(layout.origin.x)?, y.checked_sub(layout.origin.y)?);
+        if x >= layout.size.x || y >= layout.size.y {
+            return None
+        }
+        Some(x as usize + y as usize * self.width as usize)
     }
 }
 
@@ -24,11 +200,73 @@ impl Index<Point2<f64>> for IdBuffer {
     type Output = SpriteId;
 
     fn index(&self, index: Point2<f64>) -> &Self::Output {
-        let i = index.x as usize + index.y as usize * self.width as usize;
-        if i < self.data.len() {
-            &self.data[i]
-        } else {
-            &0
+        // Off the edges of the screen (like in the letterbox bars), or outside the region that was
+        // read back, there's nothing to hit
+        match self.texel(index).and_then(|i| self.data.get(i)) {
+            Some([id, _]) => id,
+            None => &0
         }
     }
-}
\ No newline at end of file
+}
+
+impl Index<MousePos> for IdBuffer {
+    type Output = SpriteId;
+
+    fn index(&self, index: MousePos) -> &Self::Output {
+        &self[index.physical]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_layout() {
+        // A 2x2 region, at (1, 1) in an id texture drawn at half the size of a 10x10 window
+        let layout = IdLayout { origin: (1, 1).into(), size: (2, 2).into(), downscale: 2, ..IdLayout::full((10, 10).into()) };
+        let ids = IdBuffer::with_layout([1, 2, 99, 3, 4, 99].map(|id| [id, 0]).to_vec(), 3, layout);
+
+        assert_eq!(ids[Point2::new(2.0, 2.0)], 1);
+        assert_eq!(ids[Point2::new(3.9, 3.9)], 1);
+        assert_eq!(ids[Point2::new(4.0, 2.0)], 2);
+        assert_eq!(ids[Point2::new(5.0, 5.0)], 4);
+
+        // Outside the region is 0, including the padding at the end of each row
+        assert_eq!(ids[Point2::new(1.0, 2.0)], 0);
+        assert_eq!(ids[Point2::new(6.0, 2.0)], 0);
+        assert!(ids.covers((5.0, 5.0)));
+        assert!(!ids.covers((6.0, 6.0)));
+        assert!(ids.contains((9, 9).into()));
+    }
+
+    #[test]
+    fn test_hits() {
+        // A 4x3 buffer: sprite 5 in the top left, 6 across the bottom, rows padded out to 5
+        let uv = |u: u32, v: u32| u | v << 16;
+        let data = vec![
+            [5, uv(0, 0)], [5, uv(65535, 0)], [0, 0], [0, 0], [99, 0],
+            [5, uv(0, 65535)], [5, uv(65535, 65535)], [0, 0], [0, 0], [99, 0],
+            [6, 0], [6, 0], [6, 0], [6, 0], [99, 0]
+        ];
+        let ids = IdBuffer::with_layout(data, 5, IdLayout::full((4, 3).into()));
+
+        let hit = ids.hit((1.5, 1.5)).unwrap();
+        assert_eq!((hit.id, hit.uv), (5, Point2::new(1.0, 1.0)));
+        assert_eq!(hit.texel((16, 16)), Point2::new(15, 15));
+        assert_eq!(ids.hit((0.0, 1.0)).unwrap().texel((16, 16)), Point2::new(0, 15));
+        assert_eq!(ids.hit((3.0, 0.0)), None);
+
+        assert_eq!(ids.ids_in_rect((3.5, 2.5), (1.0, 0.0)), vec![5, 6]);
+        assert_eq!(ids.ids_in_rect((2.0, 0.0), (3.0, 1.0)), vec![]);
+        assert_eq!(ids.ids_in_rect((-10.0, 2.0), (100.0, 100.0)), vec![6]);
+        assert_eq!(ids.ids_in_rect((-10.0, -10.0), (-1.0, -1.0)), vec![]);
+
+        // The debug image leaves out the padding, and the same id is always the same color
+        let image = ids.debug_image();
+        assert_eq!(image.dimensions(), (4, 3));
+        assert_eq!(image.get_pixel(2, 0).0, [0, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(1, 1));
+        assert_ne!(image.get_pixel(0, 0), image.get_pixel(0, 2));
+    }
+}
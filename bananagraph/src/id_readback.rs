@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex};
+use wgpu::{Buffer, BufferAsyncError, BufferUsages, Device};
+use crate::gpu_wrapper::ID_TEXEL_SIZE;
+use crate::id_buffer::{IdBuffer, IdLayout};
+
+/// How many id buffers can be on their way back from the GPU at once. Two is enough to always
+/// have one to copy into while the last frame's is being mapped; if the GPU falls further behind
+/// than that, we skip reading back frames until it catches up.
+const SLOTS: usize = 2;
+
+/// Where a staging buffer is in its trip back from the GPU
+enum SlotState {
+    /// Free to copy the next frame into
+    Idle,
+
+    /// A copy's been queued and we've asked to map it; waiting for the GPU
+    Mapping,
+
+    /// The GPU's done with it (or failed), and it's ready to read
+    Done(Result<(), BufferAsyncError>)
+}
+
+struct Slot {
+    buffer: Buffer,
+    state: Arc<Mutex<SlotState>>,
+
+    /// Which frame was copied into this, and what part of the id texture it was
+    frame: u64,
+    layout: IdLayout,
+    width: u32
+}
+
+/// Reads id buffers back from the GPU without waiting for them: each frame's ids are copied into
+/// one of a few staging buffers and mapped asynchronously, and we hand out the newest one that's
+/// finished, which is usually the one from the frame before.
+#[derive(Default)]
+pub(crate) struct IdReadback {
+    slots: Vec<Slot>,
+    frame: u64,
+
+    /// The newest id buffer that's come back, and which frame it's from
+    latest: Option<(u64, IdBuffer)>
+}
+
+impl IdReadback {
+    /// Find a staging buffer to copy this frame into, making one if there are fewer than `SLOTS`,
+    /// or `None` if they're all still busy. `size` is how many bytes a full id texture needs.
+    pub fn free_slot(&mut self, device: &Device, size: u64) -> Option<usize> {
+        let idle = self.slots.iter().position(|slot| matches!(*slot.state.lock().unwrap(), SlotState::Idle));
+        if idle.is_some() || self.slots.len() >= SLOTS {
+            return idle
+        }
+
+        self.slots.push(Slot {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("id readback buffer"),
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            state: Arc::new(Mutex::new(SlotState::Idle)),
+            frame: 0,
+            layout: IdLayout::full((0, 0).into()),
+            width: 0
+        });
+        Some(self.slots.len() - 1)
+    }
+
+    pub fn buffer(&self, slot: usize) -> &Buffer {
+        &self.slots[slot].buffer
+    }
+
+    /// Start mapping a slot, once the copy into it has been submitted. `width` is the padded width
+    /// of each row that was copied.
+    pub fn start(&mut self, slot: usize, layout: IdLayout, width: u32) {
+        self.frame += 1;
+        let slot = &mut self.slots[slot];
+        slot.frame = self.frame;
+        slot.layout = layout;
+        slot.width = width;
+
+        *slot.state.lock().unwrap() = SlotState::Mapping;
+        let state = slot.state.clone();
+        slot.buffer.slice(..Self::bytes(layout, width)).map_async(wgpu::MapMode::Read, move |result| {
+            *state.lock().unwrap() = SlotState::Done(result)
+        });
+    }
+
+    /// Read back any slots that have finished mapping, freeing them up again, and return the
+    /// newest id buffer we have (which may not be new)
+    pub fn collect(&mut self) -> Option<IdBuffer> {
+        for slot in self.slots.iter() {
+            let mut state = slot.state.lock().unwrap();
+            let SlotState::Done(result) = &*state else { continue };
+
+            let newer = self.latest.as_ref().is_none_or(|(frame, _)| slot.frame > *frame);
+            if result.is_ok() {
+                if newer {
+                    let range = slot.buffer.slice(..Self::bytes(slot.layout, slot.width)).get_mapped_range();
+                    let data = bytemuck::cast_slice(&range).to_vec();
+                    self.latest = Some((slot.frame, IdBuffer::with_layout(data, slot.width, slot.layout)));
+                }
+                slot.buffer.unmap();
+            }
+            *state = SlotState::Idle;
+        }
+
+        self.latest.as_ref().map(|(_, ids)| ids.clone())
+    }
+
+    /// Forget all the staging buffers, because the id texture they were sized for has changed.
+    /// Any still mapping just finish (or fail) on their own.
+    pub fn clear(&mut self) {
+        self.slots.clear()
+    }
+
+    /// How many bytes of a staging buffer a copy with this layout fills
+    fn bytes(layout: IdLayout, width: u32) -> u64 {
+        (width * ID_TEXEL_SIZE * layout.size.y) as u64
+    }
+}
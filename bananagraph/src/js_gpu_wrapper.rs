@@ -1,8 +1,11 @@
-use std::ops::{Deref, DerefMut, Index};
+use std::ops::{Deref, DerefMut};
+use cgmath::Point2;
 use std::time::Duration;
-use crate::{Click, Dir, ElementState, GpuWrapper, IdBuffer, MouseButton, WindowEventHandler};
+use crate::{Click, DragTracker, ElementState, GameLoop, GestureRecognizer, GpuWrapper, IdBuffer, Key, KeyEvent, KeyState, Modifiers, MouseButton, MouseMove, MousePos, Scroll, ScrollDelta, Touch, TouchPhase, WindowEventHandler, WindowHandle};
+use crate::window_handle::WindowCommand;
+use crate::touch::{handle_touch, update_gestures};
 use wasm_bindgen::prelude::wasm_bindgen;
-use crate::event_handler::KeyEvent;
+use wasm_bindgen::JsCast;
 
 /// We can't send a GpuWrapper to JS directly without it trying to generate stuff it can't generate
 /// so we need to wrap it in a bindgen'd type so we can tell bindgen to skip it. We also can't expose
@@ -16,7 +19,50 @@ pub struct JsGpuWrapper {
     pub(crate) handler: Box<dyn WindowEventHandler>,
 
     #[wasm_bindgen(skip)]
-    pub(crate) ids: Option<IdBuffer>
+    pub(crate) ids: Option<IdBuffer>,
+
+    /// The last place we saw the mouse, in physical canvas pixels, to pass to `redraw`
+    #[wasm_bindgen(skip)]
+    pub(crate) mouse_pos: Point2<f64>,
+
+    /// The modifier keys held as of the last keyboard or mouse event
+    #[wasm_bindgen(skip)]
+    pub(crate) modifiers: Modifiers,
+
+    #[wasm_bindgen(skip)]
+    pub(crate) drag: DragTracker,
+
+    #[wasm_bindgen(skip)]
+    pub(crate) gestures: GestureRecognizer,
+
+    /// The total of all the `dt`s we've been passed, which the gesture recognizer uses as its clock
+    #[wasm_bindgen(skip)]
+    pub(crate) clock: Duration,
+
+    /// How often to tick, from the handler
+    #[wasm_bindgen(skip)]
+    pub(crate) game_loop: GameLoop,
+
+    /// Our copy of the handle the handler uses to change the window; we carry out its requests
+    #[wasm_bindgen(skip)]
+    pub(crate) handle: WindowHandle,
+
+    /// The canvas we're drawing on, for the handle's fullscreen and cursor requests. We can't get
+    /// it back out of the surface, so it has to be given to us with `with_canvas`.
+    #[wasm_bindgen(skip)]
+    pub(crate) canvas: Option<web_sys::HtmlElement>,
+
+    /// Set when the handler asks to exit, after which we stop ticking and drawing
+    #[wasm_bindgen(skip)]
+    pub(crate) stopped: bool,
+
+    /// Set while the page is hidden, and then whether the next frame should pretend no time passed
+    /// (because it's the first one after the page came back)
+    #[wasm_bindgen(skip)]
+    pub(crate) suspended: bool,
+
+    #[wasm_bindgen(skip)]
+    pub(crate) skip_frame_time: bool
 }
 
 impl Deref for JsGpuWrapper {
@@ -34,91 +80,276 @@ impl DerefMut for JsGpuWrapper {
 }
 
 impl JsGpuWrapper {
-    pub fn new(wrapper: GpuWrapper<'static>, handler: impl WindowEventHandler + 'static) -> Self {
+    /// Wrap a handler to be driven by the browser. This calls the handler's `init` (after giving it
+    /// its `WindowHandle`, the same order the desktop window does it in), so don't call it yourself.
+    pub fn new(mut wrapper: GpuWrapper<'static>, mut handler: impl WindowEventHandler + 'static) -> Self {
+        let game_loop = handler.game_loop();
+        let handle = WindowHandle::new();
+        handler.window_handle(handle.clone());
+        handler.init(&mut wrapper);
         Self {
             wrapper,
             handler: Box::from(handler),
             ids: None,
+            mouse_pos: (-1.0, -1.0).into(),
+            modifiers: Modifiers::default(),
+            drag: DragTracker::new(),
+            gestures: GestureRecognizer::new(),
+            clock: Duration::ZERO,
+            game_loop,
+            handle,
+            canvas: None,
+            stopped: false,
+            suspended: false,
+            skip_frame_time: false
+        }
+    }
+
+    /// Tell the wrapper which canvas it's drawing on, so the handler's `WindowHandle` can make it
+    /// fullscreen and change its cursor
+    pub fn with_canvas(self, canvas: impl Into<web_sys::HtmlElement>) -> Self {
+        Self { canvas: Some(canvas.into()), ..self }
+    }
+
+    /// Do whatever the handler asked the window to do. This runs after every event, not just every
+    /// frame, because browsers only allow going fullscreen while handling a click or key.
+    fn run_window_commands(&mut self) {
+        let document = web_sys::window().and_then(|win| win.document());
+
+        for command in self.handle.take_commands() {
+            match command {
+                WindowCommand::SetFullscreen(true) => {
+                    if let Some(canvas) = &self.canvas {
+                        let _ = canvas.request_fullscreen();
+                    }
+                }
+                WindowCommand::SetFullscreen(false) => {
+                    if let Some(document) = &document {
+                        document.exit_fullscreen()
+                    }
+                }
+                WindowCommand::SetTitle(title) => {
+                    if let Some(document) = &document {
+                        document.set_title(&title)
+                    }
+                }
+                WindowCommand::SetCursor(cursor) => self.set_canvas_style("cursor", cursor.css_name()),
+                WindowCommand::SetCursorVisible(visible) => self.set_canvas_style("cursor", if visible { "default" } else { "none" }),
+                WindowCommand::Exit => self.stopped = true
+            }
+        }
+
+        // Fullscreen can also be left with the escape key, without us
+        if let Some(document) = &document {
+            self.handle.set_is_fullscreen(document.fullscreen_element().is_some())
+        }
+    }
+
+    /// Events come in CSS pixels, but `MousePos` (and the id buffer) want physical ones
+    fn to_physical(&self, x: f64, y: f64) -> Point2<f64> {
+        let scale = self.wrapper.scale_factor();
+        Point2::new(x * scale, y * scale)
+    }
+
+    fn set_canvas_style(&self, property: &str, value: &str) {
+        if let Some(canvas) = &self.canvas {
+            let _ = canvas.style().set_property(property, value);
         }
     }
 }
 
 #[wasm_bindgen]
 impl JsGpuWrapper {
-    /// Take an event type (mousedown, mouseup, mousemove) and a coord pair and
-    /// call the appropriate method on the gamestate (translate between js and windoweventhandler
-    /// mouse events).
-    pub fn mouse_event(&mut self, event_type: &str, x: f64, y: f64) {
-        let mouse_pos = (x, y).into();
-        let entity = match &self.ids {
-            None => None,
-            Some(buf) => {
-
-                let id = *buf.index((x, y).into());
-                if id == 0 {
-                    None
-                } else {
-                    Some(id)
-                }
-            }
+    /// Take a browser mouse event (mousedown, mouseup, mousemove, mouseenter, mouseleave) on the
+    /// canvas and call the appropriate methods on the handler (translate between js and
+    /// windoweventhandler mouse events).
+    pub fn mouse_event(&mut self, event: &web_sys::MouseEvent) {
+        self.mouse_pos = self.to_physical(event.offset_x() as f64, event.offset_y() as f64);
+        self.modifiers = mouse_modifiers(event);
+        let mouse_pos = MousePos::new(self.mouse_pos, &self.wrapper);
+        let entity = self.ids.as_ref().and_then(|buf| buf.entity(mouse_pos));
+        let modifiers = self.modifiers;
+
+        // The DOM numbers buttons in a different order than you'd think
+        let button = match event.button() {
+            0 => MouseButton::Left,
+            1 => MouseButton::Middle,
+            2 => MouseButton::Right,
+            3 => MouseButton::Back,
+            4 => MouseButton::Forward,
+            n => MouseButton::Other(n as u16)
         };
 
-        match event_type {
+        match event.type_().as_str() {
             "mousedown" => {
                 self.handler.click(Click {
-                    button: MouseButton::Left,
+                    button,
                     state: ElementState::Pressed,
                     mouse_pos,
-                    entity
-                })
+                    entity,
+                    modifiers
+                });
+                self.drag.press(button, mouse_pos, entity)
             }
             "mouseup" => {
                 self.handler.click(Click {
-                    button: MouseButton::Left,
+                    button,
                     state: ElementState::Released,
                     mouse_pos,
-                    entity
-                })
+                    entity,
+                    modifiers
+                });
+                if let Some(drag) = self.drag.release(button, mouse_pos, modifiers) {
+                    self.handler.drag(drag)
+                }
             }
             "mousemove" => {
-                // TODO
+                self.handler.mouse_move(MouseMove { mouse_pos, entity, modifiers });
+                if let Some(drag) = self.drag.motion(mouse_pos, modifiers) {
+                    self.handler.drag(drag)
+                }
             }
+            "mouseenter" => self.handler.mouse_enter(),
+            "mouseleave" => self.handler.mouse_leave(),
+            _ => {}
+        }
+        self.run_window_commands()
+    }
+
+    /// Take a browser wheel event on the canvas and send it to the handler as a scroll
+    pub fn wheel(&mut self, event: &web_sys::WheelEvent) {
+        self.mouse_pos = self.to_physical(event.offset_x() as f64, event.offset_y() as f64);
+        self.modifiers = mouse_modifiers(event);
+        let mouse_pos = MousePos::new(self.mouse_pos, &self.wrapper);
+        let entity = self.ids.as_ref().and_then(|buf| buf.entity(mouse_pos));
+
+        let delta = match event.delta_mode() {
+            web_sys::WheelEvent::DOM_DELTA_PIXEL => {
+                let scale = self.wrapper.scale_factor();
+                ScrollDelta::Pixels((event.delta_x() * scale, event.delta_y() * scale).into())
+            }
+            _ => ScrollDelta::Lines((event.delta_x(), event.delta_y()).into())
+        };
+        self.handler.scroll(Scroll { delta, mouse_pos, entity, modifiers: self.modifiers });
+        self.run_window_commands()
+    }
+
+    /// Take a browser touch event (touchstart, touchmove, touchend, touchcancel) on the canvas and
+    /// send each finger that changed to the handler. If the page calls preventDefault on these, the
+    /// browser won't also send fake mouse events for them.
+    pub fn touch_event(&mut self, event: &web_sys::TouchEvent) {
+        let phase = match event.type_().as_str() {
+            "touchstart" => TouchPhase::Start,
+            "touchmove" => TouchPhase::Move,
+            "touchend" => TouchPhase::End,
+            "touchcancel" => TouchPhase::Cancel,
+            _ => return
+        };
+
+        // Touches don't have an offset like mouse events do, so we need to know where the canvas is
+        let Some(canvas) = event.current_target().and_then(|t| t.dyn_into::<web_sys::Element>().ok()) else { return };
+        let rect = canvas.get_bounding_client_rect();
+
+        let changed = event.changed_touches();
+        for touch in (0..changed.length()).filter_map(|n| changed.get(n)) {
+            let position = MousePos::new(self.to_physical(touch.client_x() as f64 - rect.left(), touch.client_y() as f64 - rect.top()), &self.wrapper);
+            let entity = self.ids.as_ref().and_then(|buf| buf.entity(position));
+            let touch = Touch { id: touch.identifier() as u64, phase, position, entity };
+            handle_touch(self.handler.as_mut(), &mut self.gestures, touch, self.clock)
+        }
+        self.run_window_commands()
+    }
+
+    /// Call when the canvas changes size, with its new size in physical pixels (which is what the
+    /// canvas's width and height should be set to) and the page's devicePixelRatio
+    pub fn resize(&mut self, physical_width: u32, physical_height: u32, scale_factor: f64) {
+        if scale_factor != self.wrapper.scale_factor() {
+            self.handler.scale_factor_changed(scale_factor)
+        }
+
+        let resize = self.wrapper.handle_window_resize((physical_width, physical_height).into(), scale_factor);
+        self.handler.resize(resize);
+        self.run_window_commands()
+    }
+
+    /// Take a focus or blur event on the canvas, and tell the handler whether it has focus
+    pub fn focus_event(&mut self, event: &web_sys::FocusEvent) {
+        match event.type_().as_str() {
+            "focus" | "focusin" => self.handler.focus(true),
+            "blur" | "focusout" => self.handler.focus(false),
             _ => {}
         }
+        self.run_window_commands()
     }
 
-    pub fn key(&mut self, key: &str) {
-        to_banana_key(key).map(|ev| self.handler.key(ev));
+    /// Call on the document's visibilitychange event, to suspend the game while the tab is hidden
+    /// and resume it when it's back
+    pub fn visibility_change(&mut self) {
+        let hidden = web_sys::window().and_then(|win| win.document()).is_some_and(|doc| doc.hidden());
+        if hidden == self.suspended { return }
+        self.suspended = hidden;
+
+        if hidden {
+            self.handler.suspend()
+        } else {
+            // requestAnimationFrame stops while the tab's hidden, so the next dt will be the whole
+            // time we were away, which we don't want to catch up on
+            self.skip_frame_time = true;
+            self.handler.resume()
+        }
+        self.run_window_commands()
     }
 
+    /// Take a browser keyboard event (keydown or keyup) and send it to the handler
+    pub fn key(&mut self, event: &web_sys::KeyboardEvent) {
+        self.modifiers = Modifiers {
+            shift: event.shift_key(),
+            ctrl: event.ctrl_key(),
+            alt: event.alt_key(),
+            meta: event.meta_key()
+        };
+
+        let state = match (event.type_().as_str(), event.repeat()) {
+            ("keyup", _) => KeyState::Released,
+            (_, true) => KeyState::Repeat,
+            (_, false) => KeyState::Pressed
+        };
+
+        self.handler.key(KeyEvent {
+            logical: Key::from_name(&event.key()),
+            physical: event.code(),
+            state,
+            modifiers: self.modifiers
+        });
+        self.run_window_commands()
+    }
+
+    /// Run a frame: call this from requestAnimationFrame with the milliseconds since the last one.
+    /// This runs whatever ticks have come due and then renders, the same as the desktop window does.
     pub fn redraw(&mut self, dt: f64) {
-        let dt = Duration::from_millis(dt as u64);
-        // TODO normally we'd have some logic about exiting the game here, but, we're in a browser,
-        // so exiting the game just means closing the tab, which we have no control over.
-        self.handler.tick(dt);
-        self.ids = self.handler.redraw((0.0, 0.0).into(), &self.wrapper)
+        // We can't close the tab, so exiting the game just means stopping it
+        if self.stopped || self.suspended { return }
+
+        // Browsers give fractional milliseconds; keep them. Clocks shouldn't go backward, but if
+        // one does, call it no time at all.
+        let dt = if std::mem::take(&mut self.skip_frame_time) {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((dt / 1000.0).max(0.0))
+        };
+        let alpha = self.game_loop.frame(self.handler.as_mut(), dt);
+        self.clock += dt;
+        update_gestures(self.handler.as_mut(), &mut self.gestures, self.clock);
+        self.ids = self.handler.render(MousePos::new(self.mouse_pos, &self.wrapper), &self.wrapper, alpha);
+        self.run_window_commands()
     }
 }
 
-fn to_banana_key(key: &str) -> Option<KeyEvent> {
-    match key {
-        "ArrowDown" => Some(KeyEvent::Arrow(Dir::South)),
-        "ArrowUp" => Some(KeyEvent::Arrow(Dir::North)),
-        "ArrowLeft" => Some(KeyEvent::Arrow(Dir::West)),
-        "ArrowRight" => Some(KeyEvent::Arrow(Dir::East)),
-        "Enter" => Some(KeyEvent::Enter),
-        "Escape" => Some(KeyEvent::Esc),
-        _ => {
-            // Turn the string into chars, which are unicode scalar values, which isn't
-            // perfect but is better than using bytes or something.
-            // If there's exactly one (extremely normal case, US keyboard letter key)
-            // then wrap it as an event and return it
-            let ch: Vec<_> = key.chars().collect();
-            if ch.len() == 1 {
-                Some(KeyEvent::Letter(ch[0]))
-            } else {
-                None
-            }
-        }
+fn mouse_modifiers(event: &web_sys::MouseEvent) -> Modifiers {
+    Modifiers {
+        shift: event.shift_key(),
+        ctrl: event.ctrl_key(),
+        alt: event.alt_key(),
+        meta: event.meta_key()
     }
-}
\ No newline at end of file
+}
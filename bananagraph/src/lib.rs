@@ -1,24 +1,60 @@
+mod atlas;
 mod gpu_wrapper;
 mod id_buffer;
+mod id_readback;
 mod scale_transform;
 mod sprite;
 mod texture;
 mod drawing_context;
 mod typeface;
 mod event_handler;
+mod snapshot;
+mod sprite_batch;
+mod scene;
+mod camera;
+mod drag;
+mod touch;
+mod game_loop;
+mod window_handle;
+mod error;
+mod hit_test;
+mod retro_display;
+mod post_process;
+mod material;
+mod render_layer;
+mod capture;
+mod uniforms;
 
 pub use gpu_wrapper::GpuWrapper;
-pub use id_buffer::IdBuffer;
-pub use sprite::{Sprite, SpriteId};
+pub use error::{Error, Result};
+pub use scale_transform::ScalingMode;
+pub use atlas::AtlasRegion;
+pub use id_buffer::{IdBuffer, Hit};
+pub use hit_test::HitTester;
+pub use post_process::{Effect, EffectId};
+pub use material::MaterialId;
+pub use render_layer::RenderLayer;
+pub use capture::{Recording, encode_png};
+pub use retro_display::{RetroDisplay, DisplayMode, RETRO_MEMORY_SIZE, RETRO_DISPLAY_SIZE};
+pub use sprite::{Sprite, SpriteId, BlendMode};
 pub use drawing_context::DrawingContext;
-pub use event_handler::{Click, WindowEventHandler, MouseButton, Dir, ElementState};
+pub use event_handler::{Click, MousePos, MouseMove, Resize, Scroll, ScrollDelta, WindowEventHandler, MouseButton, Dir, ElementState, KeyEvent, Key, KeyState, Modifiers};
+pub use drag::{Drag, DragPhase, DragTracker};
+pub use touch::{Touch, TouchPhase, Gesture, GestureRecognizer, handle_touch, update_gestures};
 pub use typeface::{Typeface, Glyph, TypefaceBuilder, AddTexture};
+pub use snapshot::{Snapshot, SnapshotError, compare_images, diff_image};
+pub use sprite_batch::SpriteBatch;
+pub use scene::{Scene, NodeId};
+pub use camera::Camera;
+pub use game_loop::GameLoop;
+pub use window_handle::{WindowHandle, Cursor};
+pub use wgpu::PresentMode;
 
 #[cfg(feature = "desktop")]
 mod windowing;
 
 #[cfg(feature = "desktop")]
-pub use windowing::run_window;
+pub use windowing::{run_window, run_window_with, WindowConfig};
 
 #[cfg(feature = "web")]
 mod js_gpu_wrapper;
@@ -0,0 +1,132 @@
+use wgpu::{Device, Queue};
+use crate::sprite::BlendMode;
+use crate::uniforms::{uniform_buffer, write_uniforms};
+
+/// A handle to a material added with `GpuWrapper::add_material`, to draw sprites with (see
+/// `Sprite::with_material`)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub(crate) u32);
+
+/// A custom fragment shader for sprites, and the uniforms it reads. Each one gets its own set of
+/// render pipelines, one for opaque sprites and one per blend mode, since the shader is baked into
+/// the pipeline, and an id pipeline as well.
+pub(crate) struct Material {
+    /// The opaque pipeline first, then one per `BlendMode`, in the order of `BlendMode::ALL`
+    pipelines: Vec<wgpu::RenderPipeline>,
+    pub id_pipeline: wgpu::RenderPipeline,
+    uniforms: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup
+}
+
+impl Material {
+    pub fn new(device: &Device, queue: &Queue, pipelines: Vec<wgpu::RenderPipeline>, id_pipeline: wgpu::RenderPipeline, uniforms: &[u8]) -> Self {
+        let buffer = uniform_buffer(device, "material uniforms", uniforms.len());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("material"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+        });
+
+        let material = Self { pipelines, id_pipeline, uniforms: buffer, bind_group };
+        material.set_uniforms(queue, uniforms);
+        material
+    }
+
+    /// The second bind group the material pipelines have, after the one every sprite pipeline
+    /// shares: just the material's uniforms
+    pub fn bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("material"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    /// The whole shader for a material: the render shader, the material's own code, and the
+    /// fragment shader that calls it (see `material_shader.wgsl`)
+    pub fn source(wgsl: &str) -> String {
+        format!("{}\n{}\n{}", include_str!("render_shader.wgsl"), wgsl, include_str!("material_shader.wgsl"))
+    }
+
+    pub fn pipeline(&self, blend: Option<BlendMode>) -> &wgpu::RenderPipeline {
+        &self.pipelines[blend.map_or(0, |mode| mode as usize + 1)]
+    }
+
+    /// Write the uniforms, or as much of them as fits in the buffer
+    pub fn set_uniforms(&self, queue: &Queue, uniforms: &[u8]) {
+        write_uniforms(queue, &self.uniforms, uniforms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BlendMode, GpuWrapper, Sprite};
+
+    #[test]
+    fn test_material_uniforms() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        let swap = wrapper.add_material("
+            struct Swap { color: vec4<f32> }
+            @group(1) @binding(0) var<uniform> swap: Swap;
+
+            fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+                return swap.color;
+            }
+        ", bytemuck::bytes_of(&[0.0f32, 1.0, 0.0, 1.0]));
+
+        let plain = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5)).with_id(3);
+        let swapped = plain.translate((0.5, 0.5)).with_material(swap);
+        let (image, ids) = wrapper.render_to_image([plain, swapped]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(6, 6).0, [0, 0xff, 0, 0xff]);
+        assert_eq!(ids[cgmath::Point2::new(6.0, 6.0)], 3);
+
+        // Translucent sprites get the material too, and it can make pixels disappear, from the id
+        // buffer as well
+        wrapper.set_material_uniforms(swap, bytemuck::bytes_of(&[0.0f32, 0.0, 1.0, 0.0]));
+        let (image, ids) = wrapper.render_to_image([plain, swapped.with_blend(BlendMode::Alpha)]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(6, 6).0, [0, 0, 0, 0xff]);
+        assert_eq!(ids[cgmath::Point2::new(6.0, 6.0)], 0);
+        assert_eq!(ids[cgmath::Point2::new(1.0, 1.0)], 3);
+    }
+
+    #[test]
+    fn test_sample_sprite() {
+        // A 4x4 sheet: an opaque red texel in the middle of the left 2x2 sprite's right column,
+        // and a green right half the outline mustn't pick up
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((2, 2).into(), (2, 2).into()));
+        let mut sheet = [0u8, 0, 0, 0].repeat(4 * 4);
+        for y in 0..4 {
+            for x in 2..4 {
+                sheet[(x + y * 4) * 4..][..4].copy_from_slice(&[0, 0xff, 0, 0xff])
+            }
+        }
+        sheet[(1 + 4) * 4..][..4].copy_from_slice(&[0xff, 0, 0, 0xff]);
+        wrapper.add_texture_from_array(sheet, 4, None);
+
+        // White wherever a transparent texel has an opaque one to its right
+        let outline = wrapper.add_material("
+            fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+                if color.a == 0.0 && sample_sprite(in, vec2f(1.0, 0.0)).a > 0.0 {
+                    return vec4f(1.0);
+                }
+                return color;
+            }
+        ", &[]);
+
+        let sprite = Sprite::new((0, 0), (2, 2)).with_material(outline);
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(0, 1).0, [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,42 @@
+// This goes after a material's own code, which supplies
+// `fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32>`, and declares its uniforms (if it
+// has any) at `@group(1) @binding(0)`.
+
+// Read a texel of the sprite, `offset` texels away from this pixel's. Anything outside the
+// sprite's rectangle on the spritesheet is transparent, so this never bleeds into the sprites
+// next to it. Handy for outlines.
+fn sample_sprite(in: VertexOutput, offset: vec2<f32>) -> vec4<f32> {
+    let dimensions = vec2f(textureDimensions(spritesheets));
+    let coord = in.tex_coord + offset / dimensions;
+    let texel = coord * dimensions;
+    if any(texel < in.rect.xy) || any(texel >= in.rect.xy + in.rect.zw) {
+        return vec4f(0.0);
+    }
+    return textureSampleLevel(spritesheets, spritesheet_sampler, coord, in.layer, 0.0);
+}
+
+// The entry point for sprites with a material: the same as fs_main, except the material gets to
+// change the color before it's drawn
+@fragment fn fs_material(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sampled = textureSample(spritesheets, spritesheet_sampler, in.tex_coord, in.layer);
+    let color = material(sampled * in.tint, in);
+
+    if color.a == 0.0 {
+        discard;
+    } else {
+        return color;
+    }
+}
+
+// And the id pipeline's: the same as fs_id, except whatever the material makes transparent
+// doesn't count as part of the sprite
+@fragment fn fs_material_id(in: VertexOutput) -> @location(0) vec2<u32> {
+    let sampled = textureSample(spritesheets, spritesheet_sampler, in.tex_coord, in.layer);
+    let color = material(sampled * in.tint, in);
+
+    if color.a == 0.0 || in.id == 0 {
+        discard;
+    } else {
+        return vec2u(in.id, pack2x16unorm(in.uv));
+    }
+}
@@ -0,0 +1,95 @@
+// The built-in post-processing effects (see `Effect`), one fragment shader each. They all share
+// the same uniform layout: sixteen floats, which each effect uses however it likes.
+
+struct Params {
+    values: array<vec4<f32>, 4>,
+}
+
+@group(0) @binding(2) var<uniform> params: Params;
+
+const black: vec4<f32> = vec4f(0.0, 0.0, 0.0, 1.0);
+
+// values[0]: how dark the scanlines are, and how much the picture bulges
+@fragment fn fs_crt(in: PostInput) -> @location(0) vec4<f32> {
+    let scanlines = params.values[0].x;
+    let curvature = params.values[0].y;
+
+    // Bend the picture out from the center, like the glass of a tube: further from the center,
+    // we read from further out, so the edges get squashed and fall off the screen
+    let centered = in.uv * 2.0 - 1.0;
+    let uv = centered * (1.0 + curvature * dot(centered, centered) * 0.25) * 0.5 + 0.5;
+    if !in_frame(uv) {
+        return black;
+    }
+
+    let color = sample_input(uv);
+    if (u32(in.position.y) & 1u) == 1u {
+        return vec4f(color.rgb * (1.0 - scanlines), color.a);
+    }
+    return color;
+}
+
+// values[0]: how dark the corners get, and how far out (from 0 at the center to 1 at the corners)
+// the darkening starts
+@fragment fn fs_vignette(in: PostInput) -> @location(0) vec4<f32> {
+    let strength = params.values[0].x;
+    let radius = params.values[0].y;
+
+    let color = sample_input(in.uv);
+    let d = distance(in.uv, vec2f(0.5)) * 1.41421356;
+    let shade = 1.0 - strength * smoothstep(radius, 1.0, d);
+    return vec4f(color.rgb * shade, color.a);
+}
+
+// values[0]: how bright a pixel has to be to glow, how bright the glow is, and how far it spreads
+// (in pixels)
+@fragment fn fs_bloom(in: PostInput) -> @location(0) vec4<f32> {
+    let threshold = params.values[0].x;
+    let intensity = params.values[0].y;
+    let spread = params.values[0].z / 2.0 / vec2f(textureDimensions(input_texture));
+
+    // Blur just the bright parts, over a 5x5 grid of samples, and add that back on top
+    var glow = vec3f(0.0);
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let color = sample_input(in.uv + vec2f(f32(x), f32(y)) * spread).rgb;
+            let brightness = max(color.r, max(color.g, color.b));
+            glow += color * smoothstep(threshold, min(threshold + 0.1, 1.0), brightness);
+        }
+    }
+
+    let color = sample_input(in.uv);
+    return vec4f(min(color.rgb + glow / 25.0 * intensity, vec3f(1.0)), color.a);
+}
+
+// values[0]: brightness, contrast, and saturation; values[1]: a color to multiply by
+@fragment fn fs_color_grade(in: PostInput) -> @location(0) vec4<f32> {
+    let brightness = params.values[0].x;
+    let contrast = params.values[0].y;
+    let saturation = params.values[0].z;
+    let tint = params.values[1].rgb;
+
+    let color = sample_input(in.uv);
+    var rgb = (color.rgb * tint - 0.5) * contrast + 0.5 + brightness;
+    let luma = dot(rgb, vec3f(0.299, 0.587, 0.114));
+    rgb = mix(vec3f(luma), rgb, saturation);
+    return vec4f(clamp(rgb, vec3f(0.0), vec3f(1.0)), color.a);
+}
+
+// values[0]: how far to move the picture, in uv
+@fragment fn fs_shake(in: PostInput) -> @location(0) vec4<f32> {
+    let uv = in.uv - params.values[0].xy;
+    if !in_frame(uv) {
+        return black;
+    }
+    return sample_input(uv);
+}
+
+// values[0]: the color to flash; values[1]: how far to go toward it
+@fragment fn fs_flash(in: PostInput) -> @location(0) vec4<f32> {
+    let flash = params.values[0];
+    let amount = params.values[1].x;
+
+    let color = sample_input(in.uv);
+    return vec4f(mix(color.rgb, flash.rgb, clamp(amount * flash.a, 0.0, 1.0)), color.a);
+}
@@ -0,0 +1,413 @@
+use cgmath::{Vector2, Vector3, Vector4};
+use wgpu::{Buffer, Device, Queue, TextureFormat, TextureUsages};
+use crate::texture::Texture;
+use crate::uniforms::{uniform_buffer, write_uniforms};
+
+/// One of the built-in post-processing effects, with its settings. Add one with
+/// `GpuWrapper::add_effect`, and change its settings (every frame, to animate it) with
+/// `GpuWrapper::set_effect`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// Darken every other row of pixels, and bulge the picture like the glass of an old TV.
+    /// `scanlines` is how dark the dark rows get (0 to 1); `curvature` is how much it bulges
+    /// (0 is flat, 0.1 is plenty).
+    Crt { scanlines: f32, curvature: f32 },
+
+    /// Darken toward the corners. `strength` is how dark the corners get (0 to 1) and `radius`
+    /// is how far out the darkening starts, from 0 at the center to 1 at the corners.
+    Vignette { strength: f32, radius: f32 },
+
+    /// Make the bright parts of the picture glow. Pixels brighter than `threshold` (0 to 1, in
+    /// their brightest channel) spread `radius` pixels around them, `intensity` times as bright.
+    Bloom { threshold: f32, intensity: f32, radius: f32 },
+
+    /// Adjust the colors of the whole picture. `brightness` is added (0 leaves it alone),
+    /// `contrast` and `saturation` are multiplied (1 leaves them alone, 0 is all gray), and
+    /// everything's multiplied by `tint` first.
+    ColorGrade { brightness: f32, contrast: f32, saturation: f32, tint: Vector3<f32> },
+
+    /// Move the whole picture by `offset` logical pixels, leaving black behind. Jiggle it around
+    /// every frame to shake the screen. This only moves what's drawn, not the id buffer.
+    Shake { offset: Vector2<f32> },
+
+    /// Fade the picture toward `color`: `amount` 0 is no change, 1 is all `color` (times its
+    /// alpha). Fade `amount` back down over a few frames for a flash.
+    Flash { color: Vector4<f32>, amount: f32 }
+}
+
+impl Effect {
+    /// A flash to white, see `Effect::Flash`
+    pub fn flash(amount: f32) -> Self {
+        Effect::Flash { color: (1.0, 1.0, 1.0, 1.0).into(), amount }
+    }
+
+    /// A color grade that doesn't change anything, to adjust from
+    pub fn color_grade() -> Self {
+        Effect::ColorGrade { brightness: 0.0, contrast: 1.0, saturation: 1.0, tint: (1.0, 1.0, 1.0).into() }
+    }
+
+    fn entry_point(&self) -> &'static str {
+        match self {
+            Effect::Crt { .. } => "fs_crt",
+            Effect::Vignette { .. } => "fs_vignette",
+            Effect::Bloom { .. } => "fs_bloom",
+            Effect::ColorGrade { .. } => "fs_color_grade",
+            Effect::Shake { .. } => "fs_shake",
+            Effect::Flash { .. } => "fs_flash"
+        }
+    }
+
+    /// The settings as the shader reads them (see `post_effects.wgsl`). `scale` is how much uv a
+    /// logical pixel is, for the effects measured in those.
+    fn uniforms(&self, scale: Vector2<f32>) -> [f32; 16] {
+        let mut values = [0.0; 16];
+        match *self {
+            Effect::Crt { scanlines, curvature } => values[..2].copy_from_slice(&[scanlines, curvature]),
+            Effect::Vignette { strength, radius } => values[..2].copy_from_slice(&[strength, radius]),
+            Effect::Bloom { threshold, intensity, radius } => values[..3].copy_from_slice(&[threshold, intensity, radius]),
+            Effect::ColorGrade { brightness, contrast, saturation, tint } => {
+                values[..3].copy_from_slice(&[brightness, contrast, saturation]);
+                values[4..7].copy_from_slice(&[tint.x, tint.y, tint.z])
+            }
+            Effect::Shake { offset } => values[..2].copy_from_slice(&[offset.x * scale.x, offset.y * scale.y]),
+            Effect::Flash { color, amount } => {
+                values[..4].copy_from_slice(&[color.x, color.y, color.z, color.w]);
+                values[4] = amount
+            }
+        }
+        values
+    }
+}
+
+/// A handle to a pass in the post-processing chain, to change or remove it later
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EffectId(u32);
+
+struct Pass {
+    id: EffectId,
+    enabled: bool,
+
+    /// The built-in effect this is, if it is one; its uniforms are rewritten from this every frame.
+    /// Custom passes only get new uniforms when they're given them.
+    effect: Option<Effect>,
+
+    pipeline: wgpu::RenderPipeline,
+    uniforms: Buffer
+}
+
+/// The post-processing chain: when it has any passes turned on, the sprites are drawn into a
+/// texture instead of the frame, and each pass in turn draws a full-screen triangle reading the
+/// last one's output, the last pass drawing into the frame.
+pub(crate) struct PostChain {
+    passes: Vec<Pass>,
+    next_id: u32,
+
+    /// Whether to run the chain at all, so it can be switched off without removing everything
+    pub enabled: bool,
+
+    /// The format of the frame (and the textures between passes)
+    format: TextureFormat,
+    layout: wgpu::BindGroupLayout,
+
+    /// The built-in effects all live in one module, made the first time one's added
+    builtins: Option<wgpu::ShaderModule>,
+
+    /// What the passes draw into and read from, taking turns; made (or remade) the size of the
+    /// frame when it's first needed
+    textures: Option<[Texture; 2]>
+}
+
+impl PostChain {
+    pub fn new(device: &Device, format: TextureFormat) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post-processing"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    // The frame so far
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    // The pass's own uniforms
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        Self { passes: vec![], next_id: 0, enabled: true, format, layout, builtins: None, textures: None }
+    }
+
+    pub fn add_effect(&mut self, device: &Device, effect: Effect) -> EffectId {
+        let format = self.format;
+        let layout = &self.layout;
+        let module = self.builtins.get_or_insert_with(|| Self::create_shader(device, include_str!("post_effects.wgsl")));
+        let pipeline = Self::create_pipeline(device, layout, module, effect.entry_point(), format);
+        self.push(device, pipeline, Some(effect), size_of::<[f32; 16]>())
+    }
+
+    pub fn add_custom(&mut self, device: &Device, queue: &Queue, source: &str, uniforms: &[u8]) -> EffectId {
+        let module = Self::create_shader(device, source);
+        let pipeline = Self::create_pipeline(device, &self.layout, &module, "fs_main", self.format);
+        let id = self.push(device, pipeline, None, uniforms.len());
+        self.set_uniforms(queue, id, uniforms);
+        id
+    }
+
+    fn push(&mut self, device: &Device, pipeline: wgpu::RenderPipeline, effect: Option<Effect>, uniform_size: usize) -> EffectId {
+        let uniforms = uniform_buffer(device, "post-processing uniforms", uniform_size);
+
+        let id = EffectId(self.next_id);
+        self.next_id += 1;
+        self.passes.push(Pass { id, enabled: true, effect, pipeline, uniforms });
+        id
+    }
+
+    fn pass_mut(&mut self, id: EffectId) -> Option<&mut Pass> {
+        self.passes.iter_mut().find(|pass| pass.id == id)
+    }
+
+    /// Change a built-in effect's settings. It can even be changed to a different built-in effect,
+    /// as long as it's the same kind.
+    pub fn set_effect(&mut self, id: EffectId, effect: Effect) {
+        if let Some(Pass { effect: Some(old), .. }) = self.pass_mut(id) {
+            if old.entry_point() == effect.entry_point() {
+                *old = effect
+            }
+        }
+    }
+
+    /// Write a custom pass's uniforms, or as much of them as fits in its buffer
+    pub fn set_uniforms(&mut self, queue: &Queue, id: EffectId, uniforms: &[u8]) {
+        if let Some(pass) = self.pass_mut(id) {
+            write_uniforms(queue, &pass.uniforms, uniforms)
+        }
+    }
+
+    pub fn set_enabled(&mut self, id: EffectId, enabled: bool) {
+        if let Some(pass) = self.pass_mut(id) {
+            pass.enabled = enabled
+        }
+    }
+
+    pub fn is_enabled(&self, id: EffectId) -> bool {
+        self.passes.iter().any(|pass| pass.id == id && pass.enabled)
+    }
+
+    pub fn remove(&mut self, id: EffectId) {
+        self.passes.retain(|pass| pass.id != id)
+    }
+
+    /// Whether there's anything to do, so the sprites need drawing into our texture first
+    pub fn active(&self) -> bool {
+        self.enabled && self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    /// Where to draw the sprites this frame, before the chain runs
+    pub fn scene_target(&mut self, device: &Device, size: Vector2<u32>) -> wgpu::TextureView {
+        if self.textures.as_ref().is_none_or(|[texture, _]| texture.size != size) {
+            let create = || Texture::generic_texture(device, size, Some("post-processing texture"), self.format, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING);
+            self.textures = Some([create(), create()]);
+        }
+        self.textures.as_ref().unwrap()[0].view.clone()
+    }
+
+    /// Queue every pass that's turned on, in order, starting from what was drawn into
+    /// `scene_target` and ending in `target`. `scale` is how much uv a logical pixel is.
+    pub fn encode(&self, device: &Device, queue: &Queue, encoder: &mut wgpu::CommandEncoder, sampler: &wgpu::Sampler, target: &wgpu::TextureView, scale: Vector2<f32>) {
+        let Some(textures) = &self.textures else { return };
+        let passes: Vec<&Pass> = self.passes.iter().filter(|pass| pass.enabled).collect();
+
+        for (n, pass) in passes.iter().enumerate() {
+            if let Some(effect) = pass.effect {
+                write_uniforms(queue, &pass.uniforms, bytemuck::cast_slice(&effect.uniforms(scale)));
+            }
+
+            let input = &textures[n % 2];
+            let output = if n + 1 == passes.len() { target } else { &textures[(n + 1) % 2].view };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post-processing"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::Sampler(sampler) },
+                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&input.view) },
+                    wgpu::BindGroupEntry { binding: 2, resource: pass.uniforms.as_entire_binding() },
+                ],
+            });
+
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("post-processing"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: wgpu::StoreOp::Store },
+                })],
+                ..Default::default()
+            });
+            rpass.set_pipeline(&pass.pipeline);
+            rpass.set_bind_group(0, &bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+    }
+
+    /// A pass's shader: the prelude (see `post_shader.wgsl`) and then the pass's own code
+    fn create_shader(device: &Device, source: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post-processing"),
+            source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", include_str!("post_shader.wgsl"), source).into()),
+        })
+    }
+
+    fn create_pipeline(device: &Device, layout: &wgpu::BindGroupLayout, module: &wgpu::ShaderModule, entry_point: &str, format: TextureFormat) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("post-processing"),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+                label: None,
+            })),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: Some("vs_post"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GpuWrapper, Sprite};
+
+    fn red_wrapper() -> GpuWrapper<'static> {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        wrapper
+    }
+
+    #[test]
+    fn test_flash_and_toggle() {
+        let mut wrapper = red_wrapper();
+        let sprite = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5));
+        let flash = wrapper.add_effect(Effect::flash(1.0));
+
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0xff, 0xff, 0xff]);
+
+        wrapper.set_effect(flash, Effect::flash(0.0));
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+
+        wrapper.set_effect(flash, Effect::flash(1.0));
+        wrapper.set_effect_enabled(flash, false);
+        assert!(!wrapper.effect_enabled(flash));
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+
+        wrapper.set_effect_enabled(flash, true);
+        wrapper.set_post_processing(false);
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_chain_order() {
+        // Shake then flash: the flash covers the black the shake leaves behind. And the ids don't move.
+        let mut wrapper = red_wrapper();
+        let sprite = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5)).with_id(1);
+        wrapper.add_effect(Effect::Shake { offset: (4.0, 0.0).into() });
+        let flash = wrapper.add_effect(Effect::Flash { color: (0.0, 0.0, 1.0, 1.0).into(), amount: 0.0 });
+
+        let (image, ids) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(ids[cgmath::Point2::new(1.0, 1.0)], 1);
+
+        wrapper.set_effect(flash, Effect::Flash { color: (0.0, 0.0, 1.0, 1.0).into(), amount: 1.0 });
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0xff, 0xff]);
+
+        wrapper.remove_effect(flash);
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_custom_effect() {
+        let mut wrapper = red_wrapper();
+        let sprite = Sprite::new((0, 0), (4, 4)).scale((0.5, 0.5));
+        let shader = "
+            struct Swap { green: f32 }
+            @group(0) @binding(2) var<uniform> swap: Swap;
+
+            @fragment fn fs_main(in: PostInput) -> @location(0) vec4<f32> {
+                let color = sample_input(in.uv);
+                return vec4f(color.r, swap.green, color.b, color.a);
+            }
+        ";
+        let swap = wrapper.add_custom_effect(shader, bytemuck::bytes_of(&1.0f32));
+
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0xff, 0, 0xff]);
+        assert_eq!(image.get_pixel(6, 6).0, [0, 0xff, 0, 0xff]);
+
+        wrapper.set_effect_uniforms(swap, bytemuck::bytes_of(&0.0f32));
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0xff, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_builtins() {
+        // Every built-in compiles, and the ones that shouldn't change a plain picture much don't
+        let mut wrapper = red_wrapper();
+        let sprite = Sprite::new((0, 0), (4, 4));
+        for effect in [
+            Effect::Crt { scanlines: 0.0, curvature: 0.0 },
+            Effect::Vignette { strength: 1.0, radius: 1.0 },
+            Effect::Bloom { threshold: 0.5, intensity: 1.0, radius: 2.0 },
+            Effect::color_grade(),
+            Effect::Shake { offset: (0.0, 0.0).into() },
+            Effect::flash(0.0)
+        ] {
+            wrapper.add_effect(effect);
+        }
+
+        let (image, _) = wrapper.render_to_image([sprite]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(3, 3).0, [0xff, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,33 @@
+// What every post-processing pass starts with: a vertex shader that covers the whole target, and
+// the frame so far to read from. Passes supply their own fragment shader, `fs_main`, and if they
+// have uniforms, declare them at `@group(0) @binding(2)`.
+
+struct PostInput {
+    @builtin(position) position: vec4<f32>,
+    // Where this pixel is on the frame: 0..1 across the whole target, +y is down
+    @location(0) uv: vec2<f32>,
+}
+
+@group(0) @binding(0) var input_sampler: sampler;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+
+// One triangle, big enough that the part of it on the screen covers all of it:
+// uv (0, 0), (2, 0) and (0, 2)
+@vertex fn vs_post(@builtin(vertex_index) index: u32) -> PostInput {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: PostInput;
+    out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Read the frame so far. This doesn't need uniform control flow like textureSample does, so it's
+// fine to call after an early return.
+fn sample_input(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0);
+}
+
+fn in_frame(uv: vec2<f32>) -> bool {
+    return all(uv >= vec2f(0.0)) && all(uv < vec2f(1.0));
+}
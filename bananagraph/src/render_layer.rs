@@ -0,0 +1,154 @@
+use cgmath::Vector2;
+use wgpu::{Device, TextureFormat, TextureUsages};
+use crate::drawing_context::DrawingContext;
+use crate::sprite::Sprite;
+use crate::texture::Texture;
+
+/// A spritesheet layer that's drawn by the GPU instead of loaded from an image: draw a list of
+/// sprites into it with `GpuWrapper::draw_to_layer`, and then draw from it like any other layer.
+/// Good for things that are expensive to draw and don't change every frame, like a minimap, a
+/// panel of text, a portrait made of several sprites, or the terrain of a whole map.
+///
+/// Make one with `GpuWrapper::create_render_layer`. It starts out transparent.
+pub struct RenderLayer {
+    /// The spritesheet layer the sprites end up in
+    layer: u32,
+    size: Vector2<u32>,
+
+    /// The sprites get drawn into this first, in the same format as the frame, so every pipeline
+    /// (materials included) can draw into it. Then it's copied into `sheet` by a full-screen
+    /// pass, since the spritesheets are always RGBA and the frame might not be, and from there
+    /// into the layer of the spritesheet array.
+    pub(crate) canvas: Texture,
+    pub(crate) sheet: Texture,
+    pub(crate) depth: Texture,
+    pub(crate) blit_group: wgpu::BindGroup
+}
+
+impl RenderLayer {
+    pub(crate) fn new(device: &Device, layer: u32, size: Vector2<u32>, format: TextureFormat, blit: &wgpu::RenderPipeline, sampler: &wgpu::Sampler) -> Self {
+        let canvas = Texture::generic_texture(device, size, Some("render layer"), format, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING);
+        let sheet = Texture::create_render_layer(device, size);
+        let depth = Texture::create_depth_texture(device, size);
+        let blit_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("render layer"),
+            layout: &blit.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::Sampler(sampler) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&canvas.view) },
+            ],
+        });
+        Self { layer, size, canvas, sheet, depth, blit_group }
+    }
+
+    /// The pipeline that copies a canvas into its spritesheet: a post-processing pass that just
+    /// reads the input, drawing into RGBA
+    pub(crate) fn blit_pipeline(device: &Device) -> wgpu::RenderPipeline {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("render layer"),
+            source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", include_str!("post_shader.wgsl"), "
+                @fragment fn fs_main(in: PostInput) -> @location(0) vec4<f32> {
+                    return sample_input(in.uv);
+                }
+            ").into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("render layer"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_post"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TextureFormat::Rgba8Unorm,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// The spritesheet layer this draws into
+    pub fn layer(&self) -> u32 {
+        self.layer
+    }
+
+    /// How big it is, in texels
+    pub fn size(&self) -> Vector2<u32> {
+        self.size
+    }
+
+    /// A sprite of the whole layer, filling the logical screen (transform it like any other
+    /// sprite, or use `Sprite::new` with this layer for part of it)
+    pub fn sprite(&self) -> Sprite {
+        Sprite::new((0, 0), self.size).with_layer(self.layer)
+    }
+
+    /// A drawing context for placing sprites on this layer, with one unit per texel
+    pub fn drawing_context(&self) -> DrawingContext {
+        DrawingContext::new((self.size.x as f32, self.size.y as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point2;
+    use crate::{GpuWrapper, Sprite};
+
+    #[test]
+    fn test_draw_to_layer() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        wrapper.add_texture_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4, None);
+        let target = wrapper.create_render_layer((8, 8));
+        assert_eq!(target.layer(), 1);
+
+        // A red square in the top middle of the layer, and nothing (transparent) anywhere else
+        let dc = target.drawing_context();
+        wrapper.draw_to_layer(&target, [dc.place(Sprite::new((0, 0), (4, 4)), (2.0, 0.0))]);
+        let (image, ids) = wrapper.render_to_image([target.sprite().with_id(5)]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(3, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(3, 6).0, [0, 0, 0, 0xff]);
+        assert_eq!(ids[Point2::new(3.0, 1.0)], 5);
+        assert_eq!(ids[Point2::new(3.0, 6.0)], 0);
+
+        // It survives more textures being added, and the main screen still draws at its own size
+        wrapper.add_texture_from_array([0, 0, 0xff, 0xff].repeat(16 * 16), 16, None);
+        let (image, _) = wrapper.render_to_image([target.sprite().scale((0.5, 0.5))]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 0).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 1).0, [0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_layer_into_layer() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((4, 4).into(), (4, 4).into()));
+        wrapper.add_texture_from_array([0, 0xff, 0, 0xff].repeat(2 * 2), 2, None);
+        let big = wrapper.create_render_layer((8, 8));
+        let mini = wrapper.create_render_layer((4, 4));
+
+        // A minimap: the big layer, shrunk into the small one
+        wrapper.draw_to_layer(&big, [big.drawing_context().place(Sprite::new((0, 0), (2, 2)), (4.0, 4.0))]);
+        wrapper.draw_to_layer(&mini, [big.sprite()]);
+        let (image, _) = wrapper.render_to_image([mini.sprite()]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(2, 2).0, [0, 0xff, 0, 0xff]);
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0, 0xff]);
+
+        // Drawing a layer into itself sees what was there before
+        wrapper.draw_to_layer(&mini, [mini.sprite().translate((-0.5, -0.5))]);
+        let (image, _) = wrapper.render_to_image([mini.sprite()]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0xff, 0, 0xff]);
+        assert_eq!(image.get_pixel(2, 2).0, [0, 0, 0, 0xff]);
+    }
+}
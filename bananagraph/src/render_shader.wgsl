@@ -3,11 +3,18 @@ struct VertexOutput {
     @location(1) tint: vec4<f32>,
     @location(2) is_override_alpha: u32,
     @location(3) id: u32,
+    @location(4) layer: u32,
+    @location(5) uv: vec2<f32>,
+    // The sprite's rectangle on the spritesheet, in texels: origin, then size
+    @location(6) @interpolate(flat) rect: vec4<f32>,
     @builtin(position) position: vec4<f32>,
 }
 
 struct Locals {
     transform: mat4x4<f32>,
+    // The transform of the batch being drawn, on top of each sprite's own (see
+    // `SpriteBatch::set_transform`); the identity for loose sprites
+    batch: mat3x3<f32>,
 }
 
 struct Sprite {
@@ -18,11 +25,12 @@ struct Sprite {
     @location(5) size: vec2<f32>,
     @location(6) z: f32,
     @location(7) id: u32,
-    @location(8) tint: vec4<f32>
+    @location(8) tint: vec4<f32>,
+    @location(9) layer: u32
 }
 
 @group(0) @binding(0) var spritesheet_sampler: sampler;
-@group(0) @binding(1) var spritesheet: texture_2d<f32>;
+@group(0) @binding(1) var spritesheets: texture_2d_array<f32>;
 @group(0) @binding(2) var<uniform> locals: Locals;
 
 // A transform matrix to convert from corners-of-the-world (-1..1, +y is up) to
@@ -50,24 +58,30 @@ const unit_to_world: mat3x3<f32> = mat3x3<f32>(
 
     var transform = mat3x3<f32>(sprite.transform_i, sprite.transform_j, sprite.transform_k);
 
-    var pt = unit_to_world * transform * vec3f(position, 1.0);
+    var pt = unit_to_world * locals.batch * transform * vec3f(position, 1.0);
     var transformed =  locals.transform * vec4f(pt, 1.0);
     out.position = vec4f(transformed.x, transformed.y, sprite.z, 1.0);
 
-    // Convert the world-coord-square into the rectangle of the actual sprite
-    out.tex_coord = fma(position, sprite.size, sprite.origin);
+    // Convert the world-coord-square into the rectangle of the actual sprite (origin and size are
+    // in texels, so scale them down to texture coords)
+    out.tex_coord = fma(position, sprite.size, sprite.origin) / vec2f(textureDimensions(spritesheets));
 
-    // Write the alpha-override fields and the id:
+    // Write the alpha-override fields, the id, and which spritesheet to sample:
     out.tint = sprite.tint;
     out.id = sprite.id;
+    out.layer = sprite.layer;
+
+    // And where on the sprite this is, for the id pipeline to report what part of it was hit
+    out.uv = position;
+    out.rect = vec4f(sprite.origin, sprite.size);
 
     return out;
 }
 
 // The entry point for the fragment shader. Takes vertex outputs and turns them into colors
 @fragment fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    //return textureSample(spritesheet, spritesheet_sampler, in.tex_coord);
-    var color: vec4<f32> = textureSample(spritesheet, spritesheet_sampler, in.tex_coord);
+    //return textureSample(spritesheets, spritesheet_sampler, in.tex_coord, in.layer);
+    var color: vec4<f32> = textureSample(spritesheets, spritesheet_sampler, in.tex_coord, in.layer);
 
     color = color * in.tint;
 
@@ -84,15 +98,27 @@ const unit_to_world: mat3x3<f32> = mat3x3<f32>(
 }
 
 // Entry point for the id pipeline, which renders a texture with the id of the topmost sprite
-// at each pixel location (for hit detection)
-@fragment fn fs_id(in: VertexOutput) -> @location(0) u32 {
-    var color: vec4<f32> = textureSample(spritesheet, spritesheet_sampler, in.tex_coord);
+// at each pixel location (for hit detection), and where on that sprite the pixel is (its uv
+// coordinates, packed into 16 bits each)
+@fragment fn fs_id(in: VertexOutput) -> @location(0) vec2<u32> {
+    var color: vec4<f32> = textureSample(spritesheets, spritesheet_sampler, in.tex_coord, in.layer);
 
     color = color * in.tint;
 
     if color.a == 0.0 || in.id == 0 {
         discard;
     } else {
-        return in.id;
+        return vec2u(in.id, pack2x16unorm(in.uv));
     }
-}
\ No newline at end of file
+}
+// The background: the unit square, filling the logical screen, but not the letterbox bars around
+// it (those are the clear color)
+@vertex fn vs_background(@location(0) position: vec2<f32>) -> @builtin(position) vec4<f32> {
+    var pt = unit_to_world * vec3f(position, 1.0);
+    var transformed = locals.transform * vec4f(pt, 1.0);
+    return vec4f(transformed.x, transformed.y, 1.0, 1.0);
+}
+
+@fragment fn fs_background() -> @location(0) vec4<f32> {
+    return vec4f(0.0, 0.0, 0.0, 1.0);
+}
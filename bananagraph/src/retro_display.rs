@@ -0,0 +1,331 @@
+use cgmath::Vector2;
+use wgpu::{BufferUsages, Device, TextureFormat, TextureUsages};
+use crate::sprite::Sprite;
+
+/// How much memory a retro display has
+pub const RETRO_MEMORY_SIZE: usize = 128 * 1024;
+
+/// The size, in pixels, of the picture a retro display draws. Every mode fills this, just with
+/// bigger or smaller cells.
+pub const RETRO_DISPLAY_SIZE: Vector2<u32> = Vector2::new(640, 480);
+
+/// Where the registers are in memory. Everything but the mode is a 24-bit little-endian number.
+const MODE: usize = 16;
+const SCREEN: usize = 17;
+const PALETTE: usize = 20;
+const FONT: usize = 23;
+const HEIGHT: usize = 26;
+const WIDTH: usize = 29;
+const ROW_OFFSET: usize = 32;
+const COL_OFFSET: usize = 35;
+const BLANK_COLOR: usize = 38;
+
+/// What a retro display shows, and how it reads it out of memory. Text modes read a byte per
+/// character from the screen address, into the font (8 bytes per glyph, one per row, high bit on
+/// the left), followed by a byte of color per character. Graphics modes read a byte per pixel.
+///
+/// Direct modes store colors as RRRGGGBB bytes. Paletted modes look colors up in the 16-entry
+/// palette instead; in text modes the color byte is the background index in the high four bits
+/// and the foreground in the low four.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DisplayMode {
+    /// 40x30 characters, each pixel of the font doubled
+    TextLowresDirect = 0,
+
+    /// 128x128 pixels, each tripled, centered with a border of the blank color
+    GfxLowresDirect = 1,
+
+    /// 80x60 characters
+    TextHighresDirect = 2,
+
+    /// 160x120 pixels, each drawn 4x4
+    GfxHighresDirect = 3,
+
+    TextLowresPaletted = 4,
+    GfxLowresPaletted = 5,
+    TextHighresPaletted = 6,
+    GfxHighresPaletted = 7
+}
+
+impl DisplayMode {
+    /// How many characters (or pixels) across and down the screen is in this mode
+    pub fn size(&self) -> Vector2<u32> {
+        match self {
+            DisplayMode::TextLowresDirect | DisplayMode::TextLowresPaletted => Vector2::new(40, 30),
+            DisplayMode::GfxLowresDirect | DisplayMode::GfxLowresPaletted => Vector2::new(128, 128),
+            DisplayMode::TextHighresDirect | DisplayMode::TextHighresPaletted => Vector2::new(80, 60),
+            DisplayMode::GfxHighresDirect | DisplayMode::GfxHighresPaletted => Vector2::new(160, 120),
+        }
+    }
+}
+
+/// A fantasy-console style framebuffer: a block of memory (see `RETRO_MEMORY_SIZE`) holding
+/// registers, a palette, a font and screen data, which a compute shader turns into a picture
+/// every frame. Change what's shown by poking bytes into memory, or with the register setters.
+///
+/// Make one with `GpuWrapper::create_retro_display`. It gets a spritesheet layer of its own, which
+/// `GpuWrapper::update_retro_display` draws the picture into; then draw `sprite()` along with
+/// anything else, or just call `GpuWrapper::redraw_retro_display` to show it on its own.
+///
+/// This needs compute shaders, so it doesn't work on WebGL.
+pub struct RetroDisplay {
+    memory: Vec<u8>,
+
+    /// Whether memory has changed since it was last copied to the GPU
+    dirty: bool,
+
+    /// The spritesheet layer the picture gets copied into
+    layer: u32,
+
+    buffer: wgpu::Buffer,
+    pub(crate) texture: crate::texture::Texture,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup
+}
+
+impl RetroDisplay {
+    /// Where a new display keeps things: the registers are in the first 64 bytes, then the
+    /// palette, the font (256 glyphs) and the screen
+    pub const DEFAULT_PALETTE: u32 = 0x40;
+    pub const DEFAULT_FONT: u32 = 0x100;
+    pub const DEFAULT_SCREEN: u32 = 0x1000;
+
+    /// The classic 16 CGA colors, which new displays start with
+    pub const CGA_PALETTE: [[u8; 3]; 16] = [
+        [0x00, 0x00, 0x00], [0x00, 0x00, 0xaa], [0x00, 0xaa, 0x00], [0x00, 0xaa, 0xaa],
+        [0xaa, 0x00, 0x00], [0xaa, 0x00, 0xaa], [0xaa, 0x55, 0x00], [0xaa, 0xaa, 0xaa],
+        [0x55, 0x55, 0x55], [0x55, 0x55, 0xff], [0x55, 0xff, 0x55], [0x55, 0xff, 0xff],
+        [0xff, 0x55, 0x55], [0xff, 0x55, 0xff], [0xff, 0xff, 0x55], [0xff, 0xff, 0xff]
+    ];
+
+    pub(crate) fn new(device: &Device, layer: u32) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("retro display memory"),
+            size: RETRO_MEMORY_SIZE as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture = crate::texture::Texture::generic_texture(device, RETRO_DISPLAY_SIZE, Some("retro display"), TextureFormat::Rgba8Unorm, TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("retro display"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("compute_shader.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("retro display"),
+            layout: None,
+            module: &shader,
+            entry_point: Some("pixel_shader"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("retro display"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&texture.view) },
+                wgpu::BindGroupEntry { binding: 1, resource: buffer.as_entire_binding() },
+            ],
+        });
+
+        let mut display = Self { memory: vec![0; RETRO_MEMORY_SIZE], dirty: true, layer, buffer, texture, pipeline, bind_group };
+        display.set_palette(Self::DEFAULT_PALETTE);
+        display.set_font(Self::DEFAULT_FONT);
+        display.set_screen(Self::DEFAULT_SCREEN);
+        display.set_mode(DisplayMode::TextHighresPaletted);
+        for (n, color) in Self::CGA_PALETTE.iter().enumerate() {
+            display.set_palette_color(n as u8, *color)
+        }
+        display
+    }
+
+    /// A sprite of the whole picture, filling the logical screen (transform it like any other sprite)
+    pub fn sprite(&self) -> Sprite {
+        Sprite::new((0, 0), RETRO_DISPLAY_SIZE).with_layer(self.layer)
+    }
+
+    /// The spritesheet layer the picture is drawn into
+    pub fn layer(&self) -> u32 {
+        self.layer
+    }
+
+    /// Read a byte of memory. Addresses wrap around at the end of memory.
+    pub fn peek(&self, addr: u32) -> u8 {
+        self.memory[addr as usize % RETRO_MEMORY_SIZE]
+    }
+
+    pub fn poke(&mut self, addr: u32, value: u8) {
+        self.memory[addr as usize % RETRO_MEMORY_SIZE] = value;
+        self.dirty = true
+    }
+
+    /// Read a 24-bit little-endian number, the way the registers are stored
+    pub fn peek24(&self, addr: u32) -> u32 {
+        (0..3).map(|n| (self.peek(addr + n) as u32) << (n * 8)).sum()
+    }
+
+    pub fn poke24(&mut self, addr: u32, value: u32) {
+        for n in 0..3 {
+            self.poke(addr + n, (value >> (n * 8)) as u8)
+        }
+    }
+
+    /// Copy a run of bytes into memory, starting at `addr`
+    pub fn write(&mut self, addr: u32, bytes: &[u8]) {
+        for (n, byte) in bytes.iter().enumerate() {
+            self.poke(addr + n as u32, *byte)
+        }
+    }
+
+    /// All of memory, to read or change directly
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.dirty = true;
+        &mut self.memory
+    }
+
+    pub fn mode(&self) -> Option<DisplayMode> {
+        use DisplayMode::*;
+        [TextLowresDirect, GfxLowresDirect, TextHighresDirect, GfxHighresDirect, TextLowresPaletted, GfxLowresPaletted, TextHighresPaletted, GfxHighresPaletted]
+            .get(self.peek(MODE as u32) as usize).copied()
+    }
+
+    /// Change the mode, and the screen size to match it (see `DisplayMode::size`)
+    pub fn set_mode(&mut self, mode: DisplayMode) {
+        self.poke(MODE as u32, mode as u8);
+        self.set_size(mode.size())
+    }
+
+    /// Where the screen data (characters or pixels) starts
+    pub fn set_screen(&mut self, addr: u32) {
+        self.poke24(SCREEN as u32, addr)
+    }
+
+    pub fn screen(&self) -> u32 {
+        self.peek24(SCREEN as u32)
+    }
+
+    /// Where the 16 palette colors (three bytes each, red green blue) start
+    pub fn set_palette(&mut self, addr: u32) {
+        self.poke24(PALETTE as u32, addr)
+    }
+
+    pub fn palette(&self) -> u32 {
+        self.peek24(PALETTE as u32)
+    }
+
+    /// Set one of the 16 palette colors
+    pub fn set_palette_color(&mut self, index: u8, color: [u8; 3]) {
+        self.write(self.palette() + 3 * (index as u32 % 16), &color)
+    }
+
+    /// Where the font starts: 8 bytes per glyph, one per row, high bit on the left
+    pub fn set_font(&mut self, addr: u32) {
+        self.poke24(FONT as u32, addr)
+    }
+
+    pub fn font(&self) -> u32 {
+        self.peek24(FONT as u32)
+    }
+
+    /// Copy glyphs into the font, starting at glyph 0
+    pub fn load_font(&mut self, glyphs: &[u8]) {
+        self.write(self.font(), glyphs)
+    }
+
+    /// The screen's size in characters (or pixels, in the graphics modes). Setting the mode sets
+    /// this to fill the display, but it can be set bigger, to scroll around a larger screen.
+    pub fn set_size(&mut self, size: impl Into<Vector2<u32>>) {
+        let size = size.into();
+        self.poke24(WIDTH as u32, size.x);
+        self.poke24(HEIGHT as u32, size.y)
+    }
+
+    pub fn size(&self) -> Vector2<u32> {
+        Vector2::new(self.peek24(WIDTH as u32), self.peek24(HEIGHT as u32))
+    }
+
+    /// Scroll the screen, by whole rows and columns; it wraps around at the edges
+    pub fn set_scroll(&mut self, row: u32, col: u32) {
+        self.poke24(ROW_OFFSET as u32, row);
+        self.poke24(COL_OFFSET as u32, col)
+    }
+
+    /// The color around the picture in the lowres graphics modes, and behind text in the direct
+    /// text modes
+    pub fn set_blank_color(&mut self, color: [u8; 3]) {
+        self.write(BLANK_COLOR as u32, &color)
+    }
+
+    /// Write a string into a text mode screen at a character position, with a color byte for each
+    /// character (see `DisplayMode`). Characters are written as their byte values, so the font
+    /// decides what they look like.
+    pub fn print(&mut self, col: u32, row: u32, text: &str, color: u8) {
+        let (screen, size) = (self.screen(), self.size());
+        for (n, byte) in text.bytes().enumerate() {
+            let addr = screen + row * size.x + col + n as u32;
+            self.poke(addr, byte);
+            self.poke(addr + size.x * size.y, color)
+        }
+    }
+
+    /// Queue the compute pass that draws the picture, copying memory up first if it's changed
+    pub(crate) fn encode(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        if self.dirty {
+            queue.write_buffer(&self.buffer, 0, &self.memory);
+            self.dirty = false
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("retro display"), timestamp_writes: None });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(RETRO_DISPLAY_SIZE.x.div_ceil(8), RETRO_DISPLAY_SIZE.y.div_ceil(8), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GpuWrapper;
+
+    #[test]
+    fn test_registers() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((64, 48).into(), RETRO_DISPLAY_SIZE));
+        let mut display = wrapper.create_retro_display().unwrap();
+
+        assert_eq!(display.mode(), Some(DisplayMode::TextHighresPaletted));
+        assert_eq!(display.size(), Vector2::new(80, 60));
+        assert_eq!(display.peek24(display.palette() + 3 * 15), 0xffffff);
+
+        display.poke24(0x1fffe, 0x123456);
+        assert_eq!((display.peek(0x1fffe), display.peek(0x1ffff), display.peek(0)), (0x56, 0x34, 0x12));
+        assert_eq!(display.peek24(0x1fffe), 0x123456);
+
+        display.set_mode(DisplayMode::GfxLowresPaletted);
+        assert_eq!(display.size(), Vector2::new(128, 128));
+        display.print(2, 1, "hi", 0x1f);
+        assert_eq!(display.peek(RetroDisplay::DEFAULT_SCREEN + 130), b'h');
+        assert_eq!(display.peek(RetroDisplay::DEFAULT_SCREEN + 128 * 128 + 131), 0x1f);
+    }
+
+    #[test]
+    fn test_draw() {
+        // A 160x120 window, so each 4x4 pixel of the highres graphics mode is a window pixel
+        let size = Vector2::new(RETRO_DISPLAY_SIZE.x / 4, RETRO_DISPLAY_SIZE.y / 4);
+        let mut wrapper = pollster::block_on(GpuWrapper::headless(size, RETRO_DISPLAY_SIZE));
+        wrapper.scaling_mode = crate::ScalingMode::Shrink;
+        let mut display = wrapper.create_retro_display().unwrap();
+        display.set_mode(DisplayMode::GfxHighresDirect);
+        display.poke(RetroDisplay::DEFAULT_SCREEN, 0b11100000);
+        display.poke(RetroDisplay::DEFAULT_SCREEN + 160 * 60 + 80, 0b00011100);
+
+        wrapper.update_retro_display(&mut display);
+        let (image, _) = wrapper.render_to_image([display.sprite()]).unwrap().unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(80, 60).0, [0, 0xff, 0, 0xff]);
+        assert_eq!(image.get_pixel(10, 10).0, [0, 0, 0, 0xff]);
+    }
+}
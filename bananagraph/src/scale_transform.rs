@@ -1,29 +1,59 @@
-use cgmath::Vector2;
-
-/// Create a transform matrix to scale / center a texture into a larger rectangle,
-/// scaling it up as much as possible but only evenly. This is copied from the `pixels`
-/// crate: https://github.com/parasyte/pixels/blob/main/src/renderers.rs
-///
-/// That being said... This is actually not a great scaling system for Vulcan. For one
-/// thing, it's probably actually okay that we scale to non-integer scaling factors,
-/// since we're almost forced to by some monitors: any monitor with a non-integer factor
-/// will have winit create a window that's in between scales. Also, we may not even want
-/// the nearest-neighbor sampler that this works with; at a high enough resolution it
-/// may actually just look fine blurry. So this may get cut down or altered later.
+use cgmath::{Point2, Vector2};
+
+/// How the logical screen gets fit into a window that's a different size (or shape) than it
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum ScalingMode {
+    /// Scale up by the biggest whole number that fits, so every logical pixel is the same number
+    /// of window pixels, and center it with bars around the edges. Pixel-perfect, but can waste a
+    /// lot of the window.
+    Integer,
+
+    /// Scale evenly as much as fits and center it, with bars along two edges. Like always, this
+    /// never scales below one window pixel per logical pixel: a window smaller than the logical
+    /// screen shows the middle of it. See `Shrink` to fit it in anyway.
+    #[default]
+    Letterbox,
+
+    /// Like `Letterbox`, but scales down too, so a tiny window still shows the whole logical screen
+    Shrink,
+
+    /// Scale each direction separately to fill the window exactly, distorting the aspect ratio
+    Stretch,
+
+    /// Scale evenly to cover the whole window, and center it, cropping off two edges
+    Crop
+}
+
+impl ScalingMode {
+    /// How many window pixels wide and tall one texture pixel is
+    fn scale(self, texture_size: Vector2<u32>, window_size: Vector2<u32>) -> Vector2<f32> {
+        let width_ratio = window_size.x as f32 / texture_size.x as f32;
+        let height_ratio = window_size.y as f32 / texture_size.y as f32;
+
+        let even = |scale: f32| Vector2::new(scale, scale);
+        match self {
+            ScalingMode::Integer => even(width_ratio.min(height_ratio).floor().max(1.0)),
+            ScalingMode::Letterbox => even(width_ratio.min(height_ratio).max(1.0)),
+            ScalingMode::Shrink => even(width_ratio.min(height_ratio)),
+            ScalingMode::Stretch => Vector2::new(width_ratio, height_ratio),
+            ScalingMode::Crop => even(width_ratio.max(height_ratio))
+        }
+    }
+}
+
+/// Create a transform matrix to scale / center a texture into a window, according to a
+/// `ScalingMode`. The centering is nudged by half a pixel for odd-sized windows, so texture
+/// pixels line up with window pixels (when the scale is a whole number). This was originally
+/// copied from the `pixels` crate: https://github.com/parasyte/pixels/blob/main/src/renderers.rs
 #[rustfmt::skip]
-pub fn transform(texture_size: Vector2<u32>, window_size: Vector2<u32>) -> [f32; 16] {
+pub fn transform(texture_size: Vector2<u32>, window_size: Vector2<u32>, mode: ScalingMode) -> [f32; 16] {
     let (texture_width, texture_height) = texture_size.into();
     let (screen_width, screen_height) = window_size.into();
     let (screen_width, screen_height) = (screen_width as f32, screen_height as f32);
+    let scale = mode.scale(texture_size, window_size);
 
-    let width_ratio = (screen_width / texture_width as f32).max(1.0);
-    let height_ratio = (screen_height / texture_height as f32).max(1.0);
-
-    // Get smallest scale size. To force integer scales, add a .floor() to this
-    let scale = width_ratio.clamp(1.0, height_ratio);
-
-    let scaled_width = texture_width as f32 * scale;
-    let scaled_height = texture_height as f32 * scale;
+    let scaled_width = texture_width as f32 * scale.x;
+    let scaled_height = texture_height as f32 * scale.y;
 
     // Create a transformation matrix
     let sw = scaled_width / screen_width;
@@ -38,3 +68,69 @@ pub fn transform(texture_size: Vector2<u32>, window_size: Vector2<u32>) -> [f32;
         tx,  ty,  0.0, 1.0,
     ]
 }
+
+/// The rectangle of the window (origin and size, in whole window pixels) that the texture is
+/// drawn over, cut down to the window. Everything outside of this is letterbox bars.
+pub fn viewport(texture_size: Vector2<u32>, window_size: Vector2<u32>, mode: ScalingMode) -> (Point2<u32>, Vector2<u32>) {
+    let top_left = texture_to_window(texture_size, window_size, Point2::new(0.0, 0.0), mode);
+    let bottom_right = texture_to_window(texture_size, window_size, Point2::new(texture_size.x as f64, texture_size.y as f64), mode);
+
+    let left = top_left.x.round().clamp(0.0, window_size.x as f64) as u32;
+    let top = top_left.y.round().clamp(0.0, window_size.y as f64) as u32;
+    let right = bottom_right.x.round().clamp(0.0, window_size.x as f64) as u32;
+    let bottom = bottom_right.y.round().clamp(0.0, window_size.y as f64) as u32;
+    (Point2::new(left, top), Vector2::new(right - left, bottom - top))
+}
+
+/// The inverse of `transform`: given a point in window pixels (like a mouse position), return the
+/// point in the texture that's drawn there, in texture pixels. Points in the border around the
+/// texture come out negative or past `texture_size`.
+pub fn window_to_texture(texture_size: Vector2<u32>, window_size: Vector2<u32>, point: Point2<f64>, mode: ScalingMode) -> Point2<f64> {
+    let m = transform(texture_size, window_size, mode);
+    let (sw, sh, tx, ty) = (m[0] as f64, m[5] as f64, m[12] as f64, m[13] as f64);
+
+    // Window pixels to clip space (-1..1, +y is up), then undo the scale and offset
+    let clip_x = point.x / window_size.x as f64 * 2.0 - 1.0;
+    let clip_y = 1.0 - point.y / window_size.y as f64 * 2.0;
+    let (x, y) = ((clip_x - tx) / sw, (clip_y - ty) / sh);
+
+    // And back out of clip space into the texture
+    Point2::new((x + 1.0) / 2.0 * texture_size.x as f64, (1.0 - y) / 2.0 * texture_size.y as f64)
+}
+
+/// Where a point in the texture (in texture pixels) ends up in the window, in window pixels;
+/// the opposite of `window_to_texture`
+pub fn texture_to_window(texture_size: Vector2<u32>, window_size: Vector2<u32>, point: Point2<f64>, mode: ScalingMode) -> Point2<f64> {
+    let m = transform(texture_size, window_size, mode);
+    let (sw, sh, tx, ty) = (m[0] as f64, m[5] as f64, m[12] as f64, m[13] as f64);
+
+    let x = point.x / texture_size.x as f64 * 2.0 - 1.0;
+    let y = 1.0 - point.y / texture_size.y as f64 * 2.0;
+    let (clip_x, clip_y) = (x * sw + tx, y * sh + ty);
+
+    Point2::new((clip_x + 1.0) / 2.0 * window_size.x as f64, (1.0 - clip_y) / 2.0 * window_size.y as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewport() {
+        let (texture, window) = (Vector2::new(16, 16), Vector2::new(40, 36));
+        assert_eq!(viewport(texture, window, ScalingMode::Integer), ((4, 2).into(), (32, 32).into()));
+        assert_eq!(viewport(texture, window, ScalingMode::Letterbox), ((2, 0).into(), (36, 36).into()));
+        assert_eq!(viewport(texture, window, ScalingMode::Shrink), ((2, 0).into(), (36, 36).into()));
+        assert_eq!(viewport(texture, window, ScalingMode::Stretch), ((0, 0).into(), (40, 36).into()));
+        assert_eq!(viewport(texture, window, ScalingMode::Crop), ((0, 0).into(), (40, 36).into()));
+
+        // Cropping cuts off the top and bottom, so the middle of the window is still the middle
+        let top = window_to_texture(texture, window, Point2::new(20.0, 0.0), ScalingMode::Crop);
+        assert!((top.x - 8.0).abs() < 0.001 && (top.y - 0.8).abs() < 0.001);
+
+        // A window smaller than the texture only shrinks it if we ask
+        let small = Vector2::new(8, 12);
+        assert_eq!(viewport(texture, small, ScalingMode::Letterbox), ((0, 0).into(), (8, 12).into()));
+        assert_eq!(viewport(texture, small, ScalingMode::Shrink), ((0, 2).into(), (8, 8).into()));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{DrawingContext, GpuWrapper, IdBuffer, Sprite, SpriteBatch};
+
+/// A handle to a node in a `Scene`. Handles to removed nodes stay invalid even if their slot is
+/// reused, so holding onto an old one is harmless.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId {
+    index: usize,
+    generation: u32
+}
+
+/// A retained tree of sprites, for when most of what's on screen doesn't change from one frame to
+/// the next. Each node has a `DrawingContext` and a list of sprites; a node's context is applied
+/// on top of its sprites' own transforms, and on top of that go the contexts of all its ancestors.
+/// So, place a node's sprites with a plain `DrawingContext::new(screen)` and then move the whole
+/// node around by changing its context.
+///
+/// Each node is kept on the GPU as a `SpriteBatch`, and only nodes whose sprites have changed get
+/// re-encoded when the scene is drawn. Moving a node just changes its batch's transform (and its
+/// descendants'), which is applied on the GPU:
+/// ```no_run
+/// # use bananagraph::{DrawingContext, GpuWrapper, Scene, Sprite};
+/// # fn f(wrapper: &GpuWrapper, tiles: Vec<Sprite>, player: Sprite) {
+/// let dc = DrawingContext::new((320.0, 240.0));
+/// let mut scene = Scene::new();
+/// let map = scene.insert(None, dc, tiles).unwrap();
+///
+/// // Scroll the map; the tiles themselves don't have to be placed again
+/// scene.set_context(map, dc.translate((-0.1, 0.0)));
+/// scene.redraw(wrapper, [player]);
+/// # }
+/// ```
+/// Like with `GpuWrapper::redraw_batches`, translucent sprites are only drawn back to front
+/// relative to other sprites in the same node.
+#[derive(Default)]
+pub struct Scene {
+    slots: Vec<Slot>,
+    free: Vec<usize>
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>
+}
+
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    context: DrawingContext,
+    sprites: Vec<Sprite>,
+
+    /// The node's sprites as last encoded, or None if it's never been drawn
+    batch: Option<SpriteBatch>,
+
+    /// Whether the batch is out of date with the sprites
+    dirty: bool,
+
+    /// Whether the batch's transform is out of date with the contexts
+    moved: bool
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node to the scene, either at the top level or as a child of `parent`. Returns the
+    /// handle for it, or None if the parent doesn't exist.
+    pub fn insert<I: IntoIterator<Item=S>, S: Into<Sprite>>(&mut self, parent: Option<NodeId>, context: DrawingContext, sprites: I) -> Option<NodeId> {
+        if parent.is_some_and(|parent| self.node(parent).is_none()) {
+            return None
+        }
+
+        let node = Node {
+            parent,
+            children: vec![],
+            context,
+            sprites: sprites.into_iter().map(Into::into).collect(),
+            batch: None,
+            dirty: true,
+            moved: true
+        };
+
+        let index = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(Slot::default());
+            self.slots.len() - 1
+        });
+        let slot = &mut self.slots[index];
+        slot.node = Some(node);
+        let id = NodeId { index, generation: slot.generation };
+
+        if let Some(parent) = parent.and_then(|parent| self.node_mut(parent)) {
+            parent.children.push(id)
+        }
+        Some(id)
+    }
+
+    /// Remove a node and all its descendants from the scene. Returns whether it existed.
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let Some(node) = self.node(id) else { return false };
+        if let Some(parent) = node.parent.and_then(|parent| self.node_mut(parent)) {
+            parent.children.retain(|child| *child != id)
+        }
+
+        let mut doomed = vec![id];
+        while let Some(id) = doomed.pop() {
+            let slot = &mut self.slots[id.index];
+            if let Some(node) = slot.node.take() {
+                doomed.extend(node.children);
+                slot.generation += 1;
+                self.free.push(id.index)
+            }
+        }
+        true
+    }
+
+    /// Whether this handle refers to a node that's still in the scene
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.node(id).is_some()
+    }
+
+    pub fn context(&self, id: NodeId) -> Option<&DrawingContext> {
+        self.node(id).map(|node| &node.context)
+    }
+
+    pub fn sprites(&self, id: NodeId) -> Option<&[Sprite]> {
+        self.node(id).map(|node| node.sprites.as_slice())
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).and_then(|node| node.parent)
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.node(id).map_or(&[], |node| node.children.as_slice())
+    }
+
+    /// Move a node (and everything under it) by giving it a new context
+    pub fn set_context(&mut self, id: NodeId, context: DrawingContext) {
+        if let Some(node) = self.node_mut(id) {
+            node.context = context;
+            self.mark_moved(id)
+        }
+    }
+
+    /// Replace the sprites in a node. Its children are unaffected.
+    pub fn set_sprites<I: IntoIterator<Item=S>, S: Into<Sprite>>(&mut self, id: NodeId, sprites: I) {
+        if let Some(node) = self.node_mut(id) {
+            node.sprites = sprites.into_iter().map(Into::into).collect();
+            node.dirty = true
+        }
+    }
+
+    /// Move a node to a different parent (or to the top level), keeping its children. Returns
+    /// false (and changes nothing) if either node doesn't exist, or if `parent` is inside `id`.
+    pub fn reparent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        if !self.contains(id) {
+            return false
+        }
+
+        // Walk up from the new parent to make sure we're not making a cycle
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id || !self.contains(a) {
+                return false
+            }
+            ancestor = self.parent(a)
+        }
+
+        if let Some(old) = self.parent(id).and_then(|old| self.node_mut(old)) {
+            old.children.retain(|child| *child != id)
+        }
+        if let Some(new) = parent.and_then(|new| self.node_mut(new)) {
+            new.children.push(id)
+        }
+        self.node_mut(id).unwrap().parent = parent;
+        self.mark_moved(id);
+        true
+    }
+
+    /// Re-encode every node whose sprites have changed since the last update, and re-place every
+    /// one that's moved
+    pub fn update(&mut self, wrapper: &GpuWrapper) {
+        for index in 0..self.slots.len() {
+            let Some(node) = &self.slots[index].node else { continue };
+            let context = node.moved.then(|| self.world_context(NodeId { index, generation: self.slots[index].generation }));
+
+            let node = self.slots[index].node.as_mut().unwrap();
+            if node.dirty {
+                match &mut node.batch {
+                    Some(batch) => wrapper.update_batch(batch, &node.sprites),
+                    None => node.batch = Some(wrapper.create_batch(&node.sprites))
+                }
+                node.dirty = false
+            }
+            if let (Some(context), Some(batch)) = (context, &mut node.batch) {
+                batch.set_transform(context.transform());
+                node.moved = false
+            }
+        }
+    }
+
+    /// The batches for every node, as of the last `update`
+    pub fn batches(&self) -> Vec<&SpriteBatch> {
+        self.slots.iter().filter_map(|slot| slot.node.as_ref()?.batch.as_ref()).collect()
+    }
+
+    /// Update the scene and draw it, along with some other (non-retained) sprites
+    pub fn redraw<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&mut self, wrapper: &GpuWrapper, sprites: I) {
+        self.update(wrapper);
+        wrapper.redraw_batches(&self.batches(), sprites)
+    }
+
+    /// Like `redraw`, but also populates and returns the id buffer
+    pub fn redraw_with_ids<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&mut self, wrapper: &GpuWrapper, sprites: I) -> Result<IdBuffer, wgpu::BufferAsyncError> {
+        self.update(wrapper);
+        wrapper.redraw_batches_with_ids(&self.batches(), sprites)
+    }
+
+    pub fn try_redraw_with_ids<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&mut self, wrapper: &GpuWrapper, sprites: I) -> crate::Result<IdBuffer> {
+        self.update(wrapper);
+        wrapper.try_redraw_batches_with_ids(&self.batches(), sprites)
+    }
+
+    /// Like `redraw`, but also returns the newest id buffer without waiting for this frame's; see
+    /// `GpuWrapper::redraw_with_latest_ids`
+    pub fn redraw_with_latest_ids<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&mut self, wrapper: &GpuWrapper, sprites: I) -> crate::Result<Option<IdBuffer>> {
+        self.update(wrapper);
+        wrapper.redraw_batches_with_latest_ids(&self.batches(), sprites)
+    }
+
+    fn node(&self, id: NodeId) -> Option<&Node> {
+        self.slots.get(id.index).filter(|slot| slot.generation == id.generation)?.node.as_ref()
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots.get_mut(id.index).filter(|slot| slot.generation == id.generation)?.node.as_mut()
+    }
+
+    /// Mark a node and all its descendants as needing their transforms worked out again
+    fn mark_moved(&mut self, id: NodeId) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.node_mut(id) {
+                node.moved = true;
+                stack.extend(node.children.iter().copied())
+            }
+        }
+    }
+
+    /// The context a node's sprites are actually drawn with: its own, within all its ancestors'
+    fn world_context(&self, id: NodeId) -> DrawingContext {
+        let node = self.node(id).unwrap();
+        match node.parent {
+            Some(parent) => node.context.within(&self.world_context(parent)),
+            None => node.context
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_and_reparent() {
+        let dc = DrawingContext::new((8.0, 8.0));
+        let mut scene = Scene::new();
+        let a = scene.insert(None, dc, [Sprite::new((0, 0), (1, 1))]).unwrap();
+        let b = scene.insert(Some(a), dc, [Sprite::new((0, 0), (1, 1))]).unwrap();
+        let c = scene.insert(Some(b), dc, [Sprite::new((0, 0), (1, 1))]).unwrap();
+
+        // Can't put a node inside its own descendant
+        assert!(!scene.reparent(a, Some(c)));
+        assert!(scene.reparent(c, Some(a)));
+        assert_eq!(scene.children(a), &[b, c]);
+
+        assert!(scene.remove(a));
+        assert!(!scene.contains(b));
+        assert!(!scene.contains(c));
+
+        // Slots get reused, but old handles don't see the new nodes
+        let d = scene.insert(None, dc, [Sprite::new((0, 0), (1, 1))]).unwrap();
+        assert!(scene.contains(d));
+        assert!(!scene.contains(a) && !scene.contains(b) && !scene.contains(c));
+        assert_eq!(scene.slots.len(), 3);
+    }
+
+    #[test]
+    fn test_only_dirty_nodes_update() {
+        let mut wrapper = pollster::block_on(GpuWrapper::headless((8, 8).into(), (8, 8).into()));
+        let red = wrapper.add_image_from_array([0xff, 0, 0, 0xff].repeat(4 * 4), 4);
+        let blue = wrapper.add_image_from_array([0, 0, 0xff, 0xff].repeat(4 * 4), 4);
+
+        let dc = DrawingContext::new((8.0, 8.0));
+        let mut scene = Scene::new();
+        let map = scene.insert(None, dc, [dc.place(red, (0.0, 0.0))]).unwrap();
+        let marker = scene.insert(Some(map), dc, [dc.place(blue, (0.0, 4.0))]).unwrap();
+        let hud = scene.insert(None, dc, [dc.place(blue, (4.0, 4.0))]).unwrap();
+        scene.update(&wrapper);
+
+        // Moving the map moves its child too, but leaves the other node alone, and doesn't
+        // re-encode anything
+        scene.set_context(map, dc.translate((0.5, 0.0)));
+        assert!(scene.node(map).unwrap().moved);
+        assert!(scene.node(marker).unwrap().moved);
+        assert!(!scene.node(hud).unwrap().moved);
+        assert!(!scene.node(map).unwrap().dirty);
+        scene.update(&wrapper);
+        assert!(!scene.node(marker).unwrap().moved);
+
+        let (image, _) = wrapper.render_batches_to_image(&scene.batches(), Vec::<Sprite>::new()).unwrap().unwrap();
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 1).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(5, 5).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(1, 5).0, [0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,177 @@
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use cgmath::Vector2;
+use image::{Rgba, RgbaImage};
+use crate::{GpuWrapper, Sprite};
+
+/// A headless `GpuWrapper` for golden-image tests. Add whatever textures (or typefaces) the
+/// scene needs through it as you would a normal wrapper, then compare a list of sprites against
+/// a checked-in PNG:
+/// ```no_run
+/// # use bananagraph::{Snapshot, Sprite};
+/// let mut snapshot = Snapshot::new((64, 64));
+/// snapshot.add_texture(&std::fs::read("spritesheet.png").unwrap(), None);
+/// snapshot.assert_matches("src/snapshots/glyph.png", [Sprite::new((0, 0), (16, 16))], 2);
+/// ```
+/// Golden images are only written when the `BANANAGRAPH_BLESS` environment variable is set: then
+/// the rendered image is saved there and the comparison passes. Without it, a missing golden image
+/// is an error, so a test can't pass just because its golden image never got checked in.
+pub struct Snapshot {
+    wrapper: GpuWrapper<'static>
+}
+
+/// Why a rendered image didn't match its golden image
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The images aren't even the same size: (golden, actual)
+    Size(Vector2<u32>, Vector2<u32>),
+
+    /// This many pixels were off by more than the tolerance
+    Pixels(usize),
+
+    /// There's no golden image at this path (set `BANANAGRAPH_BLESS` to write one)
+    Missing(PathBuf),
+
+    /// Couldn't read or write one of the images
+    Image(image::ImageError)
+}
+
+impl Snapshot {
+    /// Create a snapshot renderer with a given size in pixels; this is both the physical and the
+    /// logical size, so one sprite pixel is one image pixel.
+    pub fn new(size: impl Into<Vector2<u32>>) -> Self {
+        let size = size.into();
+        Self { wrapper: pollster::block_on(GpuWrapper::headless(size, size)) }
+    }
+
+    /// Render the sprites into an image, exactly as `redraw` would draw them to a window
+    pub fn render<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, sprites: I) -> RgbaImage {
+        let (image, _ids) = self.wrapper.render_to_image(sprites)
+            .expect("Snapshot wrapper is always headless")
+            .expect("Failed to read back the rendered frame");
+        image
+    }
+
+    /// Render the sprites and compare them to the golden image at `path`. Every channel of every
+    /// pixel has to be within `tolerance` of the golden image. On a mismatch this writes the
+    /// rendered image next to the golden one (as `name.actual.png`) along with a diff image
+    /// (`name.diff.png`) showing the mismatched pixels in red.
+    pub fn compare<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, path: impl AsRef<Path>, sprites: I, tolerance: u8) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let actual = self.render(sprites);
+
+        if std::env::var_os("BANANAGRAPH_BLESS").is_some() {
+            return actual.save(path).map_err(SnapshotError::Image)
+        }
+        if !path.exists() {
+            return Err(SnapshotError::Missing(path.to_path_buf()))
+        }
+
+        let golden = image::open(path).map_err(SnapshotError::Image)?.to_rgba8();
+        let result = compare_images(&golden, &actual, tolerance);
+
+        if let Err(SnapshotError::Pixels(_)) = result {
+            actual.save(path.with_extension("actual.png")).map_err(SnapshotError::Image)?;
+            diff_image(&golden, &actual, tolerance).save(path.with_extension("diff.png")).map_err(SnapshotError::Image)?;
+        }
+        result
+    }
+
+    /// Like `compare` but panics on a mismatch, for use in tests
+    pub fn assert_matches<I: IntoIterator<Item=S>, S: AsRef<Sprite>>(&self, path: impl AsRef<Path>, sprites: I, tolerance: u8) {
+        let path = path.as_ref();
+        if let Err(err) = self.compare(path, sprites, tolerance) {
+            panic!("Snapshot {} doesn't match: {:?}", path.display(), err)
+        }
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = GpuWrapper<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.wrapper
+    }
+}
+
+impl DerefMut for Snapshot {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.wrapper
+    }
+}
+
+/// Whether two pixels are the same within a tolerance, per channel
+fn pixels_match(a: &Rgba<u8>, b: &Rgba<u8>, tolerance: u8) -> bool {
+    a.0.iter().zip(b.0.iter()).all(|(a, b)| a.abs_diff(*b) <= tolerance)
+}
+
+/// Compare two images, returning how many pixels are off by more than `tolerance`, if any
+pub fn compare_images(golden: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Result<(), SnapshotError> {
+    if golden.dimensions() != actual.dimensions() {
+        return Err(SnapshotError::Size(golden.dimensions().into(), actual.dimensions().into()))
+    }
+
+    let mismatched = golden.pixels().zip(actual.pixels()).filter(|(a, b)| !pixels_match(a, b, tolerance)).count();
+    if mismatched == 0 {
+        Ok(())
+    } else {
+        Err(SnapshotError::Pixels(mismatched))
+    }
+}
+
+/// Create an image showing where two (same-sized) images differ: mismatched pixels are solid red,
+/// and matching ones are a dimmed copy of the golden image, for context
+pub fn diff_image(golden: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> RgbaImage {
+    RgbaImage::from_fn(golden.width(), golden.height(), |x, y| {
+        let (a, b) = (golden.get_pixel(x, y), actual.get_pixel(x, y));
+        if pixels_match(a, b, tolerance) {
+            let [r, g, b, _] = a.0;
+            Rgba([r / 4, g / 4, b / 4, 0xff])
+        } else {
+            Rgba([0xff, 0, 0, 0xff])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_images() {
+        let golden = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 0xff]));
+        let mut actual = golden.clone();
+        actual.put_pixel(1, 2, Rgba([12, 20, 30, 0xff]));
+        assert!(compare_images(&golden, &actual, 2).is_ok());
+
+        actual.put_pixel(3, 3, Rgba([0, 0, 0, 0xff]));
+        assert!(matches!(compare_images(&golden, &actual, 2), Err(SnapshotError::Pixels(1))));
+        assert!(matches!(compare_images(&golden, &RgbaImage::new(2, 2), 2), Err(SnapshotError::Size(..))));
+
+        // A golden image that was never written is a failure, not a pass
+        let snapshot = Snapshot::new((4, 4));
+        let missing = std::env::temp_dir().join("bananagraph-missing-golden.png");
+        if std::env::var_os("BANANAGRAPH_BLESS").is_none() {
+            assert!(matches!(snapshot.compare(&missing, [] as [Sprite; 0], 0), Err(SnapshotError::Missing(_))));
+            assert!(!missing.exists());
+        }
+
+        let diff = diff_image(&golden, &actual, 2);
+        assert_eq!(diff.get_pixel(3, 3).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(diff.get_pixel(0, 0).0, [2, 5, 7, 0xff]);
+    }
+
+    #[test]
+    fn test_z_order() {
+        let mut snapshot = Snapshot::new((32, 32));
+        snapshot.add_texture_from_array([0xff, 0, 0, 0xff].repeat(16 * 16), 16, None);
+        snapshot.add_texture_from_array([0, 0, 0xff, 0xff].repeat(16 * 16), 16, None);
+
+        // The blue square is on top of the red one, even though it's first in the list
+        let dc = crate::DrawingContext::new((32.0, 32.0));
+        snapshot.assert_matches(concat!(env!("CARGO_MANIFEST_DIR"), "/src/snapshots/z_order.png"), [
+            dc.place(Sprite::new((0, 0), (16, 16)).with_layer(1).with_z(0.1), (12.0, 12.0)),
+            dc.place(Sprite::new((0, 0), (16, 16)).with_z(0.5), (4.0, 4.0)),
+        ], 0);
+    }
+}
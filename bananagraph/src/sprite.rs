@@ -1,4 +1,5 @@
-use cgmath::{ElementWise, Matrix3, Point2, Rad, SquareMatrix, Vector2, Vector4};
+use cgmath::{Matrix3, Point2, Rad, SquareMatrix, Vector2, Vector4};
+use crate::material::MaterialId;
 
 pub type SpriteId = u32;
 
@@ -20,7 +21,46 @@ pub struct Sprite {
     pub origin: Point2<u32>,
     pub layer: u32,
     pub tint: Vector4<f32>,
-    pub id: SpriteId
+    pub id: SpriteId,
+    pub blend: Option<BlendMode>,
+
+    /// A custom shader to draw this with, instead of the usual one; see `GpuWrapper::add_material`
+    pub material: Option<MaterialId>
+}
+
+/// How a translucent sprite combines with whatever's drawn behind it. Sprites without a blend
+/// mode are opaque: they're drawn first, and a pixel is either entirely the sprite or (at alpha 0)
+/// entirely not. Sprites with one are drawn afterward, back to front, and blended like this:
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BlendMode {
+    /// Normal transparency: mix the sprite with the background by its alpha
+    Alpha,
+
+    /// Add the sprite's color (scaled by its alpha) to the background, for glows and lights
+    Additive,
+
+    /// Multiply the background by the sprite's color, for shadows and fog
+    Multiply
+}
+
+impl BlendMode {
+    pub(crate) const ALL: [BlendMode; 3] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply];
+
+    pub(crate) fn blend_state(self) -> wgpu::BlendState {
+        use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState};
+
+        match self {
+            BlendMode::Alpha => BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => BlendState {
+                color: BlendComponent { src_factor: BlendFactor::SrcAlpha, dst_factor: BlendFactor::One, operation: BlendOperation::Add },
+                alpha: BlendComponent { src_factor: BlendFactor::Zero, dst_factor: BlendFactor::One, operation: BlendOperation::Add },
+            },
+            BlendMode::Multiply => BlendState {
+                color: BlendComponent { src_factor: BlendFactor::Dst, dst_factor: BlendFactor::Zero, operation: BlendOperation::Add },
+                alpha: BlendComponent { src_factor: BlendFactor::Zero, dst_factor: BlendFactor::One, operation: BlendOperation::Add },
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug, bytemuck::Zeroable, bytemuck::Pod)]
@@ -33,7 +73,8 @@ pub(crate) struct RawSprite {
     size: [f32; 2],
     z: f32,
     id: u32,
-    tint: [f32; 4]
+    tint: [f32; 4],
+    layer: u32
 }
 
 impl Sprite {
@@ -46,20 +87,19 @@ impl Sprite {
             origin: origin.into(),
             size: size.into(),
             tint: (1.0, 1.0, 1.0, 1.0).into(),
-            id: 0
+            id: 0,
+            blend: None,
+            material: None
         }
     }
 
-    /// Convert a sprite into a `RawSprite` which can be loaded into an instance buffer and sent to the GPU
-    pub(crate) fn into_raw(self, texture_size: impl Into<Vector2<u32>>) -> RawSprite {
+    /// Convert a sprite into a `RawSprite` which can be loaded into an instance buffer and sent to the GPU.
+    /// The origin and size stay in texels; the shader divides them by the spritesheet size, so a
+    /// `RawSprite` stays valid even if adding a texture makes the spritesheet array bigger.
+    pub(crate) fn into_raw(self) -> RawSprite {
         let [transform_i, transform_j, transform_k] = self.transform.into();
-        let fsize = Point2::new(self.size.x as f32, self.size.y as f32);
-        let forigin = Point2::new(self.origin.x as f32, self.origin.y as f32);
-        let texture_size: Vector2<u32> = texture_size.into();
-        let ftsize = Point2::new(texture_size.x as f32, texture_size.y as f32);
-
-        let origin: [f32; 2] = forigin.div_element_wise(ftsize).into();
-        let size: [f32; 2] = fsize.div_element_wise(ftsize).into();
+        let origin = [self.origin.x as f32, self.origin.y as f32];
+        let size = [self.size.x as f32, self.size.y as f32];
 
         RawSprite {
             transform_i,
@@ -70,6 +110,7 @@ impl Sprite {
             z: self.z,
             id: self.id,
             tint: self.tint.into(),
+            layer: self.layer,
         }
     }
 
@@ -140,6 +181,24 @@ impl Sprite {
         }
     }
     
+    /// Returns a sprite drawn translucently, blending with the sprites behind it instead of
+    /// covering them; see `BlendMode`
+    pub fn with_blend(self, blend: BlendMode) -> Self {
+        Self {
+            blend: Some(blend),
+            ..self
+        }
+    }
+
+    /// Returns a sprite drawn with a material (see `GpuWrapper::add_material`). The id buffer sees
+    /// what the material does to its alpha, but `HitTester` doesn't.
+    pub fn with_material(self, material: MaterialId) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
     /// Returns a sprite with the given layer
     pub fn with_layer(self, layer: u32) -> Self {
         Self {
@@ -204,6 +263,11 @@ impl RawSprite {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: 76,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
         ]
         }
     }
@@ -0,0 +1,46 @@
+use cgmath::Matrix3;
+use wgpu::Buffer;
+use crate::gpu_wrapper::Run;
+
+/// A list of sprites that's been sorted and uploaded to the GPU once, so it can be drawn every
+/// frame without doing that again. Create one with `GpuWrapper::create_batch`, change it with
+/// `GpuWrapper::update_batch`, and draw it (alongside any other sprites) with
+/// `GpuWrapper::redraw_batches`:
+/// ```no_run
+/// # use bananagraph::{GpuWrapper, Sprite};
+/// # fn f(wrapper: &GpuWrapper, map: Vec<Sprite>, player: Sprite) {
+/// let terrain = wrapper.create_batch(map);
+/// loop {
+///     wrapper.redraw_batches(&[&terrain], [player]);
+/// }
+/// # }
+/// ```
+pub struct SpriteBatch {
+    pub(crate) buffer: Buffer,
+    pub(crate) runs: Vec<Run>,
+    pub(crate) transform: Matrix3<f32>
+}
+
+impl SpriteBatch {
+    /// How many sprites are in the batch
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The transform applied on top of every sprite in the batch, like `DrawingContext::apply`
+    /// does; the identity unless it's been set
+    pub fn transform(&self) -> Matrix3<f32> {
+        self.transform
+    }
+
+    /// Move (or scale, or rotate) the whole batch. This happens on the GPU, when the batch is
+    /// drawn, so the sprites in it don't have to be placed or uploaded again: good for scrolling
+    /// a map around.
+    pub fn set_transform(&mut self, transform: impl Into<Matrix3<f32>>) {
+        self.transform = transform.into()
+    }
+}
@@ -1,6 +1,6 @@
-use cgmath::Vector2;
+use cgmath::{Point2, Vector2};
 use wgpu::{Device, Extent3d, Queue, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView};
-use image::{ImageError, RgbaImage};
+use image::RgbaImage;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -9,63 +9,80 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: Option<&str>) -> Result<Self, ImageError> {
-        let img = image::load_from_memory(bytes)?;
-        Ok(Self::from_image(device, queue, &img.to_rgba8(), label))
+    /// Turn raw RGBA data into an image `width` pixels wide, as long as it's a whole number of rows
+    pub fn rgba_image(bytes: Vec<u8>, width: u32) -> crate::Result<RgbaImage> {
+        let len = bytes.len();
+        RgbaImage::from_raw(width, Self::rgba_height(len, width)?, bytes).ok_or(crate::Error::ImageSize(len, width))
     }
 
-    pub fn from_array(device: &Device, queue: &Queue, bytes: Vec<u8>, width: u32, label: Option<&str>) -> Result<Self, ImageError> {
-        let img: RgbaImage = RgbaImage::from_raw(width, bytes.len() as u32 / 4 / width, bytes).unwrap();
-        Ok(Self::from_image(device, queue, &img, label))
+    /// How many rows of `width` RGBA pixels `len` bytes is, if it's a whole number of them
+    pub fn rgba_height(len: usize, width: u32) -> crate::Result<u32> {
+        let row = width as usize * 4;
+        if row == 0 || !len.is_multiple_of(row) {
+            return Err(crate::Error::ImageSize(len, width))
+        }
+        Ok((len / row) as u32)
     }
 
-    pub fn from_image(device: &Device, queue: &Queue, img: &RgbaImage, label: Option<&str>) -> Self {
-        let diffuse_rgba = img;
+    /// Copy an image into part of one layer of this texture (which is probably the spritesheet
+    /// array), with its top-left corner at `origin`
+    pub fn write_image(&self, queue: &Queue, layer: u32, origin: Point2<u32>, img: &RgbaImage) {
         let dimensions = img.dimensions();
 
-        let size = Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-
-        let texture = device.create_texture(&TextureDescriptor {
-            label,
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
-        let view = texture.create_view(&Default::default());
-
         queue.write_texture(
             TexelCopyTextureInfo {
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: layer },
                 aspect: wgpu::TextureAspect::All,
             },
-            diffuse_rgba,
+            img,
             TexelCopyBufferLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * dimensions.0),
                 rows_per_image: Some(dimensions.1),
             },
-            size,
+            Extent3d {
+                width: dimensions.0,
+                height: dimensions.1,
+                depth_or_array_layers: 1,
+            },
         );
+    }
+
+    /// Create an empty (transparent) texture array, with `layers` layers of the given size, for
+    /// spritesheets to be written into. It can be copied out of too, into a bigger one. This always has at least two layers: the GL backend decides whether a
+    /// texture is an array or a plain 2d texture by how many layers it has, and a plain one can't
+    /// be bound as an array.
+    pub fn create_array(device: &Device, size: Vector2<u32>, layers: u32) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("spritesheet array"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: layers.max(2),
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
 
-        Self { texture, view, size: Vector2::new(dimensions.0, dimensions.1) }
+        Self { texture, view, size }
     }
 
     /// Create a texture the size of the surface, with a given format and label
-    pub fn generic_texture(device: &Device, config: &wgpu::SurfaceConfiguration, label: Option<&str>, format: TextureFormat, usage: TextureUsages) -> Self {
+    pub fn generic_texture(device: &Device, size: Vector2<u32>, label: Option<&str>, format: TextureFormat, usage: TextureUsages) -> Self {
         let size = Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: size.x.max(1),
+            height: size.y.max(1),
             depth_or_array_layers: 1,
         };
 
@@ -87,12 +104,22 @@ impl Texture {
     }
 
     /// Create a texture suitable for use as a depth texture
-    pub fn create_depth_texture(device: &Device, config: &wgpu::SurfaceConfiguration) -> Self {
-        Self::generic_texture(device, config, Some("depth texture"), TextureFormat::Depth32Float, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+    pub fn create_depth_texture(device: &Device, size: Vector2<u32>) -> Self {
+        Self::generic_texture(device, size, Some("depth texture"), TextureFormat::Depth32Float, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
     }
 
     /// Create a texture for the ID shader to use as its output
-    pub fn create_id_texture(device: &Device, config: &wgpu::SurfaceConfiguration) -> Self {
-        Self::generic_texture(device, config, Some("id texture"), TextureFormat::R32Uint, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC)
+    pub fn create_id_texture(device: &Device, size: Vector2<u32>) -> Self {
+        Self::generic_texture(device, size, Some("id texture"), TextureFormat::Rg32Uint, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC)
+    }
+
+    /// Create an RGBA texture for a `RenderLayer` to be drawn into, on its way to the spritesheet array
+    pub fn create_render_layer(device: &Device, size: Vector2<u32>) -> Self {
+        Self::generic_texture(device, size, Some("render layer"), TextureFormat::Rgba8Unorm, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC)
+    }
+
+    /// Create a texture for the render shader to draw into when there's no window surface
+    pub fn create_offscreen_texture(device: &Device, size: Vector2<u32>, format: TextureFormat) -> Self {
+        Self::generic_texture(device, size, Some("offscreen texture"), format, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC)
     }
 }
\ No newline at end of file
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use cgmath::Point2;
+use crate::{Dir, MousePos, SpriteId, WindowEventHandler};
+
+/// How far (in physical pixels) a finger can wander and still count as holding still, for taps
+/// and long presses
+const TAP_SLOP: f64 = 10.0;
+
+/// How far a finger has to move before lifting for it to be a swipe
+const SWIPE_DISTANCE: f64 = 30.0;
+
+/// How long a finger has to be held still to be a long press rather than a tap
+const LONG_PRESS: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TouchPhase {
+    Start,
+    Move,
+    End,
+
+    /// The system took the touch away from us, for a notification or a system gesture or
+    /// something. Treat it as undoing whatever it was doing, rather than finishing it.
+    Cancel
+}
+
+/// A finger touching (or moving on, or lifting off of) the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Touch {
+    /// Tells apart different fingers touching at once. The same finger keeps the same id from its
+    /// start to its end, but ids can be reused after that.
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub position: MousePos,
+
+    /// The sprite under the finger right now
+    pub entity: Option<SpriteId>
+}
+
+/// Higher-level touch gestures, sent to `WindowEventHandler::gesture` if the handler wants them
+/// (see `WindowEventHandler::recognize_gestures`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gesture {
+    /// A quick touch without moving
+    Tap { position: MousePos, entity: Option<SpriteId> },
+
+    /// A touch held without moving. This is sent as soon as it's been held long enough, not when
+    /// the finger lifts.
+    LongPress { position: MousePos, entity: Option<SpriteId> },
+
+    /// A finger moved some distance and lifted, mostly in this direction. The entity is the
+    /// one where the swipe started.
+    Swipe { dir: Dir, start: MousePos, entity: Option<SpriteId> },
+
+    /// Two fingers moved closer together or farther apart. `scale` is the ratio of the distance
+    /// between them now to what it was at the last pinch event, so >1 is spreading apart (zoom in)
+    Pinch { scale: f64, center: MousePos }
+}
+
+/// A finger we're watching to see what gesture it turns into
+#[derive(Copy, Clone)]
+struct Tracked {
+    start: MousePos,
+    start_time: Duration,
+    last: MousePos,
+    entity: Option<SpriteId>,
+
+    /// Once a finger is part of a long press or a pinch, lifting it doesn't also make a tap or swipe
+    used: bool
+}
+
+impl Tracked {
+    fn distance_moved(&self) -> f64 {
+        distance(self.start.physical, self.last.physical)
+    }
+}
+
+/// Turns raw `Touch`es into `Gesture`s. Times are passed in, as the time since any starting
+/// point, so this works the same with the desktop and browser clocks.
+#[derive(Default)]
+pub struct GestureRecognizer {
+    touches: HashMap<u64, Tracked>,
+
+    /// The distance between the two fingers last time we sent a pinch
+    pinch_distance: Option<f64>
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a touch event, and return any gestures it finishes
+    pub fn touch(&mut self, touch: &Touch, now: Duration) -> Option<Gesture> {
+        match touch.phase {
+            TouchPhase::Start => {
+                self.touches.insert(touch.id, Tracked { start: touch.position, start_time: now, last: touch.position, entity: touch.entity, used: false });
+                self.pinch_distance = None;
+
+                // A second finger down means this is a pinch, not a tap or swipe
+                if self.touches.len() > 1 {
+                    self.touches.values_mut().for_each(|t| t.used = true)
+                }
+                None
+            }
+
+            TouchPhase::Move => {
+                self.touches.get_mut(&touch.id)?.last = touch.position;
+                self.pinch()
+            }
+
+            TouchPhase::End => {
+                let tracked = self.touches.remove(&touch.id)?;
+                let tracked = Tracked { last: touch.position, ..tracked };
+                self.pinch_distance = None;
+                if tracked.used {
+                    return None
+                }
+
+                let moved = tracked.distance_moved();
+                if moved < TAP_SLOP {
+                    Some(Gesture::Tap { position: tracked.start, entity: tracked.entity })
+                } else if moved >= SWIPE_DISTANCE {
+                    let (dx, dy) = (tracked.last.physical.x - tracked.start.physical.x, tracked.last.physical.y - tracked.start.physical.y);
+                    let dir = match (dx.abs() > dy.abs(), dx > 0.0, dy > 0.0) {
+                        (true, true, _) => Dir::East,
+                        (true, false, _) => Dir::West,
+                        (false, _, true) => Dir::South,
+                        (false, _, false) => Dir::North
+                    };
+                    Some(Gesture::Swipe { dir, start: tracked.start, entity: tracked.entity })
+                } else {
+                    None
+                }
+            }
+
+            TouchPhase::Cancel => {
+                self.touches.remove(&touch.id);
+                self.pinch_distance = None;
+                None
+            }
+        }
+    }
+
+    /// Check for gestures that happen by waiting rather than by touching: long presses. Call this
+    /// regularly (every tick is fine).
+    pub fn update(&mut self, now: Duration) -> Option<Gesture> {
+        if self.touches.len() != 1 { return None }
+        let tracked = self.touches.values_mut().next()?;
+
+        if !tracked.used && tracked.distance_moved() < TAP_SLOP && now.saturating_sub(tracked.start_time) >= LONG_PRESS {
+            tracked.used = true;
+            Some(Gesture::LongPress { position: tracked.start, entity: tracked.entity })
+        } else {
+            None
+        }
+    }
+
+    /// If exactly two fingers are down, how much they've spread since last time
+    fn pinch(&mut self) -> Option<Gesture> {
+        let [a, b] = self.touches.values().collect::<Vec<_>>()[..] else { return None };
+        let dist = distance(a.last.physical, b.last.physical);
+        let center = MousePos {
+            physical: midpoint(a.last.physical, b.last.physical),
+            logical: midpoint(a.last.logical, b.last.logical)
+        };
+
+        let last = self.pinch_distance.replace(dist);
+        match last {
+            Some(last) if last > 0.0 && dist != last => Some(Gesture::Pinch { scale: dist / last, center }),
+            _ => None
+        }
+    }
+}
+
+fn distance(a: Point2<f64>, b: Point2<f64>) -> f64 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+fn midpoint(a: Point2<f64>, b: Point2<f64>) -> Point2<f64> {
+    Point2::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0)
+}
+
+/// Send a touch to a handler, and through the gesture recognizer if the handler wants gestures.
+/// Both event loops do exactly this, and a game with its own browser wrapper should too.
+pub fn handle_touch<H: WindowEventHandler + ?Sized>(handler: &mut H, gestures: &mut GestureRecognizer, touch: Touch, now: Duration) {
+    handler.touch(touch);
+    if handler.recognize_gestures() {
+        if let Some(gesture) = gestures.touch(&touch, now) {
+            handler.gesture(gesture)
+        }
+    }
+}
+
+/// Check for long presses, for the handler if it wants them. Call this every frame.
+pub fn update_gestures<H: WindowEventHandler + ?Sized>(handler: &mut H, gestures: &mut GestureRecognizer, now: Duration) {
+    if handler.recognize_gestures() {
+        if let Some(gesture) = gestures.update(now) {
+            handler.gesture(gesture)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f64, y: f64) -> Touch {
+        Touch { id, phase, position: MousePos { physical: (x, y).into(), logical: (x, y).into() }, entity: Some(id as SpriteId) }
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_tap_and_swipe() {
+        let mut gestures = GestureRecognizer::new();
+        assert_eq!(gestures.touch(&touch(1, TouchPhase::Start, 10.0, 10.0), ms(0)), None);
+        assert_eq!(gestures.touch(&touch(1, TouchPhase::End, 12.0, 10.0), ms(100)), Some(Gesture::Tap { position: touch(1, TouchPhase::Start, 10.0, 10.0).position, entity: Some(1) }));
+
+        gestures.touch(&touch(2, TouchPhase::Start, 100.0, 100.0), ms(200));
+        gestures.touch(&touch(2, TouchPhase::Move, 90.0, 60.0), ms(250));
+        let swipe = gestures.touch(&touch(2, TouchPhase::End, 90.0, 40.0), ms(300));
+        assert!(matches!(swipe, Some(Gesture::Swipe { dir: Dir::North, entity: Some(2), .. })));
+
+        // Cancelled touches don't do anything
+        gestures.touch(&touch(3, TouchPhase::Start, 10.0, 10.0), ms(400));
+        assert_eq!(gestures.touch(&touch(3, TouchPhase::Cancel, 10.0, 10.0), ms(450)), None);
+        assert_eq!(gestures.touch(&touch(3, TouchPhase::End, 10.0, 10.0), ms(450)), None);
+    }
+
+    #[test]
+    fn test_long_press() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.touch(&touch(1, TouchPhase::Start, 10.0, 10.0), ms(0));
+        assert_eq!(gestures.update(ms(400)), None);
+        assert!(matches!(gestures.update(ms(500)), Some(Gesture::LongPress { entity: Some(1), .. })));
+        assert_eq!(gestures.update(ms(600)), None);
+
+        // And then lifting it isn't also a tap
+        assert_eq!(gestures.touch(&touch(1, TouchPhase::End, 10.0, 10.0), ms(700)), None);
+    }
+
+    #[test]
+    fn test_pinch() {
+        let mut gestures = GestureRecognizer::new();
+        gestures.touch(&touch(1, TouchPhase::Start, 0.0, 0.0), ms(0));
+        gestures.touch(&touch(2, TouchPhase::Start, 10.0, 0.0), ms(0));
+        assert_eq!(gestures.touch(&touch(2, TouchPhase::Move, 10.0, 0.0), ms(10)), None);
+
+        let pinch = gestures.touch(&touch(2, TouchPhase::Move, 20.0, 0.0), ms(20));
+        let Some(Gesture::Pinch { scale, center }) = pinch else { panic!("expected a pinch, got {:?}", pinch) };
+        assert_eq!(scale, 2.0);
+        assert_eq!(center.physical, Point2::new(10.0, 0.0));
+
+        // Neither finger taps when they lift
+        assert_eq!(gestures.touch(&touch(2, TouchPhase::End, 20.0, 0.0), ms(30)), None);
+        assert_eq!(gestures.touch(&touch(1, TouchPhase::End, 0.0, 0.0), ms(30)), None);
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 use cgmath::{Point2, Vector2};
 use image::{DynamicImage, GenericImage, GenericImageView};
-use crate::{DrawingContext, GpuWrapper, Sprite};
+use crate::{AtlasRegion, DrawingContext, GpuWrapper, Sprite};
 
 pub struct TypefaceBuilder {
     /// The image data, used for automatically adding glyphs
@@ -44,12 +44,25 @@ pub struct Glyph {
 /// A trait to allow us to create TypefaceBuilders without a real GPU wrapper (tests)
 pub trait AddTexture {
     fn add_texture_from_array(&mut self, bytes: Vec<u8>, width: u32, name: Option<&str>) -> u32;
+
+    /// Add an image to the texture atlas, if there is one. By default this just gives the image
+    /// a layer of its own. Like `GpuWrapper::add_image_from_array`, this panics if the data isn't
+    /// a whole number of rows.
+    fn add_image_from_array(&mut self, bytes: Vec<u8>, width: u32) -> AtlasRegion {
+        let height = crate::texture::Texture::rgba_height(bytes.len(), width).unwrap();
+        let layer = self.add_texture_from_array(bytes, width, None);
+        AtlasRegion { layer, origin: (0, 0).into(), size: (width, height).into() }
+    }
 }
 
 impl AddTexture for GpuWrapper<'_> {
     fn add_texture_from_array(&mut self, bytes: Vec<u8>, width: u32, name: Option<&str>) -> u32 {
         self.add_texture_from_array(bytes, width, name)
     }
+
+    fn add_image_from_array(&mut self, bytes: Vec<u8>, width: u32) -> AtlasRegion {
+        self.add_image_from_array(bytes, width)
+    }
 }
 
 impl TypefaceBuilder {
@@ -145,8 +158,8 @@ impl TypefaceBuilder {
     }
 
     pub fn into_typeface(self, gpu_wrapper: &mut impl AddTexture) -> Typeface {
-        let layer = gpu_wrapper.add_texture_from_array(Vec::from(self.image.as_bytes()), self.image.width(), None);
-        let glyphs = self.glyphs.into_iter().map(|(ch, glyph)| (ch, glyph.with_layer(layer))).collect();
+        let region = gpu_wrapper.add_image_from_array(Vec::from(self.image.as_bytes()), self.image.width());
+        let glyphs = self.glyphs.into_iter().map(|(ch, glyph)| (ch, glyph.in_region(region))).collect();
         Typeface {
             glyphs,
             height: self.height
@@ -196,9 +209,10 @@ impl Typeface {
 }
 
 impl Glyph {
-    pub(crate) fn with_layer(self, layer: u32) -> Self {
+    /// Move the glyph's sprite to wherever the typeface's image was put in the atlas
+    pub(crate) fn in_region(self, region: AtlasRegion) -> Self {
         Self {
-            sprite: self.sprite.with_layer(layer),
+            sprite: region.sprite(self.sprite.origin, self.sprite.size),
             ..self
         }
     }
@@ -228,6 +242,14 @@ mod tests {
         assert_eq!(g.sprite.size, (7, 15).into());
     }
 
+    #[test]
+    fn test_default_add_image() {
+        let region = TestGpu {}.add_image_from_array(vec![0; 4 * 6], 3);
+        assert_eq!(region.size, (3, 2).into());
+        assert!(crate::texture::Texture::rgba_height(16, 0).is_err());
+        assert!(crate::texture::Texture::rgba_height(20, 2).is_err());
+    }
+
     #[test]
     fn test_add_glyphs() {
         let mut builder = TypefaceBuilder::new(include_bytes!("Curly-Girly.png"), [0, 0, 0, 0xff], 4, 7);
@@ -249,4 +271,15 @@ mod tests {
         let sprites = tf.print(dc, (0.0, 50.0), 0.0,"foo");
         assert_eq!(sprites.len(), 3);
     }
+
+    #[test]
+    fn test_print_snapshot() {
+        let mut snapshot = crate::Snapshot::new((64, 32));
+        let mut builder = TypefaceBuilder::new(include_bytes!("Curly-Girly.png"), [0, 0, 0, 0xff], 4, 7);
+        builder.add_glyphs("abcdefgh", (7, 15), (1, 65), Some(1));
+        builder.add_glyphs("ijklmnop", (7, 15), (1, 81), Some(1));
+        let tf: Typeface = builder.into_typeface(&mut *snapshot);
+        let sprites = tf.print(DrawingContext::new((64.0, 32.0)), (2.0, 12.0), 0.0, "hello\nfood");
+        snapshot.assert_matches(concat!(env!("CARGO_MANIFEST_DIR"), "/src/snapshots/print.png"), sprites, 0);
+    }
 }
\ No newline at end of file
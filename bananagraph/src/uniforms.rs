@@ -0,0 +1,23 @@
+use wgpu::{Buffer, BufferUsages, Device, Queue};
+
+/// Make a buffer for a shader's uniforms, big enough for `size` bytes of them. Materials and
+/// post-processing passes both have one of these.
+pub(crate) fn uniform_buffer(device: &Device, label: &str, size: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        // Uniform buffers are read 16 bytes at a time, and a shader might not have any at all
+        size: size.max(1).next_multiple_of(16) as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Write uniforms to the start of a buffer made by `uniform_buffer`, or as much of them as fits
+pub(crate) fn write_uniforms(queue: &Queue, buffer: &Buffer, uniforms: &[u8]) {
+    // Buffer writes have to be whole words
+    let mut bytes = uniforms[..uniforms.len().min(buffer.size() as usize)].to_vec();
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    if !bytes.is_empty() {
+        queue.write_buffer(buffer, 0, &bytes)
+    }
+}
@@ -0,0 +1,136 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Which mouse pointer to show over the window. These are the common ones that both browsers
+/// and desktops have.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Cursor {
+    #[default]
+    Default,
+
+    /// The pointing hand, for things that can be clicked
+    Pointer,
+    Crosshair,
+    Text,
+    Move,
+    Grab,
+    Grabbing,
+    Wait,
+    NotAllowed
+}
+
+impl Cursor {
+    /// The CSS name for this cursor, which is also what winit calls it
+    pub fn css_name(&self) -> &'static str {
+        match self {
+            Cursor::Default => "default",
+            Cursor::Pointer => "pointer",
+            Cursor::Crosshair => "crosshair",
+            Cursor::Text => "text",
+            Cursor::Move => "move",
+            Cursor::Grab => "grab",
+            Cursor::Grabbing => "grabbing",
+            Cursor::Wait => "wait",
+            Cursor::NotAllowed => "not-allowed"
+        }
+    }
+}
+
+/// Something the game asked the window to do, waiting for the event loop to get to it
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum WindowCommand {
+    SetFullscreen(bool),
+    SetTitle(String),
+    SetCursor(Cursor),
+    SetCursorVisible(bool),
+    Exit
+}
+
+/// A handle to the window (or canvas) the game is running in, to change it while the game runs.
+/// The event loop gives one of these to `WindowEventHandler::window_handle` before `init`; keep it
+/// around and call it whenever. Requests are carried out by the event loop after the current
+/// event, not immediately.
+#[derive(Clone, Default)]
+pub struct WindowHandle {
+    commands: Rc<RefCell<Vec<WindowCommand>>>,
+
+    /// Whether the window is fullscreen, as of the last time the event loop looked
+    fullscreen: Rc<Cell<bool>>
+}
+
+impl WindowHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the window fill the screen (borderless, at the desktop's resolution), or not. In a
+    /// browser this only works from inside a click or key handler, because browsers only allow
+    /// going fullscreen in response to the user doing something.
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        self.push(WindowCommand::SetFullscreen(fullscreen))
+    }
+
+    pub fn toggle_fullscreen(&self) {
+        self.set_fullscreen(!self.is_fullscreen())
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen.get()
+    }
+
+    /// Change the window's title (or, in a browser, the page's)
+    pub fn set_title(&self, title: impl Into<String>) {
+        self.push(WindowCommand::SetTitle(title.into()))
+    }
+
+    /// Change the mouse pointer shown over the window
+    pub fn set_cursor(&self, cursor: Cursor) {
+        self.push(WindowCommand::SetCursor(cursor))
+    }
+
+    /// Hide the mouse pointer while it's over the window, for games that draw their own
+    pub fn set_cursor_visible(&self, visible: bool) {
+        self.push(WindowCommand::SetCursorVisible(visible))
+    }
+
+    /// Close the window and end the event loop, without asking `WindowEventHandler::exit`. A
+    /// browser tab can't be closed from inside it, so there this just stops ticking and drawing.
+    pub fn exit(&self) {
+        self.push(WindowCommand::Exit)
+    }
+
+    fn push(&self, command: WindowCommand) {
+        self.commands.borrow_mut().push(command)
+    }
+
+    /// All the requests made since last time, for the event loop to carry out
+    pub(crate) fn take_commands(&self) -> Vec<WindowCommand> {
+        std::mem::take(&mut self.commands.borrow_mut())
+    }
+
+    /// The event loop tells us when fullscreen changes, so `toggle_fullscreen` knows which way to go
+    pub(crate) fn set_is_fullscreen(&self, fullscreen: bool) {
+        self.fullscreen.set(fullscreen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        let handle = WindowHandle::new();
+        let game_copy = handle.clone();
+
+        game_copy.set_title("Hello");
+        game_copy.toggle_fullscreen();
+        assert_eq!(handle.take_commands(), vec![WindowCommand::SetTitle("Hello".into()), WindowCommand::SetFullscreen(true)]);
+        assert!(handle.take_commands().is_empty());
+
+        // It doesn't know it's fullscreen until the event loop says so
+        handle.set_is_fullscreen(true);
+        game_copy.toggle_fullscreen();
+        assert_eq!(handle.take_commands(), vec![WindowCommand::SetFullscreen(false)]);
+    }
+}
@@ -1,16 +1,18 @@
-use std::ops::Index;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use cgmath::{Point2, Vector2};
 use winit::application::ApplicationHandler;
 use winit::dpi::LogicalSize;
-use winit::error::EventLoopError;
-use winit::event::{KeyEvent, StartCause, WindowEvent};
+use winit::event::{MouseScrollDelta, StartCause, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow};
-use winit::keyboard::{Key, NamedKey};
-use winit::window::{Window, WindowAttributes, WindowId};
-use crate::{Dir, GpuWrapper, IdBuffer};
-use crate::event_handler::{Click, ElementState, MouseButton, WindowEventHandler};
+use winit::keyboard::{Key, KeyCode, ModifiersState, NamedKey, PhysicalKey};
+use winit::window::{CursorIcon, Fullscreen, Icon, Window, WindowAttributes, WindowId};
+use image::RgbaImage;
+use wgpu::PresentMode;
+use crate::{Cursor, DragTracker, GameLoop, GestureRecognizer, GpuWrapper, IdBuffer, SpriteId, Touch, TouchPhase, WindowHandle};
+use crate::window_handle::WindowCommand;
+use crate::touch::{handle_touch, update_gestures};
+use crate::event_handler::{Click, ElementState, KeyEvent, KeyState, Modifiers, MouseButton, MouseMove, MousePos, Scroll, ScrollDelta, WindowEventHandler};
 
 /// A struct that can impl ApplicationHandler for winit to send it events
 #[cfg(feature = "desktop")]
@@ -29,26 +31,97 @@ struct App<'a, H> {
     /// Attributes to create the window with, needed until we create the window in `resumed`
     attrs: WindowAttributes,
 
-    /// The event loop will tick at this frequency, calling `handler.tick` when this timer runs down
-    timer_length: Duration,
+    /// Things from the `WindowConfig` that are set on the window or wrapper after they're created
+    present_mode: PresentMode,
+    cursor_visible: bool,
+
+    /// Our copy of the handle the handler uses to change the window; we carry out its requests
+    handle: WindowHandle,
+
+    /// How often to tick and draw, from the handler. The event loop wakes up every `frame_length`
+    /// to run however many ticks have come due and then draw.
+    game_loop: GameLoop,
+
+    /// When we last woke up to run a frame, to know how much time has really passed
+    last_frame: Instant,
+
+    /// Whether the OS has put the app in the background (winit's `suspended`, until `resumed`)
+    backgrounded: bool,
+
+    /// Whether the window is completely hidden, by other windows or by being minimized
+    occluded: bool,
+
+    /// Whether either of those is true, so we shouldn't tick or draw
+    suspended: bool,
 
     /// There's no built-in facility for tracking the mouse position, so we'll just store it and update it
-    /// on mouse moved events
+    /// on mouse moved events. This is in physical pixels; we work out the logical position when we
+    /// send it to the handler, since that changes if the window resizes.
     mouse_pos: Point2<f64>,
 
+    /// Same deal for the modifier keys, which winit tells us about separately from key events
+    modifiers: Modifiers,
+
+    /// Turns button presses and mouse motion into drags
+    drag: DragTracker,
+
+    /// Turns touches into taps, swipes and so on, for handlers that want them
+    gestures: GestureRecognizer,
+
+    /// When the loop started, which the gesture recognizer uses as its clock
+    start: Instant,
+
     /// The id buffer created by bananagraph's render process
-    id_buffer: Option<IdBuffer>
+    id_buffer: Option<IdBuffer>,
+
+    /// Why we gave up and exited, if it wasn't the handler's idea, for `run_window_with` to return
+    error: Option<crate::Error>
+}
+
+#[cfg(feature = "desktop")]
+impl<H: WindowEventHandler> App<'_, H> {
+    /// Stop or start ticking and drawing, and tell the handler. Being hidden and being in the
+    /// background are the same thing to the handler, but they come and go separately: the window
+    /// can be uncovered while the app is still in the background, or the other way around. So we
+    /// track them both and only wake back up once neither applies.
+    fn update_suspended(&mut self, event_loop: &ActiveEventLoop) {
+        let suspended = self.backgrounded || self.occluded;
+        if suspended == self.suspended { return }
+        self.suspended = suspended;
+
+        if suspended {
+            self.handler.suspend();
+            event_loop.set_control_flow(ControlFlow::Wait)
+        } else {
+            // Start the clock over, so we don't try to catch up on the time we were away
+            self.last_frame = Instant::now();
+            self.handler.resume();
+            event_loop.set_control_flow(ControlFlow::WaitUntil(self.last_frame + self.game_loop.frame_length))
+        }
+    }
+
+    /// Where the mouse is, and the entity under it (from the last id buffer we drew)
+    fn mouse_state(&self) -> (MousePos, Option<SpriteId>) {
+        let mouse_pos = MousePos::new(self.mouse_pos, self.wrapper.as_ref().unwrap());
+        (mouse_pos, self.id_buffer.as_ref().and_then(|buf| buf.entity(mouse_pos)))
+    }
 }
 
 #[cfg(feature = "desktop")]
 impl<H: WindowEventHandler> ApplicationHandler for App<'_, H> {
-    // When the timer fires, redraw thw window and restart the timer (update will go here)
+    // When the timer fires, run the ticks that are due, redraw the window and restart the timer
     fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
         if let StartCause::ResumeTimeReached { .. } = cause {
-            self.handler.tick(self.timer_length);
+            if self.suspended { return }
+            let now = Instant::now();
+            let alpha = self.game_loop.frame(&mut self.handler, now - self.last_frame);
+            self.last_frame = now;
+
+            update_gestures(&mut self.handler, &mut self.gestures, self.start.elapsed());
             if self.handler.running() {
-                self.id_buffer = self.handler.redraw(self.mouse_pos, self.wrapper.as_ref().unwrap());
-                event_loop.set_control_flow(ControlFlow::WaitUntil(Instant::now() + self.timer_length));
+                let wrapper = self.wrapper.as_ref().unwrap();
+                self.id_buffer = self.handler.render(MousePos::new(self.mouse_pos, wrapper), wrapper, alpha);
+                event_loop.set_control_flow(ControlFlow::WaitUntil(now + self.game_loop.frame_length));
             } else {
                 event_loop.exit()
             }
@@ -56,58 +129,123 @@ impl<H: WindowEventHandler> ApplicationHandler for App<'_, H> {
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        // The first time this is the start of the program; after that, it's coming back from the
+        // background
+        if self.window.is_some() {
+            self.backgrounded = false;
+            return self.update_suspended(event_loop)
+        }
+
         let window = event_loop.create_window(self.attrs.clone()).unwrap();
         let window = Arc::new(window);
-        self.window = Some(window.clone());
         let physical_size = window.inner_size();
         let physical_size = Vector2::from((physical_size.width, physical_size.height));
         let logical_size = window.inner_size().to_logical(window.scale_factor());
         let logical_size = Vector2::from((logical_size.width, logical_size.height));
-        let mut wrapper = pollster::block_on(GpuWrapper::targeting(window.clone(), physical_size, logical_size));
+        let mut wrapper = match pollster::block_on(GpuWrapper::try_targeting(window.clone(), physical_size, logical_size)) {
+            Ok(wrapper) => wrapper,
+            Err(err) => {
+                // Without a GPU there's nothing we can do; the window closes as it's dropped
+                self.error = Some(err);
+                return event_loop.exit()
+            }
+        };
+        self.window = Some(window.clone());
+        wrapper.set_present_mode(self.present_mode);
+        wrapper.window_size = logical_size;
+        wrapper.scale_factor = window.scale_factor();
+        window.set_cursor_visible(self.cursor_visible);
+        self.handle.set_is_fullscreen(window.fullscreen().is_some());
+        self.handler.window_handle(self.handle.clone());
         self.handler.init(&mut wrapper);
         self.wrapper = Some(wrapper);
-        event_loop.set_control_flow(ControlFlow::WaitUntil(Instant::now() + self.timer_length))
+        self.last_frame = Instant::now();
+        event_loop.set_control_flow(ControlFlow::WaitUntil(self.last_frame + self.game_loop.frame_length))
+    }
+
+    fn suspended(&mut self, event_loop: &ActiveEventLoop) {
+        self.backgrounded = true;
+        self.update_suspended(event_loop)
+    }
+
+    // Once we've handled a batch of events, do whatever the handler asked the window to do
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(window) = &self.window else { return };
+
+        for command in self.handle.take_commands() {
+            match command {
+                WindowCommand::SetFullscreen(fullscreen) => window.set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None))),
+                WindowCommand::SetTitle(title) => window.set_title(&title),
+                WindowCommand::SetCursor(cursor) => window.set_cursor(to_winit_cursor(cursor)),
+                WindowCommand::SetCursorVisible(visible) => window.set_cursor_visible(visible),
+                WindowCommand::Exit => event_loop.exit()
+            }
+        }
+
+        // The user can also leave fullscreen without us (with the OS's own controls)
+        self.handle.set_is_fullscreen(window.fullscreen().is_some())
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _our_id: WindowId, event: WindowEvent) {
         match event {
             // Exit if we click the little x
-            WindowEvent::CloseRequested => {
-                if self.handler.exit() {
-                    event_loop.exit()
-                }
-            },
+            WindowEvent::CloseRequested if self.handler.exit() => event_loop.exit(),
 
             // Redraw if it's redrawing time
             WindowEvent::RedrawRequested => {
-                self.id_buffer = self.handler.redraw(self.mouse_pos, self.wrapper.as_ref().unwrap());
+                let wrapper = self.wrapper.as_ref().unwrap();
+                self.id_buffer = self.handler.render(MousePos::new(self.mouse_pos, wrapper), wrapper, self.game_loop.alpha());
             },
 
             // Resize if it's resizing time
             WindowEvent::Resized(new_size)  => {
-                self.wrapper.as_mut().unwrap().handle_resize((new_size.width, new_size.height).into())
+                let scale_factor = self.window.as_ref().unwrap().scale_factor();
+                let resize = self.wrapper.as_mut().unwrap().handle_window_resize((new_size.width, new_size.height).into(), scale_factor);
+                self.handler.resize(resize)
+            }
+
+            // This is usually followed by a Resized with the new physical size, but not if the
+            // physical size stays the same (which still changes the logical size), so resize here too
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.handler.scale_factor_changed(scale_factor);
+                let physical_size = self.window.as_ref().unwrap().inner_size();
+                let resize = self.wrapper.as_mut().unwrap().handle_window_resize((physical_size.width, physical_size.height).into(), scale_factor);
+                self.handler.resize(resize)
+            }
+
+            WindowEvent::Focused(focused) => self.handler.focus(focused),
+            WindowEvent::Occluded(occluded) => {
+                self.occluded = occluded;
+                self.update_suspended(event_loop)
             }
 
             // Update that the mouse moved if it did
             WindowEvent::CursorMoved { position: pos, device_id: _ } => {
                 self.mouse_pos = (pos.x, pos.y).into();
+                let (mouse_pos, entity) = self.mouse_state();
+                self.handler.mouse_move(MouseMove { mouse_pos, entity, modifiers: self.modifiers });
+
+                if let Some(drag) = self.drag.motion(mouse_pos, self.modifiers) {
+                    self.handler.drag(drag)
+                }
+            }
+
+            WindowEvent::CursorEntered { .. } => self.handler.mouse_enter(),
+            WindowEvent::CursorLeft { .. } => self.handler.mouse_leave(),
+
+            WindowEvent::MouseWheel { delta, .. } => {
+                // winit has positive y being scrolling up, so flip it
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => ScrollDelta::Lines((x as f64, -y as f64).into()),
+                    MouseScrollDelta::PixelDelta(pos) => ScrollDelta::Pixels((pos.x, -pos.y).into())
+                };
+                let (mouse_pos, entity) = self.mouse_state();
+                self.handler.scroll(Scroll { delta, mouse_pos, entity, modifiers: self.modifiers })
             }
 
             // Mouse clicked
             WindowEvent::MouseInput { device_id: _, state, button } => {
-                // Handle the buffer not being there, and 0 isn't a valid sprite id; 0 means there's
-                // no entity there / the sprite is unclickable.
-                let entity = match &self.id_buffer {
-                    None => None,
-                    Some(buf) => {
-                        let id = *buf.index(self.mouse_pos);
-                        if id == 0 {
-                            None
-                        } else {
-                            Some(id)
-                        }
-                    }
-                };
+                let (mouse_pos, entity) = self.mouse_state();
 
                 let state = match state {
                     winit::event::ElementState::Pressed => ElementState::Pressed,
@@ -117,24 +255,51 @@ impl<H: WindowEventHandler> ApplicationHandler for App<'_, H> {
                 let button = match button {
                     winit::event::MouseButton::Left => MouseButton::Left,
                     winit::event::MouseButton::Right => MouseButton::Right,
-                    _ => MouseButton::Left // TODO handle other buttons
+                    winit::event::MouseButton::Middle => MouseButton::Middle,
+                    winit::event::MouseButton::Back => MouseButton::Back,
+                    winit::event::MouseButton::Forward => MouseButton::Forward,
+                    winit::event::MouseButton::Other(n) => MouseButton::Other(n)
                 };
 
                 self.handler.click(Click {
                     button,
                     state,
                     entity,
-                    mouse_pos: self.mouse_pos,
+                    mouse_pos,
+                    modifiers: self.modifiers
                 });
+
+                match state {
+                    ElementState::Pressed => self.drag.press(button, mouse_pos, entity),
+                    ElementState::Released => {
+                        if let Some(drag) = self.drag.release(button, mouse_pos, self.modifiers) {
+                            self.handler.drag(drag)
+                        }
+                    }
+                }
+            }
+
+            WindowEvent::Touch(touch) => {
+                let phase = match touch.phase {
+                    winit::event::TouchPhase::Started => TouchPhase::Start,
+                    winit::event::TouchPhase::Moved => TouchPhase::Move,
+                    winit::event::TouchPhase::Ended => TouchPhase::End,
+                    winit::event::TouchPhase::Cancelled => TouchPhase::Cancel
+                };
+
+                let position = MousePos::new((touch.location.x, touch.location.y), self.wrapper.as_ref().unwrap());
+                let entity = self.id_buffer.as_ref().and_then(|buf| buf.entity(position));
+                let touch = Touch { id: touch.id, phase, position, entity };
+                handle_touch(&mut self.handler, &mut self.gestures, touch, self.start.elapsed())
+            }
+
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = to_banana_modifiers(modifiers.state())
             }
 
             // Key pressed or released
             WindowEvent::KeyboardInput { device_id: _, event, .. } => {
-                // We can ignore release events...
-                if event.state == winit::event::ElementState::Released { return }
-
-                let banana_event = to_banana_key(event);
-                banana_event.map(|e| self.handler.key(e));
+                self.handler.key(to_banana_key(event, self.modifiers))
             }
 
             _ => {} // toss the others
@@ -142,43 +307,424 @@ impl<H: WindowEventHandler> ApplicationHandler for App<'_, H> {
     }
 }
 
-fn to_banana_key(event: KeyEvent) -> Option<crate::event_handler::KeyEvent> {
-    use crate::event_handler::KeyEvent;
+/// Translate a winit key event into ours, so it comes out exactly the same as the browser's
+/// version of the same key would (see `JsGpuWrapper`)
+fn to_banana_key(event: winit::event::KeyEvent, modifiers: Modifiers) -> KeyEvent {
+    let logical = match &event.logical_key {
+        Key::Named(named) => to_banana_named_key(*named),
+        Key::Character(s) => crate::Key::from_name(s),
+        _ => crate::Key::Other
+    };
+
+    let physical = match event.physical_key {
+        PhysicalKey::Code(code) => key_code_name(code).to_string(),
+        PhysicalKey::Unidentified(_) => String::new()
+    };
+
+    let state = match (event.state, event.repeat) {
+        (winit::event::ElementState::Released, _) => KeyState::Released,
+        (winit::event::ElementState::Pressed, true) => KeyState::Repeat,
+        (winit::event::ElementState::Pressed, false) => KeyState::Pressed
+    };
+
+    KeyEvent { logical, physical, state, modifiers }
+}
 
-    match event.logical_key {
-        Key::Named(NamedKey::ArrowDown) => Some(KeyEvent::Arrow(Dir::South)),
-        Key::Named(NamedKey::ArrowUp) => Some(KeyEvent::Arrow(Dir::North)),
-        Key::Named(NamedKey::ArrowLeft) => Some(KeyEvent::Arrow(Dir::West)),
-        Key::Named(NamedKey::ArrowRight) => Some(KeyEvent::Arrow(Dir::East)),
-        Key::Named(NamedKey::Enter) => Some(KeyEvent::Enter),
-        Key::Named(NamedKey::Escape) => Some(KeyEvent::Esc),
-        Key::Character(s) => {
-            s.chars().next().map(|c| KeyEvent::Letter(c))
-        },
-        Key::Named(NamedKey::Space) => Some(KeyEvent::Letter(' ')),
-        _ => None
+fn to_banana_named_key(named: NamedKey) -> crate::Key {
+    match named {
+        NamedKey::Enter => crate::Key::Enter,
+        NamedKey::Escape => crate::Key::Escape,
+        NamedKey::Tab => crate::Key::Tab,
+        NamedKey::Backspace => crate::Key::Backspace,
+        NamedKey::Delete => crate::Key::Delete,
+        NamedKey::Insert => crate::Key::Insert,
+        NamedKey::ArrowUp => crate::Key::Arrow(crate::Dir::North),
+        NamedKey::ArrowDown => crate::Key::Arrow(crate::Dir::South),
+        NamedKey::ArrowRight => crate::Key::Arrow(crate::Dir::East),
+        NamedKey::ArrowLeft => crate::Key::Arrow(crate::Dir::West),
+        NamedKey::Home => crate::Key::Home,
+        NamedKey::End => crate::Key::End,
+        NamedKey::PageUp => crate::Key::PageUp,
+        NamedKey::PageDown => crate::Key::PageDown,
+        NamedKey::Shift => crate::Key::Shift,
+        NamedKey::Control => crate::Key::Control,
+        NamedKey::Alt => crate::Key::Alt,
+        NamedKey::Super | NamedKey::Meta => crate::Key::Meta,
+        NamedKey::CapsLock => crate::Key::CapsLock,
+        NamedKey::Space => crate::Key::Character(' '),
+        NamedKey::F1 => crate::Key::F(1),
+        NamedKey::F2 => crate::Key::F(2),
+        NamedKey::F3 => crate::Key::F(3),
+        NamedKey::F4 => crate::Key::F(4),
+        NamedKey::F5 => crate::Key::F(5),
+        NamedKey::F6 => crate::Key::F(6),
+        NamedKey::F7 => crate::Key::F(7),
+        NamedKey::F8 => crate::Key::F(8),
+        NamedKey::F9 => crate::Key::F(9),
+        NamedKey::F10 => crate::Key::F(10),
+        NamedKey::F11 => crate::Key::F(11),
+        NamedKey::F12 => crate::Key::F(12),
+        NamedKey::F13 => crate::Key::F(13),
+        NamedKey::F14 => crate::Key::F(14),
+        NamedKey::F15 => crate::Key::F(15),
+        NamedKey::F16 => crate::Key::F(16),
+        NamedKey::F17 => crate::Key::F(17),
+        NamedKey::F18 => crate::Key::F(18),
+        NamedKey::F19 => crate::Key::F(19),
+        NamedKey::F20 => crate::Key::F(20),
+        NamedKey::F21 => crate::Key::F(21),
+        NamedKey::F22 => crate::Key::F(22),
+        NamedKey::F23 => crate::Key::F(23),
+        NamedKey::F24 => crate::Key::F(24),
+        _ => crate::Key::Other
     }
+}
 
+/// The W3C name for a physical key, as a browser's `KeyboardEvent.code` would have it. Mostly
+/// these are the same as winit's names, but not always (winit says "Super" where the browser
+/// says "Meta"), so they're all spelled out here.
+fn key_code_name(code: KeyCode) -> &'static str {
+    match code {
+        KeyCode::Backquote => "Backquote",
+        KeyCode::Backslash => "Backslash",
+        KeyCode::BracketLeft => "BracketLeft",
+        KeyCode::BracketRight => "BracketRight",
+        KeyCode::Comma => "Comma",
+        KeyCode::Digit0 => "Digit0",
+        KeyCode::Digit1 => "Digit1",
+        KeyCode::Digit2 => "Digit2",
+        KeyCode::Digit3 => "Digit3",
+        KeyCode::Digit4 => "Digit4",
+        KeyCode::Digit5 => "Digit5",
+        KeyCode::Digit6 => "Digit6",
+        KeyCode::Digit7 => "Digit7",
+        KeyCode::Digit8 => "Digit8",
+        KeyCode::Digit9 => "Digit9",
+        KeyCode::Equal => "Equal",
+        KeyCode::IntlBackslash => "IntlBackslash",
+        KeyCode::IntlRo => "IntlRo",
+        KeyCode::IntlYen => "IntlYen",
+        KeyCode::KeyA => "KeyA",
+        KeyCode::KeyB => "KeyB",
+        KeyCode::KeyC => "KeyC",
+        KeyCode::KeyD => "KeyD",
+        KeyCode::KeyE => "KeyE",
+        KeyCode::KeyF => "KeyF",
+        KeyCode::KeyG => "KeyG",
+        KeyCode::KeyH => "KeyH",
+        KeyCode::KeyI => "KeyI",
+        KeyCode::KeyJ => "KeyJ",
+        KeyCode::KeyK => "KeyK",
+        KeyCode::KeyL => "KeyL",
+        KeyCode::KeyM => "KeyM",
+        KeyCode::KeyN => "KeyN",
+        KeyCode::KeyO => "KeyO",
+        KeyCode::KeyP => "KeyP",
+        KeyCode::KeyQ => "KeyQ",
+        KeyCode::KeyR => "KeyR",
+        KeyCode::KeyS => "KeyS",
+        KeyCode::KeyT => "KeyT",
+        KeyCode::KeyU => "KeyU",
+        KeyCode::KeyV => "KeyV",
+        KeyCode::KeyW => "KeyW",
+        KeyCode::KeyX => "KeyX",
+        KeyCode::KeyY => "KeyY",
+        KeyCode::KeyZ => "KeyZ",
+        KeyCode::Minus => "Minus",
+        KeyCode::Period => "Period",
+        KeyCode::Quote => "Quote",
+        KeyCode::Semicolon => "Semicolon",
+        KeyCode::Slash => "Slash",
+        KeyCode::AltLeft => "AltLeft",
+        KeyCode::AltRight => "AltRight",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::CapsLock => "CapsLock",
+        KeyCode::ContextMenu => "ContextMenu",
+        KeyCode::ControlLeft => "ControlLeft",
+        KeyCode::ControlRight => "ControlRight",
+        KeyCode::Enter => "Enter",
+        KeyCode::SuperLeft => "MetaLeft",
+        KeyCode::SuperRight => "MetaRight",
+        KeyCode::ShiftLeft => "ShiftLeft",
+        KeyCode::ShiftRight => "ShiftRight",
+        KeyCode::Space => "Space",
+        KeyCode::Tab => "Tab",
+        KeyCode::Convert => "Convert",
+        KeyCode::KanaMode => "KanaMode",
+        KeyCode::Lang1 => "Lang1",
+        KeyCode::Lang2 => "Lang2",
+        KeyCode::NonConvert => "NonConvert",
+        KeyCode::Delete => "Delete",
+        KeyCode::End => "End",
+        KeyCode::Help => "Help",
+        KeyCode::Home => "Home",
+        KeyCode::Insert => "Insert",
+        KeyCode::PageDown => "PageDown",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::ArrowDown => "ArrowDown",
+        KeyCode::ArrowLeft => "ArrowLeft",
+        KeyCode::ArrowRight => "ArrowRight",
+        KeyCode::ArrowUp => "ArrowUp",
+        KeyCode::NumLock => "NumLock",
+        KeyCode::Numpad0 => "Numpad0",
+        KeyCode::Numpad1 => "Numpad1",
+        KeyCode::Numpad2 => "Numpad2",
+        KeyCode::Numpad3 => "Numpad3",
+        KeyCode::Numpad4 => "Numpad4",
+        KeyCode::Numpad5 => "Numpad5",
+        KeyCode::Numpad6 => "Numpad6",
+        KeyCode::Numpad7 => "Numpad7",
+        KeyCode::Numpad8 => "Numpad8",
+        KeyCode::Numpad9 => "Numpad9",
+        KeyCode::NumpadAdd => "NumpadAdd",
+        KeyCode::NumpadComma => "NumpadComma",
+        KeyCode::NumpadDecimal => "NumpadDecimal",
+        KeyCode::NumpadDivide => "NumpadDivide",
+        KeyCode::NumpadEnter => "NumpadEnter",
+        KeyCode::NumpadEqual => "NumpadEqual",
+        KeyCode::NumpadMultiply => "NumpadMultiply",
+        KeyCode::NumpadSubtract => "NumpadSubtract",
+        KeyCode::Escape => "Escape",
+        KeyCode::PrintScreen => "PrintScreen",
+        KeyCode::ScrollLock => "ScrollLock",
+        KeyCode::Pause => "Pause",
+        KeyCode::BrowserBack => "BrowserBack",
+        KeyCode::BrowserFavorites => "BrowserFavorites",
+        KeyCode::BrowserForward => "BrowserForward",
+        KeyCode::BrowserHome => "BrowserHome",
+        KeyCode::BrowserRefresh => "BrowserRefresh",
+        KeyCode::BrowserSearch => "BrowserSearch",
+        KeyCode::BrowserStop => "BrowserStop",
+        KeyCode::Eject => "Eject",
+        KeyCode::LaunchApp1 => "LaunchApp1",
+        KeyCode::LaunchApp2 => "LaunchApp2",
+        KeyCode::LaunchMail => "LaunchMail",
+        KeyCode::MediaPlayPause => "MediaPlayPause",
+        KeyCode::MediaSelect => "MediaSelect",
+        KeyCode::MediaStop => "MediaStop",
+        KeyCode::MediaTrackNext => "MediaTrackNext",
+        KeyCode::MediaTrackPrevious => "MediaTrackPrevious",
+        KeyCode::Power => "Power",
+        KeyCode::Sleep => "Sleep",
+        KeyCode::AudioVolumeDown => "AudioVolumeDown",
+        KeyCode::AudioVolumeMute => "AudioVolumeMute",
+        KeyCode::AudioVolumeUp => "AudioVolumeUp",
+        KeyCode::WakeUp => "WakeUp",
+        KeyCode::F1 => "F1",
+        KeyCode::F2 => "F2",
+        KeyCode::F3 => "F3",
+        KeyCode::F4 => "F4",
+        KeyCode::F5 => "F5",
+        KeyCode::F6 => "F6",
+        KeyCode::F7 => "F7",
+        KeyCode::F8 => "F8",
+        KeyCode::F9 => "F9",
+        KeyCode::F10 => "F10",
+        KeyCode::F11 => "F11",
+        KeyCode::F12 => "F12",
+        KeyCode::F13 => "F13",
+        KeyCode::F14 => "F14",
+        KeyCode::F15 => "F15",
+        KeyCode::F16 => "F16",
+        KeyCode::F17 => "F17",
+        KeyCode::F18 => "F18",
+        KeyCode::F19 => "F19",
+        KeyCode::F20 => "F20",
+        KeyCode::F21 => "F21",
+        KeyCode::F22 => "F22",
+        KeyCode::F23 => "F23",
+        KeyCode::F24 => "F24",
+        // Keys that aren't on any keyboard a browser would report, or that it has no name for
+        _ => ""
+    }
+}
+
+fn to_winit_cursor(cursor: Cursor) -> CursorIcon {
+    match cursor {
+        Cursor::Default => CursorIcon::Default,
+        Cursor::Pointer => CursorIcon::Pointer,
+        Cursor::Crosshair => CursorIcon::Crosshair,
+        Cursor::Text => CursorIcon::Text,
+        Cursor::Move => CursorIcon::Move,
+        Cursor::Grab => CursorIcon::Grab,
+        Cursor::Grabbing => CursorIcon::Grabbing,
+        Cursor::Wait => CursorIcon::Wait,
+        Cursor::NotAllowed => CursorIcon::NotAllowed
+    }
+}
+
+fn to_banana_modifiers(state: ModifiersState) -> Modifiers {
+    Modifiers {
+        shift: state.shift_key(),
+        ctrl: state.control_key(),
+        alt: state.alt_key(),
+        meta: state.super_key()
+    }
 }
 
+/// Everything about the window to open, for `run_window_with`. Sizes are in logical pixels, like
+/// `run_window` takes.
+/// ```no_run
+/// # use bananagraph::{WindowConfig, WindowEventHandler, PresentMode};
+/// # async fn f(handler: impl WindowEventHandler) {
+/// let config = WindowConfig::new("My game", (640, 480))
+///     .with_min_size((320, 240))
+///     .with_resizable(false)
+///     .with_present_mode(PresentMode::AutoNoVsync);
+/// bananagraph::run_window_with(config, handler).await.unwrap();
+/// # }
+/// ```
 #[cfg(feature = "desktop")]
-pub async fn run_window(title: &str, initial_size: Vector2<u32>, min_size: Vector2<u32>, handler: impl WindowEventHandler) -> Result<(), EventLoopError> {
-    let event_loop = winit::event_loop::EventLoop::new().expect("Failed to create event loop!");
-    event_loop.set_control_flow(ControlFlow::Wait);
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    pub size: Vector2<u32>,
+    pub min_size: Option<Vector2<u32>>,
+    pub resizable: bool,
+
+    /// Start out filling the screen (borderless, at the desktop's resolution)
+    pub fullscreen: bool,
+
+    /// Whether the window has a title bar and border. Turn this off for a borderless window.
+    pub decorations: bool,
+    pub present_mode: PresentMode,
+    pub icon: Option<RgbaImage>,
+    pub cursor_visible: bool,
+
+    /// How often to tick and draw. If this isn't set, we ask the handler's `game_loop`.
+    pub game_loop: Option<GameLoop>
+}
+
+#[cfg(feature = "desktop")]
+impl WindowConfig {
+    /// A resizable, decorated, vsynced window
+    pub fn new(title: impl Into<String>, size: impl Into<Vector2<u32>>) -> Self {
+        Self {
+            title: title.into(),
+            size: size.into(),
+            min_size: None,
+            resizable: true,
+            fullscreen: false,
+            decorations: true,
+            present_mode: PresentMode::AutoVsync,
+            icon: None,
+            cursor_visible: true,
+            game_loop: None
+        }
+    }
+
+    pub fn with_min_size(self, min_size: impl Into<Vector2<u32>>) -> Self {
+        Self { min_size: Some(min_size.into()), ..self }
+    }
+
+    pub fn with_resizable(self, resizable: bool) -> Self {
+        Self { resizable, ..self }
+    }
+
+    pub fn with_fullscreen(self, fullscreen: bool) -> Self {
+        Self { fullscreen, ..self }
+    }
+
+    pub fn with_decorations(self, decorations: bool) -> Self {
+        Self { decorations, ..self }
+    }
+
+    /// See `GpuWrapper::set_present_mode`
+    pub fn with_present_mode(self, present_mode: PresentMode) -> Self {
+        Self { present_mode, ..self }
+    }
+
+    /// Set the window icon from a (probably png-encoded) image. This fails if the image can't be
+    /// decoded.
+    pub fn with_icon(self, bytes: &[u8]) -> crate::Result<Self> {
+        Ok(Self { icon: Some(image::load_from_memory(bytes)?.to_rgba8()), ..self })
+    }
 
-    let attrs = Window::default_attributes()
-        .with_title(title)
-        .with_inner_size(LogicalSize { width: initial_size.x, height: initial_size.y })
-        .with_min_inner_size(LogicalSize { width: min_size.x, height: min_size.y });
+    pub fn with_cursor_visible(self, cursor_visible: bool) -> Self {
+        Self { cursor_visible, ..self }
+    }
+
+    pub fn with_game_loop(self, game_loop: GameLoop) -> Self {
+        Self { game_loop: Some(game_loop), ..self }
+    }
+
+    fn attributes(&self) -> WindowAttributes {
+        let mut attrs = Window::default_attributes()
+            .with_title(&self.title)
+            .with_inner_size(LogicalSize { width: self.size.x, height: self.size.y })
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_fullscreen(self.fullscreen.then_some(Fullscreen::Borderless(None)))
+            .with_window_icon(self.icon.as_ref().and_then(|icon| Icon::from_rgba(icon.as_raw().clone(), icon.width(), icon.height()).ok()));
+
+        if let Some(min_size) = self.min_size {
+            attrs = attrs.with_min_inner_size(LogicalSize { width: min_size.x, height: min_size.y })
+        }
+        attrs
+    }
+}
 
+/// Open a window with a title and size, and run the handler in it until it closes. For more
+/// options, see `run_window_with`.
+#[cfg(feature = "desktop")]
+pub async fn run_window(title: &str, initial_size: Vector2<u32>, min_size: Vector2<u32>, handler: impl WindowEventHandler) -> crate::Result<()> {
+    run_window_with(WindowConfig::new(title, initial_size).with_min_size(min_size), handler).await
+}
+
+/// Open a window as described by a `WindowConfig`, and run the handler in it until it closes.
+/// This fails if the event loop does, or if there's no GPU to draw to the window with.
+#[cfg(feature = "desktop")]
+pub async fn run_window_with(config: WindowConfig, handler: impl WindowEventHandler) -> crate::Result<()> {
+    let event_loop = winit::event_loop::EventLoop::new()?;
+    event_loop.set_control_flow(ControlFlow::Wait);
+
+    let game_loop = config.game_loop.unwrap_or_else(|| handler.game_loop());
     let mut app = App {
         window: None,
         wrapper: None,
         id_buffer: None,
         handler,
-        attrs,
+        attrs: config.attributes(),
+        present_mode: config.present_mode,
+        cursor_visible: config.cursor_visible,
+        handle: WindowHandle::new(),
         mouse_pos: (-1f64, -1f64).into(),
-        timer_length: Duration::from_millis(20)
+        modifiers: Modifiers::default(),
+        drag: DragTracker::new(),
+        gestures: GestureRecognizer::new(),
+        start: Instant::now(),
+        game_loop,
+        last_frame: Instant::now(),
+        backgrounded: false,
+        occluded: false,
+        suspended: false,
+        error: None
     };
-    event_loop.run_app(&mut app)
+    event_loop.run_app(&mut app)?;
+    app.error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        // The same names a browser would give, which aren't always winit's
+        assert_eq!(key_code_name(KeyCode::SuperLeft), "MetaLeft");
+        assert_eq!(key_code_name(KeyCode::KeyW), "KeyW");
+        assert_eq!(key_code_name(KeyCode::Numpad4), "Numpad4");
+
+        // And named keys come out the same as parsing the browser's name for them
+        for (named, name) in [(NamedKey::Super, "Meta"), (NamedKey::ArrowLeft, "ArrowLeft"), (NamedKey::F11, "F11"), (NamedKey::Space, " "), (NamedKey::Enter, "Enter")] {
+            assert_eq!(to_banana_named_key(named), crate::Key::from_name(name));
+        }
+    }
+
+    #[test]
+    fn test_bad_icon() {
+        assert!(matches!(WindowConfig::new("test", (8, 8)).with_icon(b"not a png"), Err(crate::Error::Image(_))));
+        let icon = crate::encode_png(&RgbaImage::new(2, 2)).unwrap();
+        assert!(WindowConfig::new("test", (8, 8)).with_icon(&icon).unwrap().icon.is_some());
+    }
 }
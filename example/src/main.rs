@@ -1,5 +1,5 @@
-use bananagraph::{DrawingContext, GpuWrapper, IdBuffer, Sprite, WindowEventHandler};
-use cgmath::{Deg, Point2};
+use bananagraph::{DrawingContext, GpuWrapper, IdBuffer, MousePos, Sprite, WindowEventHandler};
+use cgmath::Deg;
 
 struct GameState {
 }
@@ -10,7 +10,7 @@ impl WindowEventHandler for GameState {
         wrapper.add_texture(include_bytes!("background.png"), None);
     }
 
-    fn redraw(&self, _mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, _mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
         // let (w, h) = (400.0, 225.0);
         // let sprite = Sprite::new((0, 0), (32, 32))
         //     //.translate((-0.5, -0.5))
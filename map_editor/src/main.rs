@@ -1,6 +1,6 @@
-use bananagraph::{Click, DrawingContext, ElementState, GpuWrapper, IdBuffer, Sprite, WindowEventHandler};
+use bananagraph::{Click, Drag, DragPhase, DrawingContext, ElementState, GpuWrapper, IdBuffer, MaterialId, MouseButton, MouseMove, MousePos, Sprite, WindowEventHandler};
 use cgmath::num_traits::Pow;
-use cgmath::{Point2, Vector2};
+use cgmath::Vector2;
 use rand::Rng;
 use grid::{Coord, Grid, GridMut};
 use crate::board::{Board, Cell};
@@ -26,6 +26,21 @@ fn toggle_wall(id: u32, board: &mut Board) {
     }
 }
 
+fn is_wall(id: u32, board: &Board) -> bool {
+    id >= 100000 && matches!(board.get(sprite_id_to_coord(id, board.size().x)), Some(Cell::ShortWall | Cell::TallWall))
+}
+
+/// Like toggle_wall, but always lands on the same state, so dragging back and forth over a tile
+/// doesn't flicker it
+fn paint_wall(id: u32, wall: bool, board: &mut Board) {
+    if id >= 100000 {
+        let coord = sprite_id_to_coord(id, board.size().x);
+        if let Some(cell) = board.get_mut(coord) {
+            *cell = if wall { Cell::TallWall } else { Board::square_color(coord) }
+        }
+    }
+}
+
 fn create_background(size: usize) -> Vec<u8> {
     let mut texture = vec![0u8; size * size * 4];
     let center = size as f32 / 2.0;
@@ -104,17 +119,31 @@ fn highlight_sprites() -> (Sprite, Sprite) {
 }
 
 struct GameState {
-    board: Board
+    board: Board,
+
+    /// While dragging, whether we're painting walls (true) or floor (false). This is picked by
+    /// whatever the drag started on, so it stays the same for the whole drag.
+    painting: Option<bool>,
+
+    /// The tile under the mouse, as of the last mouse move
+    hovered: Option<u32>,
+
+    /// The material the tile under the mouse is drawn with, once we've made it
+    outline: Option<MaterialId>
 }
 
 impl WindowEventHandler for GameState {
     fn init(&mut self, wrapper: &mut GpuWrapper) {
+        // The board is laid out to fit the window, rather than scaled to it
+        wrapper.follow_window_size = true;
         wrapper.add_texture(include_bytes!("iso_dungeon_world.png"), Some("dungeon"));
         // wrapper.add_texture(include_bytes!("background.png"), Some("background"));
         wrapper.add_texture_from_array(create_background(720), 720, Some("background"));
+        let color: Vec<u8> = [1.0f32, 0.9, 0.3, 1.0].iter().flat_map(|c| c.to_ne_bytes()).collect();
+        self.outline = Some(wrapper.add_material(include_str!("outline.wgsl"), &color));
     }
 
-    fn redraw(&self, mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
         let size = wrapper.logical_size;
         let iso_map = IsoMap::new(&self.board, (32, 48), (32, 16));
         let base_dc = DrawingContext::new((size.x as f32, size.y as f32));
@@ -123,46 +152,82 @@ impl WindowEventHandler for GameState {
         let dc = create_drawing_contexts((dims.x as f32, dims.y as f32).into(), base_dc);
 
         let mut sprites = iso_map.sprites(dc);
-        let mut buffer = wrapper.redraw_ids(&sprites).unwrap();
-
-        if buffer.contains((mouse_pos.x as u32, mouse_pos.y as u32).into()) {
-            let id = buffer[mouse_pos];
-            if id >= 100000 {
-                let board_coord = sprite_id_to_coord(id, self.board.size().x);
-                sprites = shorten_walls(&self.board, board_coord, sprites);
-                buffer = wrapper.redraw_ids(&sprites).unwrap();
+
+        let mut highlighted = vec![];
+        // Clicks and drags only need what's under the mouse, so only read back a few pixels around it
+        wrapper.set_id_region_around(mouse_pos.physical, 8);
+
+        // The tile under the mouse is found on the CPU: if it's near a tall wall, the walls around
+        // it get shortened so we can see it, and then we look again at what's there now
+        let hit_tester = wrapper.hit_tester();
+        let tile_at = |sprites: &[Sprite]| hit_tester.stack(sprites, mouse_pos.logical).into_iter().map(|hit| hit.id).find(|id| *id >= 100000);
+        if let Some(id) = tile_at(&sprites) {
+            sprites = shorten_walls(&self.board, sprite_id_to_coord(id, self.board.size().x), sprites);
+        }
+
+        if let Some(id) = tile_at(&sprites) {
+            // Outline the tile under the mouse, walls and all
+            match self.outline {
+                Some(outline) => sprites.iter_mut().filter(|s| s.id == id).for_each(|s| *s = s.with_material(outline)),
+                None => highlighted.push(sprite_id_to_coord(id, self.board.size().x))
             }
         }
 
-        if buffer.contains((mouse_pos.x as u32, mouse_pos.y as u32).into()) {
-            let id = buffer[mouse_pos];
-            if id >= 100000 {
-                let board_coord = sprite_id_to_coord(id, self.board.size().x);
-                if let Some(Cell::White | Cell::Black) = self.board.get(board_coord) {
-                    let highlight = highlight_sprites();
-                    let z = iso_map.z_coord(board_coord);
-                    sprites.push(iso_map.sprite(highlight.0.with_z(z - 0.0001), board_coord, &dc));
-                    sprites.push(iso_map.sprite(highlight.1.with_z(z - 0.0003), board_coord, &dc));
-                }
+        for board_coord in highlighted {
+            if let Some(Cell::White | Cell::Black) = self.board.get(board_coord) {
+                let highlight = highlight_sprites();
+                let z = iso_map.z_coord(board_coord);
+                sprites.push(iso_map.sprite(highlight.0.with_z(z - 0.0001), board_coord, &dc));
+                sprites.push(iso_map.sprite(highlight.1.with_z(z - 0.0003), board_coord, &dc));
             }
         }
 
         // Push the background:
         sprites.push(Sprite::new((0, 0), (720, 720)).with_layer(1).with_z(0.99999).with_tint((0.2, 0.3, 0.4, 1.0)));
 
-        wrapper.redraw_with_ids(sprites).ok()
+        wrapper.redraw_with_latest_ids(sprites).ok().flatten()
     }
 
     fn click(&mut self, event: Click) {
-        if let (ElementState::Pressed, Some(id)) = (event.state, event.entity) {
+        // Releasing the button at the end of a drag isn't a click on its own
+        if let (ElementState::Released, Some(id), None) = (event.state, event.entity, self.painting) {
             toggle_wall(id, &mut self.board)
         }
     }
+
+    fn mouse_move(&mut self, event: MouseMove) {
+        self.hovered = event.entity
+    }
+
+    /// Dragging paints every tile the mouse crosses: walls if the drag started on a floor, floor
+    /// if it started on a wall
+    fn drag(&mut self, event: Drag) {
+        if event.button != MouseButton::Left { return }
+        match event.phase {
+            DragPhase::Start => {
+                // A drag that starts off the board doesn't paint anything
+                let Some(start) = event.entity.filter(|id| *id >= 100000) else { return };
+                let wall = !is_wall(start, &self.board);
+                self.painting = Some(wall);
+                paint_wall(start, wall, &mut self.board);
+                if let Some(id) = self.hovered {
+                    paint_wall(id, wall, &mut self.board)
+                }
+            }
+            DragPhase::Update => {
+                // Mouse moves come in before the drag they're part of, so hovered is current
+                if let (Some(wall), Some(id)) = (self.painting, self.hovered) {
+                    paint_wall(id, wall, &mut self.board)
+                }
+            }
+            DragPhase::End => self.painting = None
+        }
+    }
 }
 
 pub fn main() {
     env_logger::init();
     let size = (1280, 720);
     let board = Board::new(10, 7);
-    let _ = pollster::block_on(bananagraph::run_window("The Thing", size.into(), size.into(), GameState { board }));
+    let _ = pollster::block_on(bananagraph::run_window("The Thing", size.into(), size.into(), GameState { board, painting: None, hovered: None, outline: None }));
 }
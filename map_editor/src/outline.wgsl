@@ -0,0 +1,21 @@
+// A material that draws a line around the opaque part of a sprite, in the transparent texels
+// right next to it
+
+struct Outline {
+    color: vec4<f32>,
+}
+
+@group(1) @binding(0) var<uniform> outline: Outline;
+
+fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+    if color.a > 0.0 {
+        return color;
+    }
+
+    let around = sample_sprite(in, vec2f(1.0, 0.0)).a + sample_sprite(in, vec2f(-1.0, 0.0)).a +
+        sample_sprite(in, vec2f(0.0, 1.0)).a + sample_sprite(in, vec2f(0.0, -1.0)).a;
+    if around > 0.0 {
+        return outline.color;
+    }
+    return color;
+}
@@ -1,9 +1,9 @@
 use std::collections::BTreeSet;
 use std::time::Duration;
-use cgmath::{Point2, Vector2};
+use cgmath::Vector2;
 use hecs::{Entity, World};
 use rand::Rng;
-use bananagraph::{Click, DrawingContext, GpuWrapper, IdBuffer, WindowEventHandler, ElementState};
+use bananagraph::{Click, DrawingContext, GpuWrapper, IdBuffer, MousePos, WindowEventHandler, ElementState};
 use grid::{Coord, Grid, VecGrid};
 use crate::animation::{animation_system, Animation, Fade, MoveAnimation, Pulse};
 use crate::game_state::CaptureSteps::{FadeAnimation, FallAnimation, PieceSelection, SwapAnimation};
@@ -68,7 +68,7 @@ impl<R: Rng> WindowEventHandler for GameState<'_, R> {
         }
     }
 
-    fn redraw(&self, _mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, _mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
         let mut sprites = vec![];
         let dc = DrawingContext::new((self.screen.0 as f32, self.screen.1 as f32));
 
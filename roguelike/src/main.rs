@@ -3,10 +3,10 @@ mod terrain;
 mod animation;
 
 use std::time::Duration;
-use cgmath::{Point2, Vector2};
+use cgmath::Vector2;
 use hecs::World;
 use animation::BreatheAnimation;
-use bananagraph::{GpuWrapper, IdBuffer, Sprite, WindowEventHandler};
+use bananagraph::{GpuWrapper, IdBuffer, MousePos, Sprite, WindowEventHandler};
 use grid::{Coord, Dir, VecGrid};
 use crate::components::{OnMap, Player};
 use crate::terrain::recreate_terrain;
@@ -63,7 +63,7 @@ impl WindowEventHandler for GameState {
         wrapper.add_texture(include_bytes!("Heroes-Animated.png"), Some("Heroes-Animated.png"));
     }
 
-    fn redraw(&self, _mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, _mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
         wrapper.redraw_with_ids(OnMap::system(&self.world)).ok()
     }
 
@@ -6,28 +6,49 @@ document.addEventListener('DOMContentLoaded', () => {
 
         const handleEvent = (e) => {
             e.preventDefault()
-            const rect = e.target.getBoundingClientRect();
-            const x = e.clientX - rect.left;
-            const y = e.clientY - rect.top;
             if (e.type === 'mousedown') { e.target.focus() } // If we click it, focus it in addition to whatever else
-            wrapper.mouse_event(e.type, x, y)
+            wrapper.mouse_event(e)
+        }
+
+        const handleWheel = (e) => {
+            e.preventDefault()
+            wrapper.wheel(e)
+        }
+
+        const handleTouch = (e) => {
+            // Stop the browser from scrolling, zooming, or making mouse events out of these
+            e.preventDefault()
+            wrapper.touch_event(e)
         }
 
         const handleKey = (e) => {
-            // Ignore any strokes that involve ctrl or alt, so we don't eat keys people
-            // might want, like C-r
+            // Pass along every key, but don't eat the ones involving ctrl or alt, so people
+            // still get the browser's keys, like C-r
             if (!e.ctrlKey && !e.altKey && !e.metaKey) {
                 e.preventDefault()
-                wrapper.key(e.key)
             }
+            wrapper.key(e)
         }
 
         const canvas = document.getElementById('main_canvas')
         canvas.addEventListener('mousedown', handleEvent)
         canvas.addEventListener('mouseup', handleEvent)
         canvas.addEventListener('mousemove', handleEvent)
+        canvas.addEventListener('mouseenter', handleEvent)
+        canvas.addEventListener('mouseleave', handleEvent)
+        canvas.addEventListener('wheel', handleWheel)
+
+        canvas.addEventListener('touchstart', handleTouch)
+        canvas.addEventListener('touchmove', handleTouch)
+        canvas.addEventListener('touchend', handleTouch)
+        canvas.addEventListener('touchcancel', handleTouch)
 
         canvas.addEventListener('keydown', handleKey)
+        canvas.addEventListener('keyup', handleKey)
+
+        canvas.addEventListener('focus', (e) => wrapper.focus_event(e))
+        canvas.addEventListener('blur', (e) => wrapper.focus_event(e))
+        document.addEventListener('visibilitychange', () => wrapper.visibility_change())
 
         canvas.focus()
 
@@ -1,5 +1,4 @@
-use cgmath::Point2;
-use bananagraph::{GpuWrapper, IdBuffer, WindowEventHandler};
+use bananagraph::{GpuWrapper, IdBuffer, MousePos, WindowEventHandler};
 
 pub struct GameState {
 
@@ -12,7 +11,7 @@ impl GameState {
 }
 
 impl WindowEventHandler for GameState {
-    fn redraw(&self, _mouse_pos: Point2<f64>, _wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, _mouse_pos: MousePos, _wrapper: &GpuWrapper) -> Option<IdBuffer> {
         None
     }
 }
\ No newline at end of file
@@ -16,23 +16,21 @@ pub fn run() {
 pub async fn init_game(canvas_id: &str, seed: f64) -> JsGpuWrapper {
     use web_sys::HtmlCanvasElement;
     use wgpu::SurfaceTarget;
-    use bananagraph::{GpuWrapper, WindowEventHandler};
+    use bananagraph::GpuWrapper;
     use crate::game_state::GameState;
     use web_sys::js_sys::Math::pow;
 
-    let mut wrapper = web_sys::window()
+    let canvas: HtmlCanvasElement = web_sys::window()
         .and_then(|win| win.document())
-        .and_then(|doc| {
-            let canvas = doc.get_element_by_id(canvas_id).expect("Expected to find a canvas with the given id");
-            let canvas: HtmlCanvasElement = canvas.dyn_into().expect("element must be a canvas");
-            let size = (canvas.width(), canvas.height()).into();
-            let surface_target = SurfaceTarget::Canvas(canvas);
-            Some(GpuWrapper::targeting(surface_target, size, size))
-        })
-        .expect("Failed to init wgpu somehow").await;
+        .and_then(|doc| doc.get_element_by_id(canvas_id))
+        .expect("Expected to find a canvas with the given id")
+        .dyn_into()
+        .expect("element must be a canvas");
 
-    let mut handler = GameState::new((seed * pow(2.0, 32.0)) as u64);
-    handler.init(&mut wrapper);
+    let size = (canvas.width(), canvas.height()).into();
+    let wrapper = GpuWrapper::targeting(SurfaceTarget::Canvas(canvas.clone()), size, size).await;
 
-    JsGpuWrapper::new(wrapper, handler)
+    // This calls init, once the handler has its window handle
+    let handler = GameState::new((seed * pow(2.0, 32.0)) as u64);
+    JsGpuWrapper::new(wrapper, handler).with_canvas(canvas)
 }
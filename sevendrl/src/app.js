@@ -14,12 +14,12 @@ document.addEventListener('DOMContentLoaded', () => {
         }
 
         const handleKey = (e) => {
-            // Ignore any strokes that involve ctrl or alt, so we don't eat keys people
-            // might want, like C-r
+            // Don't eat any strokes that involve ctrl or alt, so people still get keys they
+            // might want, like C-r. The game still hears about them, it just won't type them.
             if (!e.ctrlKey && !e.altKey && !e.metaKey) {
                 e.preventDefault()
-                wrapper.key(e.key)
             }
+            wrapper.key(e)
         }
 
         const canvas = document.getElementById('main_canvas')
@@ -28,6 +28,21 @@ document.addEventListener('DOMContentLoaded', () => {
         canvas.addEventListener('mousemove', handleEvent)
 
         canvas.addEventListener('keydown', handleKey)
+        canvas.addEventListener('keyup', handleKey)
+
+        // Taps are clicks and swipes are arrow keys. Not passive, so preventDefault can stop the
+        // page scrolling and the browser sending fake mouse events as well.
+        const handleTouch = (e) => {
+            e.preventDefault()
+            wrapper.touch_event(e)
+        }
+        for (const type of ['touchstart', 'touchmove', 'touchend', 'touchcancel']) {
+            canvas.addEventListener(type, handleTouch, { passive: false })
+        }
+
+        // Pause when the player clicks away, and pick back up when they come back
+        canvas.addEventListener('focus', () => wrapper.focus(true))
+        canvas.addEventListener('blur', () => wrapper.focus(false))
 
         canvas.focus()
 
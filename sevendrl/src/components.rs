@@ -1,14 +1,16 @@
-use cgmath::Vector2;
+use cgmath::{Point2, Vector2};
 use doryen_fov::{FovAlgorithm, FovRecursiveShadowCasting, MapData};
 use hecs::World;
 use tinyrand::Rand;
-use bananagraph::{DrawingContext, Sprite};
+use bananagraph::{DrawingContext, MaterialId, Sprite};
 use crate::animation::BreatheAnimation;
 use crate::enemy::{Dazed, Enemy, EnemyType};
 use crate::inventory::{EnergyPotion, Give, Grabbable, HealthPotion, Scroll, ScrollType};
+use crate::scrolls::TimeFreezeEffect;
 use crate::sprites::{AnimationSprites, Items, MapCells, SpriteFor};
 use crate::status_bar::set_message;
-use crate::terrain::{Opaque, Solid};
+use crate::door::Door;
+use crate::terrain::{Opaque, Solid, Terrain, MAP_SIZE};
 
 #[derive(Copy, Clone, Debug)]
 pub struct OnMap {
@@ -21,7 +23,10 @@ pub fn player_loc(world: &World) -> Vector2<i32> {
 }
 
 impl OnMap {
-    pub fn system(world: &World) -> Vec<Sprite> {
+    /// The sprites for everything on the map around the player. While time is frozen, enemies are
+    /// drawn with the `frozen` material, if there is one. If the terrain has been drawn into a
+    /// layer (see `draw_terrain`) then that's drawn as one big sprite, instead of one per tile.
+    pub fn system(world: &World, frozen: Option<MaterialId>, terrain: Option<u32>) -> Vec<Sprite> {
         let dc = DrawingContext::new((960.0 / 2.0, 544.0 / 2.0));
         let mut sprites = vec![];
 
@@ -39,24 +44,50 @@ impl OnMap {
         // First let's do some fov work:
         let fov_map = map_data_for(world, (64, 64), player_loc);
         let fog = MapCells::Fog.sprite().with_z(0.7);
+        let frozen = frozen.filter(|_| TimeFreezeEffect::time_freeze_remaining(world).is_some());
+
+        // The part of the region that's actually on the map, since it can hang off the edges
+        let min = Vector2::new(topleft.x.max(0), topleft.y.max(0));
+        let max = Vector2::new((topleft.x + size.x).min(MAP_SIZE), (topleft.y + size.y).min(MAP_SIZE));
+        let local = |location: Vector2<i32>| Vector2::new(
+            (location.x - topleft.x) as f32 * 16.0 + inv_width,
+            (location.y - topleft.y) as f32 * 16.0
+        );
+
+        if let Some(layer) = terrain {
+            // Behind everything else at 0.8, since the terrain layer is the last one and would
+            // win ties
+            let origin = Point2::new(min.x as u32 * 16, min.y as u32 * 16);
+            let span = (max - min).map(|n| n as u32 * 16);
+            sprites.push(dc.place(Sprite::new(origin, span).with_layer(layer).with_z(0.85), local(min)));
+        }
 
-        for (_, (on_map,)) in world.query::<(&OnMap,)>().iter() {
+        for (_, (on_map, enemy, is_terrain, door)) in world.query::<(&OnMap, Option<&Enemy>, Option<&Terrain>, Option<&Door>)>().iter() {
             let OnMap { location, sprite } = on_map;
             // Skip things not in the region
             if location.x < topleft.x || location.y < topleft.y || location.x >= topleft.x + size.x || location.y >= topleft.y + size.y {
                 continue
             }
 
-            let local_coords = Vector2::new(
-                (location.x - topleft.x) as f32 * 16.0 + inv_width,
-                (location.y - topleft.y) as f32 * 16.0
-            );
+            // And terrain that's already in the terrain layer
+            if terrain.is_some() && is_terrain.is_some() && door.is_none() {
+                continue
+            }
+
             let sprite = if sprite.z == 0.0 { sprite.with_z(0.8) } else { *sprite };
-            sprites.push(dc.place(sprite, local_coords));
+            let sprite = match (frozen, enemy) {
+                (Some(frozen), Some(_)) => sprite.with_material(frozen),
+                _ => sprite
+            };
+            sprites.push(dc.place(sprite, local(*location)));
+        }
 
-            // If this isn't in fov, plant an opaque fog sprite on top of it:
-            if !fov_map.fov[location.x as usize + location.y as usize * 64usize] { // TODO don't hard code map size
-                sprites.push(dc.place(fog, local_coords))
+        // Every cell that isn't in fov gets an opaque fog sprite on top of it:
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                if !fov_map.fov[x as usize + y as usize * MAP_SIZE as usize] {
+                    sprites.push(dc.place(fog, local(Vector2::new(x, y))))
+                }
             }
         }
 
@@ -0,0 +1,6 @@
+// A material for enemies while time is frozen: drained of color, and tinted a cold blue
+
+fn material(color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+    let gray = dot(color.rgb, vec3f(0.299, 0.587, 0.114));
+    return vec4f(vec3f(gray) * vec3f(0.8, 0.9, 1.2), color.a);
+}
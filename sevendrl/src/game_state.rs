@@ -1,11 +1,12 @@
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::time::Duration;
-use cgmath::{Point2, Vector2};
+use cgmath::Vector2;
 use hecs::{Entity, Query, World};
 use log::info;
 use tinyrand::{Rand, Seeded, Xorshift};
 use wgpu::CompositeAlphaMode::Opaque;
-use bananagraph::{GpuWrapper, IdBuffer, Sprite, Typeface, TypefaceBuilder, WindowEventHandler};
+use bananagraph::{Effect, EffectId, GpuWrapper, IdBuffer, MaterialId, MousePos, RenderLayer, Sprite, Typeface, TypefaceBuilder, WindowEventHandler};
 use grid::{create_bsp_map, CellType, Coord, Dir, Grid, VecGrid};
 use crate::animation::{BreatheAnimation, OneShotAnimation};
 use crate::components::{player_loc, Chest, OnMap, Player, Stairs};
@@ -16,7 +17,7 @@ use crate::modal::{ContentType, DismissType, Modal};
 use crate::scrolls::{actually_phasewalk, TimeFreezeEffect};
 use crate::sprites::{AnimationSprites, Items, MapCells, SpriteFor};
 use crate::status_bar::{set_message, EquippedAbilities, StatusBar};
-use crate::terrain::{recreate_terrain, Solid};
+use crate::terrain::{draw_terrain, recreate_terrain, Solid, MAP_SIZE};
 
 enum KeyPress {
     Enter,
@@ -34,13 +35,45 @@ pub enum GameMode {
     PhaseWalk, // Asking the player which dir to phase walk
 }
 
+/// How long the screen shakes and flashes red when the player gets hurt
+const HURT_TIME: Duration = Duration::from_millis(300);
+
+/// The post-processing effects we use for feedback, which are off until something happens
+pub struct ScreenEffects {
+    /// Cools the colors down a little while time is frozen (the enemies themselves are grayed out
+    /// completely, see `GameState::frozen`)
+    freeze: EffectId,
+
+    /// Shake and flash red when the player's hurt
+    shake: EffectId,
+    flash: EffectId
+}
+
 #[derive(Default)]
 pub struct GameState {
     pub world: World,
     pub rand: Xorshift,
     pub typeface: Option<Typeface>,
     pub mode: GameMode,
-    pub level: i32
+    pub level: i32,
+
+    /// Set while the game doesn't have focus, so the animations stop
+    pub paused: bool,
+
+    pub effects: Option<ScreenEffects>,
+
+    /// What enemies are drawn with while time is frozen
+    pub frozen: Option<MaterialId>,
+
+    /// The walls and floors of the whole map, drawn once into a layer of their own, and whether
+    /// the map has changed since then
+    pub terrain: Option<RenderLayer>,
+    pub terrain_dirty: Cell<bool>,
+
+    /// How much is left of the hurt effect, and the player's health last tick, so we can tell when
+    /// they've taken damage
+    pub hurt: Duration,
+    pub last_health: u32
 }
 
 impl WindowEventHandler for GameState {
@@ -77,20 +110,53 @@ impl WindowEventHandler for GameState {
         builder.set_right_offset('q', -3);
         builder.add_sized_glyph(' ', (3, 1), (17, 113));
         self.typeface = Some(builder.into_typeface(wrapper));
+
+        self.effects = Some(ScreenEffects {
+            freeze: wrapper.add_effect(Effect::ColorGrade { brightness: 0.0, contrast: 1.1, saturation: 0.6, tint: (0.9, 0.95, 1.1).into() }),
+            shake: wrapper.add_effect(Effect::Shake { offset: (0.0, 0.0).into() }),
+            flash: wrapper.add_effect(Effect::Flash { color: (1.0, 0.0, 0.0, 1.0).into(), amount: 0.0 }),
+        });
+        self.frozen = Some(wrapper.add_material(include_str!("frozen.wgsl"), &[]));
+        self.terrain = Some(wrapper.create_render_layer((MAP_SIZE as u32 * 16, MAP_SIZE as u32 * 16)));
+        self.terrain_dirty.set(true);
     }
 
-    fn redraw(&self, _mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
-        let mut sprites = OnMap::system(&self.world);
+    fn redraw(&self, _mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+        if let Some(terrain) = &self.terrain {
+            if self.terrain_dirty.replace(false) {
+                draw_terrain(&self.world, wrapper, terrain)
+            }
+        }
+
+        let mut sprites = OnMap::system(&self.world, self.frozen, self.terrain.as_ref().map(RenderLayer::layer));
         let tf = self.typeface.as_ref().unwrap();
         sprites.append(&mut StatusBar::system(&self.world, tf));
         sprites.append(&mut Inventory::system(&self.world, tf));
         sprites.append(&mut Modal::system(&self.world, tf));
-        wrapper.redraw_with_ids(sprites).ok()
+        self.update_effects(wrapper);
+        wrapper.redraw_with_latest_ids(sprites).ok().flatten()
     }
 
     fn tick(&mut self, dt: Duration) {
+        if self.paused { return }
         BreatheAnimation::system(&mut self.world, dt);
         OneShotAnimation::system(&mut self.world, dt);
+
+        // Enemies hurt the player while we're handling keys, so this is where we notice
+        if let Some((_, player)) = self.world.query::<&Player>().iter().next() {
+            if player.health < self.last_health { self.hurt = HURT_TIME }
+            self.last_health = player.health
+        }
+        self.hurt = self.hurt.saturating_sub(dt);
+    }
+
+    fn focus(&mut self, focused: bool) {
+        self.paused = !focused
+    }
+
+    /// Taps click and swipes move, so it's playable on a phone
+    fn recognize_gestures(&self) -> bool {
+        true
     }
 
     fn letter_key(&mut self, letter: char) {
@@ -111,6 +177,20 @@ impl WindowEventHandler for GameState {
 }
 
 impl GameState {
+    /// Turn the screen effects on and off to match what's happening, and animate them
+    fn update_effects(&self, wrapper: &GpuWrapper) {
+        let Some(effects) = &self.effects else { return };
+        wrapper.set_effect_enabled(effects.freeze, TimeFreezeEffect::time_freeze_remaining(&self.world).is_some());
+
+        // Rattle around, settling down as the flash fades
+        let hurt = self.hurt.as_secs_f32() / HURT_TIME.as_secs_f32();
+        let t = self.hurt.as_secs_f32() * 60.0;
+        wrapper.set_effect_enabled(effects.shake, hurt > 0.0);
+        wrapper.set_effect_enabled(effects.flash, hurt > 0.0);
+        wrapper.set_effect(effects.shake, Effect::Shake { offset: (t.sin() * 3.0 * hurt, (t * 1.3).cos() * 2.0 * hurt).into() });
+        wrapper.set_effect(effects.flash, Effect::Flash { color: (1.0, 0.0, 0.0, 1.0).into(), amount: 0.5 * hurt });
+    }
+
     fn handle_key(&mut self, key: KeyPress) {
         // if a modal is up, that gets first crack:
         if let Some((ent, modal)) = self.world.query_mut::<&Modal>().into_iter().next() {
@@ -204,6 +284,7 @@ impl GameState {
 
     pub fn set_map(&mut self, map: VecGrid<CellType>) {
         recreate_terrain(&map, &mut self.world);
+        self.terrain_dirty.set(true);
         self.spawn_enemies(&map, (self.level * 30) as u32);
         self.spawn_treasure((self.level * 10) as usize);
         self.spawn_stairs();
@@ -1,8 +1,9 @@
-use std::ops::{Deref, DerefMut, Index};
+use std::ops::{Deref, DerefMut};
 use std::time::Duration;
 use log::debug;
-use bananagraph::{Click, Dir, ElementState, GpuWrapper, IdBuffer, MouseButton, WindowEventHandler};
+use bananagraph::{handle_touch, update_gestures, Click, ElementState, GameLoop, GestureRecognizer, GpuWrapper, IdBuffer, Key, KeyEvent, KeyState, Modifiers, MouseButton, MousePos, Touch, TouchPhase, WindowEventHandler};
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsCast;
 use crate::game_state::GameState;
 
 /// We can't send a GpuWrapper to JS directly without it trying to generate stuff it can't generate
@@ -17,7 +18,19 @@ pub struct JsGpuWrapper {
     pub(crate) handler: GameState,
 
     #[wasm_bindgen(skip)]
-    pub(crate) ids: Option<IdBuffer>
+    pub(crate) ids: Option<IdBuffer>,
+
+    /// How often to tick, from the handler
+    #[wasm_bindgen(skip)]
+    pub(crate) game_loop: GameLoop,
+
+    /// Turns taps and swipes into clicks and arrow keys, so the game works on a phone
+    #[wasm_bindgen(skip)]
+    pub(crate) gestures: GestureRecognizer,
+
+    /// The total of all the `dt`s we've been passed, which the gesture recognizer uses as its clock
+    #[wasm_bindgen(skip)]
+    pub(crate) clock: Duration
 }
 
 impl Deref for JsGpuWrapper {
@@ -40,12 +53,12 @@ impl JsGpuWrapper {
     /// call the appropriate method on the gamestate (translate between js and windoweventhandler
     /// mouse events).
     pub fn mouse_event(&mut self, event_type: &str, x: f64, y: f64) {
-        let mouse_pos = (x, y).into();
+        let mouse_pos = MousePos::new((x, y), &self.wrapper);
         let entity = match &self.ids {
             None => None,
             Some(buf) => {
 
-                let id = *buf.index((x, y).into());
+                let id = buf[mouse_pos];
                 if id == 0 {
                     None
                 } else {
@@ -60,7 +73,8 @@ impl JsGpuWrapper {
                     button: MouseButton::Left,
                     state: ElementState::Pressed,
                     mouse_pos,
-                    entity
+                    entity,
+                    modifiers: Default::default()
                 })
             }
             "mouseup" => {
@@ -68,7 +82,8 @@ impl JsGpuWrapper {
                     button: MouseButton::Left,
                     state: ElementState::Released,
                     mouse_pos,
-                    entity
+                    entity,
+                    modifiers: Default::default()
                 })
             }
             "mousemove" => {
@@ -78,32 +93,68 @@ impl JsGpuWrapper {
         }
     }
 
-    pub fn key(&mut self, key: &str) {
-        debug!("key: ({})", key);
-        // TODO this is horribly wrong. This is the default impl of `key` in WindowEventHandler,
-        // which consumes winit key events. We need to translate js key events into something we
-        // can call key with, which means we need to refactor key to not expect winit events...
-        // But as long as the 7drl game doesn't need "raw" kbd handling this is fine.
-        match key {
-            "ArrowDown" => self.handler.arrow_key(Dir::South),
-            "ArrowUp" => self.handler.arrow_key(Dir::North),
-            "ArrowLeft" => self.handler.arrow_key(Dir::West),
-            "ArrowRight" => self.handler.arrow_key(Dir::East),
-            "Enter" => self.handler.enter_key(),
-            "Escape" => self.handler.esc_key(),
-            _ => {
-                if key.len() == 1 {
-                    self.handler.letter_key(key.chars().next().unwrap())
-                }
-            }
+    /// Take a browser touch event (touchstart, touchmove, touchend, touchcancel) on the canvas and
+    /// send each finger that changed to the game, the same way `bananagraph::JsGpuWrapper` does
+    pub fn touch_event(&mut self, event: &web_sys::TouchEvent) {
+        let phase = match event.type_().as_str() {
+            "touchstart" => TouchPhase::Start,
+            "touchmove" => TouchPhase::Move,
+            "touchend" => TouchPhase::End,
+            "touchcancel" => TouchPhase::Cancel,
+            _ => return
+        };
+
+        // Touches don't have an offset like mouse events do, so we need to know where the canvas is
+        let Some(canvas) = event.current_target().and_then(|t| t.dyn_into::<web_sys::Element>().ok()) else { return };
+        let rect = canvas.get_bounding_client_rect();
+
+        let changed = event.changed_touches();
+        for touch in (0..changed.length()).filter_map(|n| changed.get(n)) {
+            let position = MousePos::new((touch.client_x() as f64 - rect.left(), touch.client_y() as f64 - rect.top()), &self.wrapper);
+            let entity = self.ids.as_ref().and_then(|buf| buf.entity(position));
+            let touch = Touch { id: touch.identifier() as u64, phase, position, entity };
+            handle_touch(&mut self.handler, &mut self.gestures, touch, self.clock)
         }
     }
 
+    /// Take a browser keyboard event (keydown or keyup) and send it to the handler, the same way
+    /// `bananagraph::JsGpuWrapper` does
+    pub fn key(&mut self, event: &web_sys::KeyboardEvent) {
+        debug!("key: ({}, {})", event.type_(), event.key());
+        let state = match (event.type_().as_str(), event.repeat()) {
+            ("keyup", _) => KeyState::Released,
+            (_, true) => KeyState::Repeat,
+            (_, false) => KeyState::Pressed
+        };
+
+        self.handler.key(KeyEvent {
+            logical: Key::from_name(&event.key()),
+            physical: event.code(),
+            state,
+            modifiers: Modifiers {
+                shift: event.shift_key(),
+                ctrl: event.ctrl_key(),
+                alt: event.alt_key(),
+                meta: event.meta_key()
+            }
+        })
+    }
+
+    /// Call on focus and blur events on the canvas
+    pub fn focus(&mut self, focused: bool) {
+        self.handler.focus(focused)
+    }
+
+    /// Call from requestAnimationFrame with the milliseconds since the last frame: this runs
+    /// whatever ticks have come due and then renders, like `bananagraph::JsGpuWrapper` does.
     pub fn redraw(&mut self, dt: f64) {
-        let dt = Duration::from_millis(dt as u64);
+        // Browsers give fractional milliseconds; keep them, but a clock going backward is no time
+        let dt = Duration::from_secs_f64((dt / 1000.0).max(0.0));
         // TODO normally we'd have some logic about exiting the game here, but, we're in a browser,
         // so exiting the game just means closing the tab, which we have no control over.
-        self.handler.tick(dt);
-        self.ids = self.handler.redraw((0.0, 0.0).into(), &self.wrapper)
+        let alpha = self.game_loop.frame(&mut self.handler, dt);
+        self.clock += dt;
+        update_gestures(&mut self.handler, &mut self.gestures, self.clock);
+        self.ids = self.handler.render(MousePos::new((0.0, 0.0), &self.wrapper), &self.wrapper, alpha)
     }
 }
\ No newline at end of file
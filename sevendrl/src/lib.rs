@@ -26,7 +26,7 @@ pub fn run() {
 pub async fn init_game(canvas_id: &str, seed: f64) -> JsGpuWrapper {
     use web_sys::HtmlCanvasElement;
     use wgpu::SurfaceTarget;
-    use bananagraph::{GpuWrapper, WindowEventHandler};
+    use bananagraph::{GestureRecognizer, GpuWrapper, WindowEventHandler};
     use crate::game_state::GameState;
     use web_sys::js_sys::Math::pow;
 
@@ -46,7 +46,10 @@ pub async fn init_game(canvas_id: &str, seed: f64) -> JsGpuWrapper {
 
     JsGpuWrapper {
         wrapper,
+        game_loop: handler.game_loop(),
         handler,
         ids: None,
+        gestures: GestureRecognizer::new(),
+        clock: std::time::Duration::ZERO
     }
 }
@@ -1,6 +1,6 @@
 use cgmath::Point2;
 use hecs::{Entity, World};
-use bananagraph::Sprite;
+use bananagraph::{GpuWrapper, RenderLayer, Sprite};
 use grid::{CellType, Grid, VecGrid};
 use crate::components::OnMap;
 use crate::door::Door;
@@ -23,6 +23,9 @@ pub struct Opaque;
 #[derive(Copy, Clone, Debug)]
 pub struct Terrain;
 
+/// How many cells wide and tall the maps are
+pub const MAP_SIZE: i32 = 64;
+
 /// Given a VecGrid<char> of the map, recreates all terrain in the world (after despawning
 /// the preexisting Terrain entities).
 pub fn recreate_terrain(map: &VecGrid<CellType>, world: &mut World) {
@@ -86,3 +89,15 @@ pub fn wall_tile(neighbors: (bool, bool, bool, bool)) -> Sprite {
     origin.1 += 3;
     Sprite::new(Point2::from(origin) * 16, (16, 16))
 }
+
+/// Draw the terrain that never changes (everything but doors, which open) into a layer, one tile
+/// per cell, so it doesn't have to be sent as hundreds of sprites every frame. This needs redoing
+/// whenever the terrain is recreated.
+pub fn draw_terrain(world: &World, wrapper: &GpuWrapper, layer: &RenderLayer) {
+    let dc = layer.drawing_context();
+    let sprites: Vec<Sprite> = world.query::<(&OnMap, &Terrain, Option<&Door>)>().iter()
+        .filter(|(_, (_, _, door))| door.is_none())
+        .map(|(_, (on_map, _, _))| dc.place(on_map.sprite, (on_map.location.x as f32 * 16.0, on_map.location.y as f32 * 16.0)))
+        .collect();
+    wrapper.draw_to_layer(layer, sprites)
+}
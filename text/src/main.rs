@@ -1,7 +1,7 @@
 mod typeface;
 
-use bananagraph::{DrawingContext, GpuWrapper, IdBuffer, WindowEventHandler};
-use cgmath::{Point2, Vector2};
+use bananagraph::{DrawingContext, GpuWrapper, IdBuffer, MousePos, WindowEventHandler};
+use cgmath::Vector2;
 use crate::typeface::{Typeface, TypefaceBuilder};
 
 #[derive(Default)]
@@ -22,7 +22,7 @@ impl WindowEventHandler for GameState {
         self.typeface = Some(builder.into_typeface(wrapper));
     }
 
-    fn redraw(&self, _mouse_pos: Point2<f64>, wrapper: &GpuWrapper) -> Option<IdBuffer> {
+    fn redraw(&self, _mouse_pos: MousePos, wrapper: &GpuWrapper) -> Option<IdBuffer> {
         let dc = DrawingContext::new((160.0, 120.0));
         wrapper.redraw(self.typeface.as_ref().unwrap().print(dc, (0.0, 40.0), "i made a thing to render\nvariable width bitmap fonts"));
         None